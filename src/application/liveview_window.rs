@@ -1,181 +1,1233 @@
-use cairo::Context;
-use cairo::PdfSurface;
-use glib::Continue;
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use cairo::ImageSurface;
+use glib::{ControlFlow, Propagation};
 use gtk::prelude::*;
-use gtk::{Inhibit, WidgetExt};
-use log::{debug, trace, warn};
-use std::f64::consts::PI;
+use log::{debug, info, trace, warn};
+
+use crate::{
+    application::{
+        model::{
+            damage::Damage,
+            navigation::PageNavigator,
+            replay::{format_time, Feed, Replay, SPEEDS},
+            scene::{LayerVisibility, Scene},
+            viewport::{FitMode, Viewport},
+        },
+        render_worker::{Placement, RenderWorker, RenderedFrame, BACKGROUND},
+        view::{error::show_error, LIVEVIEW_WINDOW_STRING},
+    },
+    remarkable::{
+        documents::CachedDocument,
+        format::{
+            device::{DeviceProfile, Orientation},
+            linesdata::{parse_live_message, LiveSyncMessage},
+            scheme::ColorScheme,
+            template::PageTemplates,
+        },
+        recording::SessionRecorder,
+    },
+    render::InkStyle,
+};
 
-use crate::application::;
-use crate::remarkable::format::data::{DEVICE_HEIGHT, DEVICE_WIDTH};
-use crate::remarkable::format::linesdata::parse_binary_live_lines;
-use crate::remarkable::web_socket::data_socket;
+/// Starts loading the document with the given id and returns the channel
+/// the result is published on.
+pub type DocumentLoader = Rc<dyn Fn(String) -> glib::Receiver<Result<CachedDocument, String>>>;
 
 pub struct LiveViewWindow {
-    receiver: glib::Receiver<Vec<u8>>,
-    draw_area: gtk::DrawingArea,
+    /// The frames of a live session, unless a recording is played
+    receiver: Option<glib::Receiver<Vec<u8>>>,
+    /// The frames rendered by the render worker
+    frames: Option<glib::Receiver<RenderedFrame>>,
+    documents: Option<DocumentLoader>,
     window: gtk::Window,
-    surface: cairo::PdfSurface,
+    controls: Controls,
+    state: Rc<RefCell<LiveViewState>>,
 }
 
-const WINDOW_SCALER: f64 = 2.0;
+/// The initial size of the window relative to the tablet's resolution.
+const VIEW_SCALE: f64 = 0.5;
 
-impl LiveViewWindow {
-    pub fn new(host: &str, auth0_id: &str, session_token: &str) -> Self {
-        let (receiver, socket) = data_socket(host.to_string(), auth0_id, session_token.to_string());
+/// A layer as it is listed in the layer panel.
+type LayerRow = (u32, String, LayerVisibility);
+
+/// Moves the replay, e.g. from a button or key.
+type ReplayControl = fn(&mut Replay) -> Feed;
+
+/// Changes the viewport from a button.
+type ViewportControl = fn(&mut Viewport);
+
+/// The widgets reflecting the navigation state.
+#[derive(Clone)]
+struct Controls {
+    draw_area: gtk::DrawingArea,
+    previous: gtk::Button,
+    next: gtk::Button,
+    follow: gtk::ToggleButton,
+    page_label: gtk::Label,
+    zoom_in: gtk::Button,
+    zoom_out: gtk::Button,
+    fit_width: gtk::Button,
+    fit_page: gtk::Button,
+    rotate: gtk::Button,
+    zoom_label: gtk::Label,
+    texture: gtk::ToggleButton,
+    scheme: gtk::ComboBoxText,
+    record: gtk::ToggleButton,
+    /// Only shown when playing a recording
+    replay_bar: gtk::Box,
+    play: gtk::ToggleButton,
+    step_back: gtk::Button,
+    step_forward: gtk::Button,
+    seek: gtk::Scale,
+    replay_time: gtk::Label,
+    speed: gtk::ComboBoxText,
+    /// Pans the page when dragged
+    drag: gtk::GestureDrag,
+    /// Zooms when pinched on a touch screen
+    pinch: gtk::GestureZoom,
+    layer_box: gtk::Box,
+    /// The layers the panel currently lists
+    layers: Rc<RefCell<Vec<LayerRow>>>,
+}
+
+/// Everything the live view needs to show a message. The pages are rendered
+/// by the render worker, which is sent everything affecting them.
+struct LiveViewState {
+    profile: DeviceProfile,
+    worker: RenderWorker,
+    /// The lines received, to list the layers of the page shown
+    scene: Scene,
+    navigator: PageNavigator,
+    viewport: Viewport,
+    orientation: Orientation,
+    ink: InkStyle,
+    scheme: ColorScheme,
+    /// The templates shown while the open document lists none
+    fallback_templates: PageTemplates,
+    /// The size of the view
+    size: (f64, f64),
+    /// The number of device pixels per view unit
+    device_scale: f64,
+    /// The page the worker was last asked to show
+    requested: u32,
+    /// The view as last rendered by the worker
+    view: Option<ImageSurface>,
+    /// The part of the view to draw again with the next frame
+    damage: Damage,
+    /// The id of the document open on the tablet
+    document: Option<String>,
+    /// Records the session while switched on
+    recorder: SessionRecorder,
+    /// The directory recordings are written to, if recording is possible
+    recordings: Option<PathBuf>,
+    /// The recording played instead of a live session
+    replay: Option<Replay>,
+    closed: bool,
+}
+
+impl LiveViewState {
+    /// Creates the state and starts its render worker, which publishes the
+    /// rendered frames on the returned channel.
+    fn new(profile: DeviceProfile) -> (Self, glib::Receiver<RenderedFrame>) {
+        let (width, height) = profile.page_size(Orientation::Portrait);
+        let viewport = Viewport::new((width, height));
+
+        let placement = Placement {
+            orientation: Orientation::Portrait,
+            scale: viewport.scale(),
+            view: viewport.view_transform(),
+            page_rect: viewport.page_rect(),
+            size: (0.0, 0.0),
+            device_scale: 1.0,
+            ink: InkStyle::default(),
+            scheme: ColorScheme::default(),
+        };
+        let (worker, frames) =
+            RenderWorker::spawn(profile, placement).expect("Failed to start the render thread");
+
+        let state = LiveViewState {
+            profile,
+            worker,
+            scene: Scene::new(),
+            navigator: PageNavigator::new(),
+            viewport,
+            orientation: placement.orientation,
+            ink: placement.ink,
+            scheme: placement.scheme,
+            fallback_templates: PageTemplates::default(),
+            size: placement.size,
+            device_scale: placement.device_scale,
+            requested: 0,
+            view: None,
+            damage: Damage::default(),
+            document: None,
+            recorder: SessionRecorder::new(),
+            recordings: None,
+            replay: None,
+            closed: false,
+        };
+
+        (state, frames)
+    }
+
+    /// Renders pages the way the viewport places them.
+    fn place_pages(&mut self, orientation: Orientation) {
+        let (width, height) = self.profile.page_size(orientation);
+        self.viewport.set_page_size(width, height);
+        self.orientation = orientation;
+
+        self.worker.place(Placement {
+            orientation,
+            scale: self.viewport.scale(),
+            view: self.viewport.view_transform(),
+            page_rect: self.viewport.page_rect(),
+            size: self.size,
+            device_scale: self.device_scale,
+            ink: self.ink,
+            scheme: self.scheme,
+        });
+        self.damage.add_full();
+    }
+
+    /// Zooms, pans or rotates the view.
+    fn change_viewport<F: FnOnce(&mut Viewport)>(&mut self, change: F) {
+        change(&mut self.viewport);
+        self.place_pages(self.orientation);
+    }
+
+    /// Draws the lines of the open document on top of its source and
+    /// templates once it has been downloaded.
+    fn set_document(&mut self, document: CachedDocument) {
+        if self.document.as_ref() != Some(&document.id) {
+            debug!("Document {} is not open anymore", document.id);
+            return;
+        }
+
+        self.worker.set_source(match &document.source {
+            Some(path) if document.content.has_source() => {
+                Some((path.clone(), document.content.clone()))
+            }
+            _ => None,
+        });
+        if !document.content.templates.is_empty() {
+            self.worker.set_templates(document.content.templates);
+        }
+        self.damage.add_full();
+    }
+
+    /// Switches between clean and textured ink.
+    fn set_ink(&mut self, ink: InkStyle) {
+        if ink != self.ink {
+            self.ink = ink;
+            self.place_pages(self.orientation);
+        }
+    }
 
-        /*     let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+    /// Shows the page in other colours.
+    fn set_scheme(&mut self, scheme: ColorScheme) {
+        if scheme != self.scheme {
+            self.scheme = scheme;
+            self.place_pages(self.orientation);
+        }
+    }
 
-                std::thread::spawn(move  ||{
+    /// Shows the given templates below pages until a downloaded document
+    /// lists its own.
+    fn set_fallback_templates(&mut self, templates: PageTemplates) {
+        self.worker.set_templates(templates.clone());
+        self.fallback_templates = templates;
+        self.damage.add_full();
+    }
 
-                    let data = include_bytes!("../remarkable/format/example.bin");
+    /// Forgets everything received, so a recording can be played again from
+    /// its start. How the view is set up is kept.
+    fn restart(&mut self) {
+        self.scene.clear();
+        self.navigator.reset(0);
+        self.document = None;
+        self.worker.restart();
+        self.worker.set_templates(self.fallback_templates.clone());
+        self.damage.add_full();
+    }
 
-                    std::thread::sleep(Duration::from_secs(5));
+    /// Adapts to a new size of the drawing area.
+    fn resize(&mut self, width: f64, height: f64, device_scale: f64) {
+        let resized = self.viewport.set_view_size(width, height);
 
-                    sender.send(data.to_vec());
+        if resized || device_scale != self.device_scale {
+            self.size = (width, height);
+            self.device_scale = device_scale;
+            self.place_pages(self.orientation);
+        }
+    }
 
-                    std::thread::sleep(Duration::from_secs(10));
+    /// Applies a message to the scene and passes it on to the worker.
+    fn handle(&mut self, message: LiveSyncMessage) {
+        let pages = (self.navigator.shown(), self.navigator.tablet());
 
-                    let data = include_bytes!("../remarkable/format/example1.bin");
+        match &message {
+            LiveSyncMessage::DocumentOpened {
+                id,
+                page,
+                orientation,
+            } => {
+                if *orientation != self.orientation {
+                    debug!("Switching to {:?}", orientation);
+                }
+
+                // the templates and source of the new document are not
+                // known until it has been downloaded
+                self.document = Some(id.clone());
+                self.worker.set_source(None);
+                self.worker.set_templates(self.fallback_templates.clone());
+                self.place_pages(*orientation);
+                self.navigator.reset(*page);
+            }
+            LiveSyncMessage::PageSwitched { page } => {
+                self.navigator.tablet_switched(*page);
+            }
+            LiveSyncMessage::LineUpdated(update) => {
+                self.navigator.tablet_switched(update.page);
+            }
+            _ => {}
+        }
 
-                    sender.send(data.to_vec());
+        self.scene.apply(message.clone());
+        self.worker.apply(message);
 
+        // the toolbar shows both pages
+        if pages != (self.navigator.shown(), self.navigator.tablet()) {
+            self.damage.add_full();
+        }
+    }
+
+    /// Asks the worker for the page shown if another one has been chosen
+    /// since the last frame, and returns the part of the view to draw again.
+    fn take_damage(&mut self) -> Damage {
+        let shown = self.navigator.shown();
+        if shown != self.requested {
+            self.requested = shown;
+            self.worker.show(shown);
+        }
+
+        self.damage.take()
+    }
+
+    /// Copies the pixels rendered by the worker into the view.
+    fn present(&mut self, frame: RenderedFrame) {
+        match (frame.damage, frame.pixels) {
+            (Damage::Full, pixels) => {
+                self.view = pixels.and_then(|pixels| {
+                    pixels
+                        .into_surface()
+                        .map_err(|e| warn!("Failed to show the rendered view: {}", e))
+                        .ok()
                 });
-        */
-        let builder = gtk::Builder::from_string(WINDOWS_STRING);
+                self.damage.add_full();
+            }
+            (Damage::Area(area), Some(pixels)) => {
+                let copied = match self.view.as_mut() {
+                    Some(view) => pixels.copy_into(view),
+                    None => Err("Nothing has been rendered yet".to_string()),
+                };
+
+                match copied {
+                    Ok(()) => self.damage.add(area),
+                    Err(e) => {
+                        debug!("Asking for the whole view again: {}", e);
+                        self.worker.refresh();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Shows, hides or dims a layer on all pages.
+    fn set_layer_visibility(&mut self, layer: u32, visibility: LayerVisibility) {
+        if self.scene.set_visibility(layer, visibility) {
+            debug!("Layer {} is now {}", layer, visibility.identifier());
+            self.worker.set_visibility(layer, visibility);
+            self.damage.add_full();
+        }
+    }
+
+    /// The layers of the page currently shown, from bottom to top.
+    fn shown_layers(&self) -> Vec<LayerRow> {
+        match self.scene.page(self.navigator.shown()) {
+            Some(page) => page
+                .layers
+                .iter()
+                .map(|(index, layer)| (*index, layer.title(*index), self.scene.visibility(*index)))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl Controls {
+    /// Shows the navigation state in the toolbar and schedules a redraw of
+    /// the whole view. Must not be called while the state is borrowed, as
+    /// setting the toggle button emits its signal.
+    fn update(&self, state: &Rc<RefCell<LiveViewState>>) {
+        self.update_toolbar(state);
+        self.draw_area.queue_draw();
+    }
+
+    /// Shows the navigation state in the toolbar and the layers in the
+    /// panel.
+    fn update_toolbar(&self, state: &Rc<RefCell<LiveViewState>>) {
+        let (shown, tablet, following, previous, next, scale, ink, scheme, recording, layers) = {
+            let state = state.borrow();
+            let navigator = &state.navigator;
+            (
+                navigator.shown(),
+                navigator.tablet(),
+                navigator.is_following(),
+                navigator.has_previous(),
+                navigator.has_next(),
+                state.viewport.scale(),
+                state.ink,
+                state.scheme,
+                state.recordings.as_ref().map(|_| state.recorder.path()),
+                state.shown_layers(),
+            )
+        };
+
+        if shown == tablet {
+            self.page_label.set_text(&format!("Page {}", shown + 1));
+        } else {
+            self.page_label
+                .set_text(&format!("Page {} (tablet on {})", shown + 1, tablet + 1));
+        }
+
+        self.previous.set_sensitive(previous);
+        self.next.set_sensitive(next);
+        self.follow.set_active(following);
+        self.texture.set_active(ink == InkStyle::Textured);
+        self.scheme.set_active_id(Some(scheme.identifier()));
+
+        self.record.set_sensitive(recording.is_some());
+        match recording.flatten() {
+            Some(path) => {
+                self.record.set_active(true);
+                self.record
+                    .set_tooltip_text(Some(&format!("Recording to {}", path.display())));
+            }
+            None => {
+                self.record.set_active(false);
+                self.record
+                    .set_tooltip_text(Some("Record the raw frames of this session"));
+            }
+        }
+
+        if *self.layers.borrow() != layers {
+            self.show_layers(state, layers);
+        }
+
+        // relative to the tablet's screen
+        self.zoom_label.set_text(&format!("{:.0}%", scale * 100.0));
+
+        self.update_replay(state);
+    }
+
+    /// Shows how far the recording has been played, if one is played.
+    fn update_replay(&self, state: &Rc<RefCell<LiveViewState>>) {
+        let (time, duration, position, frames, speed, playing) = match &state.borrow().replay {
+            Some(replay) => (
+                replay.time(),
+                replay.duration(),
+                replay.position(),
+                replay.len(),
+                replay.speed(),
+                replay.is_playing(),
+            ),
+            None => return,
+        };
+
+        self.play.set_active(playing);
+        self.step_back.set_sensitive(position > 0);
+        self.step_forward.set_sensitive(position < frames);
+        self.speed.set_active_id(Some(&speed.to_string()));
+        self.seek.set_value(time.as_secs_f64());
+        self.replay_time.set_text(&format!(
+            "{} / {}",
+            format_time(time),
+            format_time(duration)
+        ));
+    }
+
+    /// Schedules drawing the damaged part of the view.
+    fn show_damage(&self, state: &Rc<RefCell<LiveViewState>>, damage: Damage) {
+        match damage {
+            Damage::None => {}
+            Damage::Area(rect) => {
+                self.update_toolbar(state);
+
+                let (x, y, width, height) = Damage::pixels(rect);
+                self.draw_area.queue_draw_area(x, y, width, height);
+            }
+            Damage::Full => self.update(state),
+        }
+    }
+
+    /// Fills the layer panel with one row per layer, topmost first like on
+    /// the tablet.
+    fn show_layers(&self, state: &Rc<RefCell<LiveViewState>>, layers: Vec<LayerRow>) {
+        for child in self.layer_box.children() {
+            self.layer_box.remove(&child);
+        }
+
+        for (index, title, visibility) in layers.iter().rev() {
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+
+            let label = gtk::Label::new(Some(title.as_str()));
+            label.set_xalign(0.0);
+            label.set_hexpand(true);
+
+            let selection = gtk::ComboBoxText::new();
+            selection.append(Some(LayerVisibility::Visible.identifier()), "Shown");
+            selection.append(Some(LayerVisibility::Dimmed.identifier()), "Dimmed");
+            selection.append(Some(LayerVisibility::Hidden.identifier()), "Hidden");
+            selection.set_active_id(Some(visibility.identifier()));
+
+            let index = *index;
+            let state = state.clone();
+            let controls = self.clone();
+            selection.connect_changed(move |selection| {
+                let visibility = match selection
+                    .active_id()
+                    .and_then(|id| LayerVisibility::from_identifier(&id))
+                {
+                    Some(visibility) => visibility,
+                    None => return,
+                };
+
+                state.borrow_mut().set_layer_visibility(index, visibility);
+
+                // keeps the row that emitted the signal instead of rebuilding
+                // the panel
+                for row in controls.layers.borrow_mut().iter_mut() {
+                    if row.0 == index {
+                        row.2 = visibility;
+                    }
+                }
+
+                controls.update(&state);
+            });
+
+            row.pack_start(&label, true, true, 0);
+            row.pack_start(&selection, false, false, 0);
+            self.layer_box.pack_start(&row, false, true, 0);
+        }
+
+        self.layer_box.show_all();
+        self.layers.replace(layers);
+    }
+}
+
+impl LiveViewWindow {
+    /**
+     * creates a new window rendering the binary frames received on the
+     * given channel for a tablet of the given profile
+     */
+    pub fn new(receiver: glib::Receiver<Vec<u8>>, profile: DeviceProfile) -> Self {
+        LiveViewWindow::build(Some(receiver), profile)
+    }
+
+    /// Creates a window playing back a recorded session with the given
+    /// title. The recording is fed through the same path as live frames.
+    pub fn replay(replay: Replay, title: &str, profile: DeviceProfile) -> Self {
+        let window = LiveViewWindow::build(None, profile);
+        window.window.set_title(title);
+
+        window
+            .controls
+            .seek
+            .set_range(0.0, replay.duration().as_secs_f64());
+        for speed in SPEEDS.iter() {
+            window
+                .controls
+                .speed
+                .append(Some(&speed.to_string()), &format!("{}×", speed));
+        }
+        window.controls.replay_bar.set_no_show_all(false);
+        window.controls.replay_bar.show_all();
+
+        window.state.borrow_mut().replay = Some(replay);
+        window.controls.update(&window.state);
+
+        window
+    }
+
+    fn build(receiver: Option<glib::Receiver<Vec<u8>>>, profile: DeviceProfile) -> Self {
+        let builder = gtk::Builder::from_string(LIVEVIEW_WINDOW_STRING);
 
         let window: gtk::Window = builder
-            .get_object("liveview_window")
+            .object("liveview_window")
             .expect("Failed to find liveview window");
         let draw_area: gtk::DrawingArea = builder
-            .get_object("liveview_draw_area")
+            .object("liveview_draw_area")
             .expect("Failed to find drawing area");
 
-        let (tx, rx) = std::sync::mpsc::channel();
-
-        let mut path = std::env::temp_dir();
-        path.push(uuid::Uuid::new_v4().to_string());
-        path.set_extension("pdf");
+        let controls = Controls {
+            drag: gtk::GestureDrag::new(&draw_area),
+            pinch: gtk::GestureZoom::new(&draw_area),
+            draw_area,
+            previous: builder
+                .object("liveview_previous_button")
+                .expect("Failed to find previous button"),
+            next: builder
+                .object("liveview_next_button")
+                .expect("Failed to find next button"),
+            follow: builder
+                .object("liveview_follow_button")
+                .expect("Failed to find follow button"),
+            page_label: builder
+                .object("liveview_page_label")
+                .expect("Failed to find page label"),
+            zoom_in: builder
+                .object("liveview_zoom_in_button")
+                .expect("Failed to find zoom in button"),
+            zoom_out: builder
+                .object("liveview_zoom_out_button")
+                .expect("Failed to find zoom out button"),
+            fit_width: builder
+                .object("liveview_fit_width_button")
+                .expect("Failed to find fit width button"),
+            fit_page: builder
+                .object("liveview_fit_page_button")
+                .expect("Failed to find fit page button"),
+            rotate: builder
+                .object("liveview_rotate_button")
+                .expect("Failed to find rotate button"),
+            zoom_label: builder
+                .object("liveview_zoom_label")
+                .expect("Failed to find zoom label"),
+            texture: builder
+                .object("liveview_texture_button")
+                .expect("Failed to find texture button"),
+            scheme: builder
+                .object("liveview_scheme_combo")
+                .expect("Failed to find colour scheme selection"),
+            record: builder
+                .object("liveview_record_button")
+                .expect("Failed to find record button"),
+            replay_bar: builder
+                .object("liveview_replay_bar")
+                .expect("Failed to find replay bar"),
+            play: builder
+                .object("liveview_play_button")
+                .expect("Failed to find play button"),
+            step_back: builder
+                .object("liveview_step_back_button")
+                .expect("Failed to find step back button"),
+            step_forward: builder
+                .object("liveview_step_forward_button")
+                .expect("Failed to find step forward button"),
+            seek: builder
+                .object("liveview_seek_scale")
+                .expect("Failed to find seek bar"),
+            replay_time: builder
+                .object("liveview_replay_time_label")
+                .expect("Failed to find replay time label"),
+            speed: builder
+                .object("liveview_speed_combo")
+                .expect("Failed to find speed selection"),
+            layer_box: builder
+                .object("liveview_layer_box")
+                .expect("Failed to find layer box"),
+            layers: Rc::new(RefCell::new(vec![])),
+        };
 
-        let surface = PdfSurface::new(
-            DEVICE_WIDTH / WINDOW_SCALER,
-            DEVICE_HEIGHT / WINDOW_SCALER,
-            path,
-        )
-        .expect("Failed to create PDF");
+        for scheme in ColorScheme::ALL.iter() {
+            controls
+                .scheme
+                .append(Some(scheme.identifier()), scheme.title());
+        }
 
-        let surface_clone = surface.clone();
+        let (state, frames) = LiveViewState::new(profile);
+        let state = Rc::new(RefCell::new(state));
 
+        let state_clone = state.clone();
         window.connect_destroy(move |_| {
-            debug!("Destroying thread");
-            surface_clone.finish();
-            let _ = tx.send(());
-        });
+            debug!("Destroying live view");
+            let mut state = state_clone.borrow_mut();
+            state.closed = true;
+            state.worker.stop();
 
-        tokio::spawn(async move {
-            rx.recv();
-            socket.join().expect("Couldn't join the socket");
+            if let Some(path) = state.recorder.stop() {
+                info!("Recorded live session to {:?}", path);
+            }
         });
 
-        window.show_all();
+        let (width, height) = profile.page_size(Orientation::Portrait);
+        window.set_default_size((width * VIEW_SCALE) as i32, (height * VIEW_SCALE) as i32);
 
-        draw_area.set_size_request(
-            (DEVICE_WIDTH / WINDOW_SCALER) as i32,
-            (DEVICE_HEIGHT / WINDOW_SCALER) as i32,
-        );
+        window.show_all();
+        controls.update(&state);
 
         LiveViewWindow {
             receiver,
-            draw_area,
+            frames: Some(frames),
+            documents: None,
             window,
-            surface,
+            controls,
+            state,
         }
     }
 
+    /// Downloads the documents opened on the tablet to show their source
+    /// and templates.
+    pub fn with_documents(mut self, documents: DocumentLoader) -> Self {
+        self.documents = Some(documents);
+        self
+    }
+
+    /// Shows the given templates below pages until a downloaded document
+    /// lists its own, e.g. when the tablet does not say which document is
+    /// open.
+    pub fn with_templates(self, templates: PageTemplates) -> Self {
+        self.state.borrow_mut().set_fallback_templates(templates);
+        self
+    }
+
+    /// Records the session into the given directory when switched on in the
+    /// window. The recorder may already be recording.
+    pub fn with_recorder(self, recorder: SessionRecorder, directory: PathBuf) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.recorder = recorder;
+            state.recordings = Some(directory);
+        }
+
+        self.controls.update(&self.state);
+        self
+    }
+
+    /// Plays the recording with the frame clock and connects the replay
+    /// bar.
+    fn connect_replay(&self) {
+        let documents = self.documents.clone();
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        let last = Cell::new(Instant::now());
+
+        self.controls.draw_area.add_tick_callback(move |_, _| {
+            if state.borrow().closed {
+                return ControlFlow::Break;
+            }
+
+            let now = Instant::now();
+            let elapsed = now - last.replace(now);
+
+            let playing = state
+                .borrow()
+                .replay
+                .as_ref()
+                .is_some_and(Replay::is_playing);
+            if playing {
+                control_replay(
+                    |replay| replay.advance(elapsed),
+                    &state,
+                    &controls,
+                    documents.as_ref(),
+                );
+            }
+
+            ControlFlow::Continue
+        });
+
+        let documents = self.documents.clone();
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.play.connect_toggled(move |button| {
+            let playing = state
+                .borrow()
+                .replay
+                .as_ref()
+                .is_some_and(Replay::is_playing);
+
+            if button.is_active() != playing {
+                control_replay(Replay::toggle, &state, &controls, documents.as_ref());
+            }
+        });
+
+        let steps: [(&gtk::Button, ReplayControl); 2] = [
+            (&self.controls.step_back, Replay::step_back),
+            (&self.controls.step_forward, Replay::step_forward),
+        ];
+
+        for (button, step) in steps.iter() {
+            let step = *step;
+            let documents = self.documents.clone();
+            let state = self.state.clone();
+            let controls = self.controls.clone();
+            button.connect_clicked(move |_| {
+                control_replay(step, &state, &controls, documents.as_ref());
+            });
+        }
+
+        let documents = self.documents.clone();
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        // only emitted when the user moves the slider
+        self.controls.seek.connect_change_value(move |_, _, value| {
+            let time = Duration::from_secs_f64(value.max(0.0));
+            control_replay(
+                |replay| replay.seek(time),
+                &state,
+                &controls,
+                documents.as_ref(),
+            );
+
+            Propagation::Proceed
+        });
+
+        let state = self.state.clone();
+        self.controls.speed.connect_changed(move |selection| {
+            let speed = selection.active_id().and_then(|id| id.parse::<f64>().ok());
+
+            if let (Some(speed), Some(replay)) = (speed, state.borrow_mut().replay.as_mut()) {
+                replay.set_speed(speed);
+            }
+        });
+    }
+
     /**
      * shows the window
      */
-    pub fn listen(self) {
+    pub fn listen(mut self) {
         debug!("Listening for events");
-        let draw = self.draw_area.clone();
 
-        let surface = self.surface.clone();
+        if let Some(receiver) = self.receiver.take() {
+            let documents = self.documents.clone();
+            let state = self.state.clone();
+            let controls = self.controls.clone();
 
-        let receiver = self.receiver;
+            receiver.attach(None, move |data| {
+                debug!("Received data");
 
-        let context = Context::new(&surface);
-        context.set_source_rgb(1., 1., 1.);
-        context.paint();
+                if state.borrow().closed {
+                    debug!("Live view closed, detaching receiver");
+                    return ControlFlow::Break;
+                }
 
-        receiver.attach(None, move |data| {
-            debug!("Received data");
+                // rendered with the next frame
+                receive(data, &state, &controls, documents.as_ref());
+                ControlFlow::Continue
+            });
+        }
 
-            let line = parse_binary_live_lines(data);
+        if let Some(frames) = self.frames.take() {
+            let state = self.state.clone();
 
-            match line {
-                Ok(line) => {
-                    trace!("Drawing {} points", line.points.len());
+            frames.attach(None, move |frame| {
+                if state.borrow().closed {
+                    return ControlFlow::Break;
+                }
 
-                    let points = line.points;
-                    let (r, g, b) = line.color.as_rgb();
+                // drawn with the next frame of the window
+                state.borrow_mut().present(frame);
+                ControlFlow::Continue
+            });
+        }
 
-                    if !points.is_empty() {
-                        context.save();
-                        context.set_source_rgb(r, g, b);
+        if self.state.borrow().replay.is_some() {
+            self.connect_replay();
+        }
 
-                        trace!("Starting at: {:?}", points[0]);
-                        trace!(
-                            "Width: {:?} {}",
-                            draw.get_property_width_request(),
-                            draw.get_property_height_request()
-                        );
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.draw_area.add_tick_callback(move |_, _| {
+            if state.borrow().closed {
+                return ControlFlow::Break;
+            }
 
-                        for p in points {
-                            context.set_line_width(p.width);
-                            context.arc(
-                                p.x / WINDOW_SCALER,
-                                p.y / WINDOW_SCALER,
-                                p.width / 2.0 / WINDOW_SCALER,
-                                0.0,
-                                2.0 * PI,
-                            );
-                            context.fill();
-                        }
+            let damage = state.borrow_mut().take_damage();
+            controls.show_damage(&state, damage);
 
-                        context.restore();
+            ControlFlow::Continue
+        });
 
-                        context.stroke();
-                    }
+        let state = self.state.clone();
+        self.controls
+            .draw_area
+            .connect_size_allocate(move |area, allocation| {
+                state.borrow_mut().resize(
+                    allocation.width() as f64,
+                    allocation.height() as f64,
+                    area.scale_factor() as f64,
+                );
+            });
 
-                    trace!("Finished");
-                }
-                Err(e) => {
-                    warn!("Error while parsing lines data: {}", e);
+        let state = self.state.clone();
+        self.controls.draw_area.connect_draw(move |_area, cx| {
+            trace!("Redrawing");
+
+            // only the clip set by GTK for the damaged area is painted
+            let state = state.borrow();
+            let painted = match &state.view {
+                Some(view) => cx.set_source_surface(view, 0.0, 0.0),
+                None => {
+                    let (r, g, b) = state.scheme.color(BACKGROUND);
+                    cx.set_source_rgb(r, g, b);
+                    Ok(())
                 }
+            }
+            .and_then(|_| cx.paint());
+
+            // the view is only written to while nothing else refers to it
+            cx.set_source_rgb(0.0, 0.0, 0.0);
+
+            if let Err(e) = painted {
+                warn!("Failed to paint the live view: {}", e);
+            }
+
+            Propagation::Proceed
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.previous.connect_clicked(move |_| {
+            if state.borrow_mut().navigator.previous_page() {
+                controls.update(&state);
+            }
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.next.connect_clicked(move |_| {
+            if state.borrow_mut().navigator.next_page() {
+                controls.update(&state);
+            }
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.follow.connect_toggled(move |button| {
+            let follow = button.is_active();
+
+            if follow == state.borrow().navigator.is_following() {
+                return;
+            }
+
+            state.borrow_mut().navigator.set_following(follow);
+            controls.update(&state);
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.texture.connect_toggled(move |button| {
+            let ink = if button.is_active() {
+                InkStyle::Textured
+            } else {
+                InkStyle::Clean
+            };
+
+            if ink == state.borrow().ink {
+                return;
+            }
+
+            state.borrow_mut().set_ink(ink);
+            controls.update(&state);
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.scheme.connect_changed(move |selection| {
+            let scheme = match selection
+                .active_id()
+                .and_then(|id| ColorScheme::from_identifier(&id))
+            {
+                Some(scheme) => scheme,
+                None => return,
             };
 
-            draw.queue_draw();
+            if scheme == state.borrow().scheme {
+                return;
+            }
 
-            Continue(true)
+            state.borrow_mut().set_scheme(scheme);
+            controls.update(&state);
         });
 
-        let draw = self.draw_area.clone();
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.record.connect_toggled(move |button| {
+            let (recorder, directory) = {
+                let state = state.borrow();
+                (state.recorder.clone(), state.recordings.clone())
+            };
 
-        let surface = self.surface.clone();
-        draw.connect_draw(move |_area, cx| {
-            trace!("Redrawing");
+            let directory = match directory {
+                Some(directory) => directory,
+                None => return,
+            };
 
-            cx.set_source_surface(&surface, 0.0, 0.0);
-            cx.paint();
-            cx.set_source_rgba(1., 1., 1., 1.);
+            if button.is_active() == recorder.is_recording() {
+                return;
+            }
 
-            Inhibit(false)
+            if button.is_active() {
+                if let Err(e) = recorder.start(&directory) {
+                    show_error("Failed to record the session", &e);
+                }
+            } else if let Some(path) = recorder.stop() {
+                info!("Recorded live session to {:?}", path);
+            }
+
+            controls.update(&state);
         });
 
-        let context = Context::new(&self.surface);
-        context.set_source_rgb(1., 1., 1.);
-        context.paint();
+        let viewport_buttons: [(&gtk::Button, ViewportControl); 5] = [
+            (&self.controls.zoom_in, Viewport::zoom_in),
+            (&self.controls.zoom_out, Viewport::zoom_out),
+            (&self.controls.fit_width, |viewport| {
+                viewport.set_fit(FitMode::Width)
+            }),
+            (&self.controls.fit_page, |viewport| {
+                viewport.set_fit(FitMode::Page)
+            }),
+            (&self.controls.rotate, |viewport| viewport.rotate(true)),
+        ];
+
+        for (button, change) in viewport_buttons.iter() {
+            let change = *change;
+            let state = self.state.clone();
+            let controls = self.controls.clone();
+            button.connect_clicked(move |_| {
+                state.borrow_mut().change_viewport(change);
+                controls.update(&state);
+            });
+        }
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls
+            .draw_area
+            .connect_scroll_event(move |area, event| {
+                let (_, dy) = event.delta();
+
+                if dy == 0.0 {
+                    return Propagation::Proceed;
+                }
+
+                // scrolling up zooms in
+                let position = event.position();
+                state
+                    .borrow_mut()
+                    .change_viewport(|viewport| viewport.zoom_steps(-dy, position));
+                controls.update(&state);
+                area.queue_draw();
 
-        draw.queue_draw();
+                Propagation::Stop
+            });
+
+        let dragged = Rc::new(Cell::new((0.0, 0.0)));
+        let dragged_clone = dragged.clone();
+        self.controls.drag.connect_drag_begin(move |_, _, _| {
+            dragged_clone.set((0.0, 0.0));
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls.drag.connect_drag_update(move |_, x, y| {
+            // the gesture reports the distance from where the drag started
+            let (last_x, last_y) = dragged.get();
+            dragged.set((x, y));
+
+            state
+                .borrow_mut()
+                .change_viewport(|viewport| viewport.pan(x - last_x, y - last_y));
+            controls.update(&state);
+        });
+
+        let pinched = Rc::new(Cell::new(1.0));
+        let pinched_clone = pinched.clone();
+        self.controls.pinch.connect_begin(move |_, _| {
+            pinched_clone.set(1.0);
+        });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        self.controls
+            .pinch
+            .connect_scale_changed(move |gesture, scale| {
+                // the gesture reports the scale since the pinch started
+                let factor = scale / pinched.get();
+                pinched.set(scale);
+
+                let center = gesture
+                    .bounding_box_center()
+                    .unwrap_or_else(|| state.borrow().viewport.center());
+                state
+                    .borrow_mut()
+                    .change_viewport(|viewport| viewport.zoom_at(factor, center));
+                controls.update(&state);
+            });
+
+        let state = self.state.clone();
+        let controls = self.controls.clone();
+        let documents = self.documents.clone();
+        self.window.connect_key_press_event(move |_, event| {
+            let keyval = event.keyval().name();
+
+            let replay_key: Option<ReplayControl> = match keyval.as_deref() {
+                Some("space") => Some(Replay::toggle),
+                Some("comma") => Some(Replay::step_back),
+                Some("period") => Some(Replay::step_forward),
+                _ => None,
+            };
+
+            if let Some(change) = replay_key.filter(|_| state.borrow().replay.is_some()) {
+                control_replay(change, &state, &controls, documents.as_ref());
+                return Propagation::Stop;
+            }
+
+            let handled = {
+                let mut state = state.borrow_mut();
+
+                match keyval.as_deref() {
+                    Some("Left") | Some("Page_Up") => {
+                        state.navigator.previous_page();
+                        true
+                    }
+                    Some("Right") | Some("Page_Down") => {
+                        state.navigator.next_page();
+                        true
+                    }
+                    Some("End") | Some("f") => {
+                        state.navigator.set_following(true);
+                        true
+                    }
+                    Some("plus") | Some("equal") | Some("KP_Add") => {
+                        state.change_viewport(Viewport::zoom_in);
+                        true
+                    }
+                    Some("minus") | Some("KP_Subtract") => {
+                        state.change_viewport(Viewport::zoom_out);
+                        true
+                    }
+                    Some("w") => {
+                        state.change_viewport(|viewport| viewport.set_fit(FitMode::Width));
+                        true
+                    }
+                    Some("p") => {
+                        state.change_viewport(|viewport| viewport.set_fit(FitMode::Page));
+                        true
+                    }
+                    Some("r") => {
+                        state.change_viewport(|viewport| viewport.rotate(true));
+                        true
+                    }
+                    Some("R") => {
+                        state.change_viewport(|viewport| viewport.rotate(false));
+                        true
+                    }
+                    Some("t") => {
+                        let ink = match state.ink {
+                            InkStyle::Clean => InkStyle::Textured,
+                            InkStyle::Textured => InkStyle::Clean,
+                        };
+                        state.set_ink(ink);
+                        true
+                    }
+                    Some("c") => {
+                        let schemes = ColorScheme::ALL;
+                        let current = schemes.iter().position(|scheme| *scheme == state.scheme);
+                        let next = current.map_or(0, |index| (index + 1) % schemes.len());
+                        state.set_scheme(schemes[next]);
+                        true
+                    }
+                    _ => false,
+                }
+            };
+
+            if handled {
+                controls.update(&state);
+            }
+
+            Propagation::from(handled)
+        });
+
+        self.controls.draw_area.queue_draw();
+    }
+}
+
+/// Parses a frame and applies it to the view. Documents opened on the tablet
+/// are downloaded to draw their source and templates.
+fn receive(
+    data: Vec<u8>,
+    state: &Rc<RefCell<LiveViewState>>,
+    controls: &Controls,
+    documents: Option<&DocumentLoader>,
+) {
+    match parse_live_message(data) {
+        Ok(message) => {
+            if let (LiveSyncMessage::DocumentOpened { id, .. }, Some(documents)) =
+                (&message, documents)
+            {
+                load_document(documents(id.clone()), state, controls);
+            }
+
+            state.borrow_mut().handle(message)
+        }
+        Err(e) => {
+            warn!("Error while parsing lines data: {}", e);
+        }
+    };
+}
+
+/// Changes the replay and feeds the frames needed to get there into the
+/// view, as if they had just been received.
+fn control_replay<F: FnOnce(&mut Replay) -> Feed>(
+    change: F,
+    state: &Rc<RefCell<LiveViewState>>,
+    controls: &Controls,
+    documents: Option<&DocumentLoader>,
+) {
+    let feed = match state.borrow_mut().replay.as_mut() {
+        Some(replay) => change(replay),
+        None => return,
+    };
+
+    if feed.restart {
+        state.borrow_mut().restart();
+    }
+
+    for frame in feed.frames {
+        receive(frame, state, controls, documents);
     }
+
+    controls.update_replay(state);
+}
+
+/// Shows the document published on the given channel once it arrives.
+fn load_document(
+    receiver: glib::Receiver<Result<CachedDocument, String>>,
+    state: &Rc<RefCell<LiveViewState>>,
+    controls: &Controls,
+) {
+    let state = state.clone();
+    let controls = controls.clone();
+
+    receiver.attach(None, move |result| {
+        match result {
+            Ok(document) => {
+                debug!("Loaded version {} of {}", document.version, document.id);
+                state.borrow_mut().set_document(document);
+                controls.update(&state);
+            }
+            Err(e) => warn!("Failed to load the open document: {}", e),
+        }
+
+        ControlFlow::Break
+    });
 }
@@ -1,2 +1,12 @@
+pub mod liveview_window;
 pub mod model;
+pub mod render_worker;
 pub mod view;
+
+/// Creates a channel whose receiver is attached to the GTK main loop. glib
+/// deprecates these channels in favour of async ones, which would change
+/// every receiver of the application.
+#[allow(deprecated)]
+pub fn main_channel<T>() -> (glib::Sender<T>, glib::Receiver<T>) {
+    glib::MainContext::channel(glib::Priority::DEFAULT)
+}
@@ -1,22 +1,36 @@
-use std::sync::{Arc, Mutex};
+use std::{cell::RefCell, path::Path, rc::Rc};
 
 use gio::prelude::*;
 use glib::clone;
-use gtk::{Application, DialogExt, GtkApplicationExt, GtkWindowExt};
-use log::{debug, trace};
+use gtk::{prelude::*, Application};
+use log::{debug, trace, warn};
 
 use crate::{
     application::{
-        model::AppModelled,
-        view::app_view::{build_about_dialog, AppView},
+        liveview_window::LiveViewWindow,
+        model::{replay::Replay, AppModelled},
+        view::{
+            app_view::{build_about_dialog, AppView},
+            error::show_error,
+        },
+    },
+    config::arguments::RecordingOptions,
+    remarkable::{
+        format::{device::DeviceProfile, template::PageTemplates},
+        recording::{SessionRecorder, EXTENSION},
     },
     view::otp_view::OtpView,
 };
 
 pub struct AppController {
-    model: Arc<Mutex<Box<dyn AppModelled>>>,
-    view: Arc<AppView>,
-    otp_view: Arc<OtpView>,
+    model: Rc<RefCell<Box<dyn AppModelled>>>,
+    view: Rc<AppView>,
+    otp_view: Rc<OtpView>,
+    recording: RecordingOptions,
+    /// The tablet whose pages are shown
+    profile: DeviceProfile,
+    /// The templates shown until the open document is downloaded
+    templates: PageTemplates,
 }
 
 impl AppController {
@@ -24,12 +38,35 @@ impl AppController {
         debug!("AppController::new()");
 
         AppController {
-            model: Arc::new(Mutex::new(model)),
-            view: Arc::new(view),
-            otp_view: Arc::new(otp_view),
+            model: Rc::new(RefCell::new(model)),
+            view: Rc::new(view),
+            otp_view: Rc::new(otp_view),
+            recording: RecordingOptions::default(),
+            profile: DeviceProfile::default(),
+            templates: PageTemplates::default(),
         }
     }
 
+    /// Sets where live sessions are recorded to and whether every session is
+    /// recorded from its start.
+    pub fn with_recording(mut self, recording: RecordingOptions) -> Self {
+        self.recording = recording;
+        self
+    }
+
+    /// Sets the tablet whose pages are shown in live sessions and replays.
+    pub fn with_device(mut self, profile: DeviceProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Sets the templates of pages in live sessions and replays, for
+    /// documents that are not downloaded.
+    pub fn with_templates(mut self, templates: PageTemplates) -> Self {
+        self.templates = templates;
+        self
+    }
+
     pub fn show_view(&self) {
         debug!("Showing Window");
         self.view.show_window();
@@ -52,15 +89,50 @@ impl AppController {
         });
 
         let quit = gio::SimpleAction::new("quit", None);
-        let channel = self.model.lock().unwrap().get_termination_channel();
+        let channel = self.model.borrow_mut().get_termination_channel();
 
         quit.connect_activate(clone!(@strong window => move |_, _| {
             debug!("Quit clicked");
             window.close();
-            let _ =channel.send(());
+            let _ = channel.try_send(());
         }));
 
-        let actions = vec![about, quit];
+        let replay = gio::SimpleAction::new("replay", None);
+        let model = self.model.clone();
+        let recordings = self.recording.directory.clone();
+        let profile = self.profile;
+        let templates = self.templates.clone();
+
+        replay.connect_activate(clone!(@strong window => move |_, _| {
+            debug!("Open recording clicked");
+            let dialog = gtk::FileChooserDialog::with_buttons(
+                Some("Open recording"),
+                Some(&window),
+                gtk::FileChooserAction::Open,
+                &[
+                    ("_Cancel", gtk::ResponseType::Cancel),
+                    ("_Open", gtk::ResponseType::Accept),
+                ],
+            );
+
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some("Recorded sessions"));
+            filter.add_pattern(&format!("*.{}", EXTENSION));
+            dialog.add_filter(filter);
+            dialog.set_current_folder(&recordings);
+
+            let path = match dialog.run() {
+                gtk::ResponseType::Accept => dialog.filename(),
+                _ => None,
+            };
+            dialog.close();
+
+            if let Some(path) = path {
+                open_replay(&model, &path, profile, &templates);
+            }
+        }));
+
+        let actions = vec![about, replay, quit];
 
         for action in actions {
             application.add_action(&action);
@@ -95,15 +167,22 @@ impl AppController {
         self.show_view();
     }
 
+    /// Opens a window playing back the recorded session at the given path.
+    pub fn open_replay(&self, path: &Path) {
+        open_replay(&self.model, path, self.profile, &self.templates);
+    }
+
     pub fn start_search(&mut self) {
         debug!("Searching");
 
-        let _ = self.model.lock().unwrap().start_search();
+        let _ = self.model.borrow_mut().start_search();
+
+        connect_liveview(&self.model, &self.recording, self.profile, &self.templates);
     }
 
     fn check_and_show_login_dialog(&mut self) {
         let model = self.model.clone();
-        let logged = model.lock().unwrap().is_logged_in();
+        let logged = model.borrow_mut().is_logged_in();
         debug!("User is logged in: {}", logged);
 
         if !logged {
@@ -121,10 +200,13 @@ impl AppController {
 
         let model = self.model.clone();
         let otp_view = self.otp_view.clone();
+        let recording = self.recording.clone();
+        let profile = self.profile;
+        let templates = self.templates.clone();
 
         channel.attach(None, move |otp| {
             trace!("OTP is: {}", otp);
-            let result = model.lock().unwrap().login_user(otp);
+            let result = model.borrow_mut().login_user(otp);
             otp_view.show_validating_info();
 
             trace!("OTP Result: {:?}", result);
@@ -133,7 +215,9 @@ impl AppController {
                 Ok(_) => {
                     debug!("OTP Validation passed!");
                     otp_view.close_login_dialog();
-                    let _ = model.lock().unwrap().start_search();
+                    let _ = model.borrow_mut().start_search();
+
+                    connect_liveview(&model, &recording, profile, &templates);
                 }
                 Err(e) => {
                     debug!("OTP Validation failed: {}", e);
@@ -141,7 +225,84 @@ impl AppController {
                 }
             }
 
-            glib::Continue(true)
+            glib::ControlFlow::Continue
         });
     }
 }
+
+/// Opens a live view window for every live session announced by the model.
+fn connect_liveview(
+    model: &Rc<RefCell<Box<dyn AppModelled>>>,
+    recording: &RecordingOptions,
+    profile: DeviceProfile,
+    templates: &PageTemplates,
+) {
+    trace!("app_controller::connect_liveview()");
+
+    let channel = match model.borrow_mut().take_liveview_channel() {
+        Some(channel) => channel,
+        None => {
+            debug!("Live view channel already connected");
+            return;
+        }
+    };
+
+    let model = model.clone();
+    let recording = recording.clone();
+    let templates = templates.clone();
+
+    channel.attach(None, move |session_token| {
+        debug!("Opening live view window");
+
+        // started before connecting, so the session is recorded completely
+        let recorder = SessionRecorder::new();
+        if recording.record {
+            if let Err(e) = recorder.start(&recording.directory) {
+                warn!("Failed to record live session: {}", e);
+            }
+        }
+
+        let frames = model
+            .borrow_mut()
+            .open_liveview(session_token, recorder.clone());
+
+        let documents = model.clone();
+        LiveViewWindow::new(frames, profile)
+            .with_templates(templates.clone())
+            .with_documents(Rc::new(move |id| documents.borrow_mut().open_document(id)))
+            .with_recorder(recorder, recording.directory.clone())
+            .listen();
+
+        glib::ControlFlow::Continue
+    });
+}
+
+/// Opens a window playing back a recorded session. Documents opened during
+/// the session are downloaded if logged in.
+fn open_replay(
+    model: &Rc<RefCell<Box<dyn AppModelled>>>,
+    path: &Path,
+    profile: DeviceProfile,
+    templates: &PageTemplates,
+) {
+    debug!("Opening replay of {:?}", path);
+
+    let replay = match Replay::open(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            show_error("Failed to open the recording", &e);
+            return;
+        }
+    };
+
+    let title = match path.file_name() {
+        Some(name) => format!("Replay of {}", name.to_string_lossy()),
+        None => "Replay".to_string(),
+    };
+
+    let documents = model.clone();
+    LiveViewWindow::replay(replay, &title, profile)
+        .with_templates(templates.clone())
+        .with_documents(Rc::new(move |id| documents.borrow_mut().open_document(id)))
+        .listen();
+}
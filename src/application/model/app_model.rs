@@ -1,47 +1,75 @@
-use log::{debug, trace};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, trace, warn};
 use tokio::{
     runtime::Runtime,
     sync::mpsc::{channel, Receiver, Sender},
 };
 
 use crate::{
-    application::model::AppModelled,
-    config::{config::Config, UnserializableConfig},
+    application::{main_channel, model::AppModelled},
+    config::{config::Config, config_io::resolve_document_cache_path, UnserializableConfig},
     remarkable::{
+        documents::{CachedDocument, DocumentCache},
+        files::{fetch_document, APIConnection},
+        recording::SessionRecorder,
         tokens,
         tokens::discover,
-        web_socket::{await_message, create_socket, get_livesync_url},
+        web_socket::{
+            await_message, create_socket, get_livesync_url, get_liveview_url, stream_liveview,
+            SocketEvent,
+        },
         BaseDomains, RMTokenInterface,
     },
 };
 
+/// The config, shared with the tasks refreshing the session key.
+type SharedConfig = Arc<Mutex<Box<dyn UnserializableConfig>>>;
+
 pub struct AppModel {
-    config: Box<dyn UnserializableConfig>,
+    config: SharedConfig,
     termination_sender: Sender<()>,
     termination_receiver: Receiver<()>,
 
-    rm_api: Box<dyn RMTokenInterface>,
+    rm_api: Arc<dyn RMTokenInterface>,
 
     runtime: Runtime,
     domains: BaseDomains,
+
+    liveview_sender: glib::Sender<String>,
+    liveview_receiver: Option<glib::Receiver<String>>,
+
+    documents: DocumentCache,
 }
 
 impl AppModel {
     pub fn new(config: Config) -> Self {
         let (termination_sender, termination_receiver) = channel(1);
+        let (liveview_sender, liveview_receiver) = main_channel();
         let runtime = Runtime::new().unwrap();
 
         let domains = runtime
             .block_on(discover())
             .expect("Failed to discover domains");
 
+        let documents = match resolve_document_cache_path() {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Caching documents in the temporary directory: {}", e);
+                std::env::temp_dir().join("review")
+            }
+        };
+
         AppModel {
-            config: Box::new(config),
+            config: Arc::new(Mutex::new(Box::new(config))),
             termination_receiver,
             termination_sender,
             runtime,
-            rm_api: Box::new(tokens::RMTokens::new(domains.clone())),
+            rm_api: Arc::new(tokens::RMTokens::new(domains.clone())),
             domains,
+            liveview_sender,
+            liveview_receiver: Some(liveview_receiver),
+            documents: DocumentCache::new(documents),
         }
     }
 }
@@ -55,7 +83,7 @@ impl AppModelled for AppModel {
     fn is_logged_in(&mut self) -> bool {
         debug!("app_model::is_logged_in()");
         // 1. get exp of session token
-        let session_exp = &self.config.get_expiry();
+        let session_exp = &self.config.lock().unwrap().get_expiry();
 
         if session_exp.is_ok() && session_exp.as_ref().unwrap().as_secs() > 0 {
             debug!("Session token is valid");
@@ -63,18 +91,16 @@ impl AppModelled for AppModel {
         }
 
         // Attempt to refresh session token
-        if let Ok(_) = self.refresh_session_token() {
+        if self.refresh_session_token().is_ok() {
             debug!("Session token refreshed");
             return true;
         }
 
-        return false;
+        false
     }
 
     fn start_search(&mut self) -> Result<(), String> {
-        let config = &self.config;
-
-        let device_key = config.get_device_key();
+        let device_key = self.config.lock().unwrap().get_device_key();
         let token = self.get_session_key();
 
         trace!("Session token is: {:?}", &token);
@@ -89,6 +115,7 @@ impl AppModelled for AppModel {
         let _rx = &self.termination_receiver;
 
         let base_domains = self.domains.clone();
+        let liveview_sender = self.liveview_sender.clone();
 
         let _search_task = self.runtime.spawn(async move {
             debug!("Searching using device key {:?}", device_key);
@@ -103,7 +130,21 @@ impl AppModelled for AppModel {
             let mut client = client.unwrap();
 
             loop {
-                let _message = await_message(&mut client).await;
+                match await_message(&mut client).await {
+                    Ok(SocketEvent::LiveSyncStarted(session_token, _)) => {
+                        debug!("Live sync started");
+                        if liveview_sender.send(session_token).is_err() {
+                            warn!("Nobody is listening for live sessions");
+                        }
+                    }
+                    Ok(SocketEvent::DocAdded(_)) => {
+                        debug!("Document added");
+                    }
+                    Err(e) => {
+                        warn!("Notification socket failed: {}", e);
+                        break;
+                    }
+                }
                 debug!("Searching for {:?}", device_key);
             }
         });
@@ -112,7 +153,7 @@ impl AppModelled for AppModel {
     }
 
     fn update_config(&mut self, config: Box<dyn UnserializableConfig>) {
-        self.config = config;
+        self.config = Arc::new(Mutex::new(config));
     }
 
     fn get_termination_channel(&self) -> Sender<()> {
@@ -120,23 +161,8 @@ impl AppModelled for AppModel {
     }
 
     fn refresh_session_token(&mut self) -> Result<String, String> {
-        debug!("Refreshing session token");
-
-        let token = self.config.get_device_key()?;
-
-        trace!("Device token is: {:?}", &token);
-
-        let result = self
-            .runtime
-            .block_on(self.rm_api.create_session_token(&token));
-
-        match result {
-            Ok(token) => {
-                self.config.set_session_key(token.clone());
-                Ok(token)
-            }
-            Err(e) => Err(e.to_string()),
-        }
+        self.runtime
+            .block_on(refresh_session_key(&self.config, self.rm_api.as_ref()))
     }
 
     fn login_user(&mut self, otp: String) -> Result<(), String> {
@@ -144,22 +170,107 @@ impl AppModelled for AppModel {
 
         let result = self.runtime.block_on(self.rm_api.login(&otp))?;
 
-        self.config.set_device_key(result);
+        self.config.lock().unwrap().set_device_key(result);
 
         Ok(())
     }
 
     fn get_session_key(&mut self) -> Result<String, String> {
-        if let Ok(key) = self.config.get_session_key() {
-            if self.config.get_expiry().is_ok() {
-                return Ok(key);
-            }
+        if let Some(key) = cached_session_key(&self.config) {
+            return Ok(key);
         }
 
-        let _key = self.refresh_session_token()?;
+        self.refresh_session_token()
+    }
 
-        return Err("No session key found".into());
+    fn take_liveview_channel(&mut self) -> Option<glib::Receiver<String>> {
+        self.liveview_receiver.take()
     }
+
+    fn open_liveview(
+        &mut self,
+        session_token: String,
+        recorder: SessionRecorder,
+    ) -> glib::Receiver<Vec<u8>> {
+        debug!("Opening live view");
+
+        let (sender, receiver) = main_channel();
+        let url = get_liveview_url(&self.domains);
+
+        trace!("Live view url: {}", url);
+
+        self.runtime.spawn(async move {
+            if let Err(e) = stream_liveview(&url, &session_token, sender, recorder).await {
+                warn!("Live view socket failed: {}", e);
+            }
+        });
+
+        receiver
+    }
+
+    fn open_document(&mut self, id: String) -> glib::Receiver<Result<CachedDocument, String>> {
+        debug!("Opening document {}", id);
+
+        let (sender, receiver) = main_channel();
+
+        let config = self.config.clone();
+        let rm_api = self.rm_api.clone();
+        let storage_url = self.domains.storage.clone();
+        let cache = self.documents.clone();
+
+        // refreshing the session key waits for the cloud, so it is not done
+        // on the main loop either
+        self.runtime.spawn(async move {
+            let session_key = match cached_session_key(&config) {
+                Some(key) => key,
+                None => match refresh_session_key(&config, rm_api.as_ref()).await {
+                    Ok(key) => key,
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                },
+            };
+
+            let connection = APIConnection {
+                storage_url: &storage_url,
+                session_key: &session_key,
+            };
+            let result = fetch_document(connection, &cache, &id).await;
+
+            if sender.send(result).is_err() {
+                debug!("Nobody is waiting for document {} anymore", id);
+            }
+        });
+
+        receiver
+    }
+}
+
+/// Returns the session key of the config unless it has expired.
+fn cached_session_key(config: &SharedConfig) -> Option<String> {
+    let config = config.lock().unwrap();
+    let key = config.get_session_key().ok()?;
+
+    config.get_expiry().ok().map(|_| key)
+}
+
+/// Creates a new session key from the device key and stores it in the
+/// config.
+async fn refresh_session_key(
+    config: &SharedConfig,
+    rm_api: &dyn RMTokenInterface,
+) -> Result<String, String> {
+    debug!("Refreshing session token");
+
+    let device_key = config.lock().unwrap().get_device_key()?;
+
+    trace!("Device token is: {:?}", &device_key);
+
+    let key = rm_api.create_session_token(&device_key).await?;
+    config.lock().unwrap().set_session_key(key.clone());
+
+    Ok(key)
 }
 
 #[cfg(test)]
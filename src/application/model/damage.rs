@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A rectangle in view coordinates as (x, y, width, height).
+pub type Rect = (f64, f64, f64, f64);
+
+/// The part of the view that has to be drawn again.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Damage {
+    #[default]
+    None,
+    /// Only the given area changed
+    Area(Rect),
+    /// Everything changed, e.g. because the page was placed differently
+    Full,
+}
+
+/// The changes to the scene since the last frame. Messages often arrive
+/// faster than the screen refreshes, so they are collected and rendered
+/// together once per frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameChanges {
+    /// The first new point of every line changed, by page, layer and id
+    lines: BTreeMap<(u32, u32, String), usize>,
+    /// Pages that have to be rendered completely
+    pages: BTreeSet<u32>,
+}
+
+impl Damage {
+    /// Adds an area to the damage.
+    pub fn add(&mut self, rect: Rect) {
+        let (_, _, width, height) = rect;
+
+        if width <= 0.0 || height <= 0.0 {
+            return;
+        }
+
+        *self = match *self {
+            Damage::None => Damage::Area(rect),
+            Damage::Area(area) => Damage::Area(union(area, rect)),
+            Damage::Full => Damage::Full,
+        };
+    }
+
+    pub fn add_full(&mut self) {
+        *self = Damage::Full;
+    }
+
+    /// Returns the damage collected so far and starts over.
+    pub fn take(&mut self) -> Damage {
+        std::mem::take(self)
+    }
+
+    /// The smallest area of whole pixels containing the damaged area.
+    pub fn pixels(rect: Rect) -> (i32, i32, i32, i32) {
+        let (x, y, width, height) = rect;
+        let (left, top) = (x.floor(), y.floor());
+        let (right, bottom) = ((x + width).ceil(), (y + height).ceil());
+
+        (
+            left as i32,
+            top as i32,
+            (right - left) as i32,
+            (bottom - top) as i32,
+        )
+    }
+}
+
+impl FrameChanges {
+    /// Records that the points of a line starting at the given index
+    /// changed.
+    pub fn draw(&mut self, page: u32, layer: u32, id: &str, from: usize) {
+        if self.pages.contains(&page) {
+            return;
+        }
+
+        self.lines
+            .entry((page, layer, id.to_string()))
+            .and_modify(|first| *first = (*first).min(from))
+            .or_insert(from);
+    }
+
+    /// Records that a page has to be rendered completely, which includes all
+    /// lines changed on it.
+    pub fn redraw(&mut self, page: u32) {
+        self.lines.retain(|(line_page, _, _), _| *line_page != page);
+        self.pages.insert(page);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.pages.is_empty()
+    }
+
+    /// Returns the changes collected so far and starts over.
+    pub fn take(&mut self) -> FrameChanges {
+        std::mem::take(self)
+    }
+
+    /// The changed lines as page, layer, id and first changed point.
+    pub fn lines(&self) -> impl Iterator<Item = (u32, u32, &str, usize)> {
+        self.lines
+            .iter()
+            .map(|((page, layer, id), from)| (*page, *layer, id.as_str(), *from))
+    }
+
+    /// The pages to render completely.
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pages.iter().copied()
+    }
+}
+
+fn union(a: Rect, b: Rect) -> Rect {
+    let left = a.0.min(b.0);
+    let top = a.1.min(b.1);
+    let right = (a.0 + a.2).max(b.0 + b.2);
+    let bottom = (a.1 + a.3).max(b.1 + b.3);
+
+    (left, top, right - left, bottom - top)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_damage_grows() {
+        let mut damage = Damage::default();
+
+        damage.add((10.0, 10.0, 5.0, 5.0));
+        damage.add((0.0, 12.0, 2.0, 20.0));
+        damage.add((50.0, 50.0, 0.0, 0.0));
+
+        assert_eq!(damage, Damage::Area((0.0, 10.0, 15.0, 22.0)));
+
+        damage.add_full();
+        damage.add((0.0, 0.0, 1.0, 1.0));
+        assert_eq!(damage.take(), Damage::Full);
+        assert_eq!(damage, Damage::None);
+    }
+
+    #[test]
+    fn test_pixels_cover_area() {
+        assert_eq!(Damage::pixels((0.5, 1.2, 2.0, 0.5)), (0, 1, 3, 1));
+    }
+
+    #[test]
+    fn test_lines_are_coalesced() {
+        let mut changes = FrameChanges::default();
+
+        changes.draw(0, 0, "a", 10);
+        changes.draw(0, 0, "a", 12);
+        changes.draw(0, 0, "a", 4);
+        changes.draw(1, 0, "b", 0);
+
+        assert_eq!(
+            changes.lines().collect::<Vec<_>>(),
+            vec![(0, 0, "a", 4), (1, 0, "b", 0)]
+        );
+    }
+
+    #[test]
+    fn test_redraw_replaces_lines() {
+        let mut changes = FrameChanges::default();
+
+        changes.draw(0, 0, "a", 0);
+        changes.redraw(0);
+        changes.draw(0, 1, "b", 0);
+
+        assert_eq!(changes.lines().count(), 0);
+        assert_eq!(changes.pages().collect::<Vec<_>>(), vec![0]);
+
+        let taken = changes.take();
+        assert!(!taken.is_empty());
+        assert!(changes.is_empty());
+    }
+}
@@ -1 +1,169 @@
+use std::collections::HashMap;
 
+use log::trace;
+
+use crate::remarkable::format::data::Line;
+
+/// Describes how a line changed after an update was merged into the store.
+#[derive(Debug, Eq, PartialEq)]
+pub enum StrokeUpdate {
+    /// The line has not been seen before
+    Added,
+    /// The line grew. Contains the index of the first point not yet rendered
+    Extended(usize),
+    /// The line changed in a way that requires a full redraw
+    Replaced,
+}
+
+/// Keeps the latest version of every line received during a live session,
+/// keyed by the line id sent by the tablet.
+#[derive(Debug, Default)]
+pub struct StrokeStore {
+    lines: HashMap<String, Line>,
+    order: Vec<String>,
+}
+
+impl StrokeStore {
+    pub fn new() -> Self {
+        StrokeStore::default()
+    }
+
+    /// Merges a (possibly partial) line into the store and reports what
+    /// needs to be rendered.
+    pub fn update(&mut self, id: String, line: Line) -> StrokeUpdate {
+        let result = match self.lines.get(&id) {
+            None => {
+                self.order.push(id.clone());
+                StrokeUpdate::Added
+            }
+            Some(previous) if is_extension(previous, &line) => {
+                StrokeUpdate::Extended(previous.points.len())
+            }
+            Some(_) => StrokeUpdate::Replaced,
+        };
+
+        trace!("Line {} updated: {:?}", id, result);
+
+        self.lines.insert(id, line);
+
+        result
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Line> {
+        self.lines.get(id)
+    }
+
+    /// Adds a line after all others, replacing a line with the same id.
+    pub fn insert(&mut self, id: String, line: Line) {
+        if !self.lines.contains_key(&id) {
+            self.order.push(id.clone());
+        }
+        self.lines.insert(id, line);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Line> {
+        let line = self.lines.remove(id)?;
+        self.order.retain(|other| other != id);
+        Some(line)
+    }
+
+    /// Iterates the ids and lines in the order they were first received.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Line)> {
+        self.order
+            .iter()
+            .filter_map(move |id| self.lines.get(id).map(|line| (id, line)))
+    }
+
+    /// Iterates all lines in the order they were first received.
+    pub fn lines(&self) -> impl Iterator<Item = &Line> {
+        self.order.iter().filter_map(move |id| self.lines.get(id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.order.clear();
+    }
+}
+
+/// A line is an extension of another if it uses the same tool and starts
+/// with the same points.
+fn is_extension(previous: &Line, line: &Line) -> bool {
+    previous.brush == line.brush &&
+        previous.color == line.color &&
+        line.points.len() >= previous.points.len() &&
+        previous
+            .points
+            .iter()
+            .zip(line.points.iter())
+            .all(|(a, b)| a.x == b.x && a.y == b.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::format::data::{PenColor, PenType, Point};
+
+    fn line(points: &[(f64, f64)]) -> Line {
+        Line {
+            points: points
+                .iter()
+                .map(|(x, y)| Point::new(*x, *y, 2.0, 1.0))
+                .collect(),
+            brush: PenType::Fineliner,
+            color: PenColor::BLACK,
+        }
+    }
+
+    #[test]
+    fn test_growing_line_is_extended() {
+        let mut store = StrokeStore::new();
+
+        assert_eq!(
+            store.update("a".into(), line(&[(0.0, 0.0), (1.0, 1.0)])),
+            StrokeUpdate::Added
+        );
+        assert_eq!(
+            store.update("a".into(), line(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)])),
+            StrokeUpdate::Extended(2)
+        );
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("a").unwrap().points.len(), 3);
+    }
+
+    #[test]
+    fn test_changed_line_is_replaced() {
+        let mut store = StrokeStore::new();
+
+        store.update("a".into(), line(&[(0.0, 0.0), (1.0, 1.0)]));
+
+        assert_eq!(
+            store.update("a".into(), line(&[(5.0, 5.0), (1.0, 1.0)])),
+            StrokeUpdate::Replaced
+        );
+        assert_eq!(
+            store.update("a".into(), line(&[(5.0, 5.0)])),
+            StrokeUpdate::Replaced
+        );
+    }
+
+    #[test]
+    fn test_lines_keep_order() {
+        let mut store = StrokeStore::new();
+
+        store.update("b".into(), line(&[(1.0, 0.0)]));
+        store.update("a".into(), line(&[(2.0, 0.0)]));
+        store.update("b".into(), line(&[(1.0, 0.0), (3.0, 0.0)]));
+
+        let xs: Vec<f64> = store.lines().map(|l| l.points[0].x).collect();
+
+        assert_eq!(xs, vec![1.0, 2.0]);
+    }
+}
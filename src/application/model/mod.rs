@@ -1,10 +1,18 @@
 use tokio::sync::mpsc::Sender;
 
-use crate::config::UnserializableConfig;
+use crate::{
+    config::UnserializableConfig,
+    remarkable::{documents::CachedDocument, recording::SessionRecorder},
+};
 
 pub mod app_controller;
 pub mod app_model;
+pub mod damage;
 pub mod liveview;
+pub mod navigation;
+pub mod replay;
+pub mod scene;
+pub mod viewport;
 
 pub trait AppModelled {
     /// check if the currently loaded config is logged in.
@@ -30,6 +38,23 @@ pub trait AppModelled {
 
     /// Attempts to find a valid session key to use.
     fn get_session_key(&mut self) -> Result<String, String>;
+
+    /// Returns the channel on which the session tokens of newly started live
+    /// sessions are published. The channel can only be taken once.
+    fn take_liveview_channel(&mut self) -> Option<glib::Receiver<String>>;
+
+    /// Connects to the live view of the given session and returns a channel
+    /// containing the raw binary frames sent by the tablet. Everything
+    /// received is passed to the recorder.
+    fn open_liveview(
+        &mut self,
+        session_token: String,
+        recorder: SessionRecorder,
+    ) -> glib::Receiver<Vec<u8>>;
+
+    /// Looks up a document in the cloud and downloads it unless it is cached
+    /// already. The result is published on the returned channel.
+    fn open_document(&mut self, id: String) -> glib::Receiver<Result<CachedDocument, String>>;
 }
 
 pub trait AppControllerable {}
@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+
+/// Decides which page the live view shows. It follows the tablet until the
+/// user browses to another page on their own.
+#[derive(Debug)]
+pub struct PageNavigator {
+    shown: u32,
+    tablet: u32,
+    follow: bool,
+    visited: BTreeSet<u32>,
+}
+
+impl Default for PageNavigator {
+    fn default() -> Self {
+        PageNavigator {
+            shown: 0,
+            tablet: 0,
+            follow: true,
+            visited: [0].into_iter().collect(),
+        }
+    }
+}
+
+impl PageNavigator {
+    pub fn new() -> Self {
+        PageNavigator::default()
+    }
+
+    /// The page currently shown.
+    pub fn shown(&self) -> u32 {
+        self.shown
+    }
+
+    /// The page the tablet is on.
+    pub fn tablet(&self) -> u32 {
+        self.tablet
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.follow
+    }
+
+    /// Records the page the tablet is on. Returns whether the shown page
+    /// changed.
+    pub fn tablet_switched(&mut self, page: u32) -> bool {
+        self.tablet = page;
+        self.visited.insert(page);
+
+        self.follow && self.show(page)
+    }
+
+    /// Whether a previously viewed page comes before the shown one.
+    pub fn has_previous(&self) -> bool {
+        self.visited.range(..self.shown).next_back().is_some()
+    }
+
+    /// Whether a previously viewed page comes after the shown one.
+    pub fn has_next(&self) -> bool {
+        self.visited.range(self.shown + 1..).next().is_some()
+    }
+
+    /// Shows the previously viewed page before the shown one.
+    pub fn previous_page(&mut self) -> bool {
+        match self.visited.range(..self.shown).next_back() {
+            Some(page) => {
+                self.follow = false;
+                self.show(*page)
+            }
+            None => false,
+        }
+    }
+
+    /// Shows the previously viewed page after the shown one.
+    pub fn next_page(&mut self) -> bool {
+        match self.visited.range(self.shown + 1..).next() {
+            Some(page) => {
+                self.follow = false;
+                self.show(*page)
+            }
+            None => false,
+        }
+    }
+
+    /// Follows the tablet again or stops following it.
+    pub fn set_following(&mut self, follow: bool) -> bool {
+        self.follow = follow;
+
+        follow && self.show(self.tablet)
+    }
+
+    /// Forgets all pages, e.g. when another document is opened.
+    pub fn reset(&mut self, page: u32) {
+        self.visited.clear();
+        self.follow = true;
+        self.shown = page;
+        self.tablet_switched(page);
+    }
+
+    fn show(&mut self, page: u32) -> bool {
+        let changed = self.shown != page;
+        self.shown = page;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follows_tablet() {
+        let mut navigator = PageNavigator::new();
+
+        assert!(navigator.tablet_switched(3));
+        assert!(!navigator.tablet_switched(3));
+        assert_eq!(navigator.shown(), 3);
+    }
+
+    #[test]
+    fn test_browsing_stops_following() {
+        let mut navigator = PageNavigator::new();
+        navigator.tablet_switched(2);
+        navigator.tablet_switched(5);
+
+        assert!(navigator.previous_page());
+        assert_eq!(navigator.shown(), 2);
+        assert!(!navigator.is_following());
+
+        assert!(!navigator.tablet_switched(7));
+        assert_eq!(navigator.shown(), 2);
+
+        assert!(navigator.next_page());
+        assert!(navigator.next_page());
+        assert_eq!(navigator.shown(), 7);
+        assert!(!navigator.next_page());
+        assert!(!navigator.has_next());
+        assert!(navigator.has_previous());
+    }
+
+    #[test]
+    fn test_follow_again() {
+        let mut navigator = PageNavigator::new();
+        navigator.tablet_switched(1);
+        navigator.previous_page();
+
+        assert!(navigator.set_following(true));
+        assert_eq!(navigator.shown(), 1);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut navigator = PageNavigator::new();
+        navigator.tablet_switched(4);
+        navigator.previous_page();
+
+        navigator.reset(2);
+
+        assert_eq!(navigator.shown(), 2);
+        assert!(navigator.is_following());
+        assert!(!navigator.previous_page());
+    }
+}
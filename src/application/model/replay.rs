@@ -0,0 +1,376 @@
+use std::{fs::File, io::BufReader, path::Path, time::Duration};
+
+use log::{debug, warn};
+
+use crate::remarkable::recording::{Record, RecordKind, SessionReader};
+
+/// The speeds a recording can be played at, relative to real time.
+pub const SPEEDS: [f64; 4] = [1.0, 2.0, 4.0, 8.0];
+
+/// Plays the frames of a recorded session back at the time they arrived.
+/// The replay only keeps track of time, it is driven by whatever clock the
+/// view uses and returns the frames to feed into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    /// The frames with the time they arrived at
+    frames: Vec<(Duration, Vec<u8>)>,
+    /// The time of the last record
+    duration: Duration,
+    /// The point of the recording reached
+    time: Duration,
+    /// The number of frames fed so far
+    position: usize,
+    speed: f64,
+    playing: bool,
+}
+
+/// The frames to feed into the view to get to another point of the
+/// recording.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feed {
+    /// Whether everything fed before has to be forgotten first, because the
+    /// replay went back
+    pub restart: bool,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl Replay {
+    /// Collects the frames of a recording. Socket events are only used for
+    /// the length of the recording.
+    pub fn from_records<I: IntoIterator<Item = Record>>(records: I) -> Self {
+        let mut frames = vec![];
+        let mut duration = Duration::ZERO;
+
+        for record in records {
+            duration = duration.max(record.timestamp);
+
+            match record.kind {
+                RecordKind::Frame => frames.push((record.timestamp, record.payload)),
+                kind => debug!("Skipping {:?} event at {:?}", kind, record.timestamp),
+            }
+        }
+
+        Replay {
+            frames,
+            duration,
+            time: Duration::ZERO,
+            position: 0,
+            speed: SPEEDS[0],
+            playing: false,
+        }
+    }
+
+    /// Reads the recording at the given path. A recording cut off, e.g.
+    /// because the application crashed, is played up to where it ends.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        debug!("Opening recording {:?}", path);
+
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let reader = SessionReader::new(BufReader::new(file))?;
+
+        let mut records = vec![];
+        for record in reader {
+            match record {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    warn!("Recording {:?} is incomplete: {}", path, e);
+                    break;
+                }
+            }
+        }
+
+        Ok(Replay::from_records(records))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The point of the recording reached.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// The number of frames fed so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.time >= self.duration && self.position == self.frames.len()
+    }
+
+    /// Starts playing, from the start if the end has been reached.
+    pub fn play(&mut self) -> Feed {
+        let feed = if self.is_finished() {
+            self.seek(Duration::ZERO)
+        } else {
+            Feed::default()
+        };
+
+        self.playing = true;
+        feed
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Plays if paused and pauses if playing.
+    pub fn toggle(&mut self) -> Feed {
+        if self.playing {
+            self.pause();
+            Feed::default()
+        } else {
+            self.play()
+        }
+    }
+
+    /// Moves on by the given real time, adjusted to the speed, while
+    /// playing. Stops at the end of the recording.
+    pub fn advance(&mut self, elapsed: Duration) -> Feed {
+        if !self.playing {
+            return Feed::default();
+        }
+
+        let time = self.time + elapsed.mul_f64(self.speed);
+
+        if time >= self.duration {
+            self.playing = false;
+        }
+
+        self.seek(time)
+    }
+
+    /// Jumps to the given point of the recording.
+    pub fn seek(&mut self, time: Duration) -> Feed {
+        self.time = time.min(self.duration);
+
+        let count = self
+            .frames
+            .partition_point(|(timestamp, _)| *timestamp <= self.time);
+        self.feed_until(count)
+    }
+
+    /// Pauses and feeds the next frame.
+    pub fn step_forward(&mut self) -> Feed {
+        self.step_to((self.position + 1).min(self.frames.len()))
+    }
+
+    /// Pauses and goes back to before the last frame fed.
+    pub fn step_back(&mut self) -> Feed {
+        self.step_to(self.position.saturating_sub(1))
+    }
+
+    fn step_to(&mut self, count: usize) -> Feed {
+        self.playing = false;
+
+        let feed = self.feed_until(count);
+        self.time = match count {
+            0 => Duration::ZERO,
+            count => self.frames[count - 1].0,
+        };
+
+        feed
+    }
+
+    /// Returns the frames needed to have fed the given number of frames.
+    fn feed_until(&mut self, count: usize) -> Feed {
+        let restart = count < self.position;
+
+        if restart {
+            self.position = 0;
+        }
+
+        let frames = self.frames[self.position..count]
+            .iter()
+            .map(|(_, frame)| frame.clone())
+            .collect();
+        self.position = count;
+
+        Feed { restart, frames }
+    }
+}
+
+/// Formats a point of a recording as minutes and seconds.
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::remarkable::recording::SessionRecorder;
+
+    fn frame(millis: u64, data: u8) -> Record {
+        Record {
+            kind: RecordKind::Frame,
+            timestamp: Duration::from_millis(millis),
+            payload: vec![data],
+        }
+    }
+
+    fn replay() -> Replay {
+        Replay::from_records(vec![
+            Record {
+                kind: RecordKind::Opened,
+                timestamp: Duration::ZERO,
+                payload: vec![],
+            },
+            frame(100, 1),
+            frame(200, 2),
+            frame(200, 3),
+            frame(1000, 4),
+            Record {
+                kind: RecordKind::Closed,
+                timestamp: Duration::from_millis(1500),
+                payload: vec![],
+            },
+        ])
+    }
+
+    fn frames(feed: &Feed) -> Vec<u8> {
+        feed.frames.iter().map(|frame| frame[0]).collect()
+    }
+
+    #[test]
+    fn test_plays_in_real_time() {
+        let mut replay = replay();
+
+        assert_eq!(replay.len(), 4);
+        assert_eq!(replay.duration(), Duration::from_millis(1500));
+        assert_eq!(replay.advance(Duration::from_millis(500)), Feed::default());
+
+        replay.play();
+        assert_eq!(frames(&replay.advance(Duration::from_millis(150))), vec![1]);
+        assert_eq!(
+            frames(&replay.advance(Duration::from_millis(100))),
+            vec![2, 3]
+        );
+
+        replay.toggle();
+        assert!(replay.advance(Duration::from_secs(10)).frames.is_empty());
+
+        replay.toggle();
+        assert_eq!(frames(&replay.advance(Duration::from_secs(10))), vec![4]);
+        assert!(replay.is_finished());
+        assert!(!replay.is_playing());
+        assert_eq!(replay.time(), replay.duration());
+    }
+
+    #[test]
+    fn test_plays_accelerated() {
+        let mut replay = replay();
+
+        replay.set_speed(SPEEDS[3]);
+        replay.play();
+
+        assert_eq!(
+            frames(&replay.advance(Duration::from_millis(30))),
+            vec![1, 2, 3]
+        );
+        assert_eq!(replay.time(), Duration::from_millis(240));
+    }
+
+    #[test]
+    fn test_seek_back_restarts() {
+        let mut replay = replay();
+
+        assert_eq!(
+            replay.seek(Duration::from_millis(1200)),
+            Feed {
+                restart: false,
+                frames: vec![vec![1], vec![2], vec![3], vec![4]],
+            }
+        );
+
+        let feed = replay.seek(Duration::from_millis(150));
+        assert!(feed.restart);
+        assert_eq!(frames(&feed), vec![1]);
+        assert_eq!(replay.position(), 1);
+
+        let feed = replay.seek(Duration::from_millis(150));
+        assert!(!feed.restart && feed.frames.is_empty());
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut replay = replay();
+        replay.play();
+
+        assert_eq!(frames(&replay.step_forward()), vec![1]);
+        assert!(!replay.is_playing());
+        assert_eq!(frames(&replay.step_forward()), vec![2]);
+        assert_eq!(replay.time(), Duration::from_millis(200));
+
+        let feed = replay.step_back();
+        assert!(feed.restart);
+        assert_eq!(frames(&feed), vec![1]);
+        assert_eq!(replay.time(), Duration::from_millis(100));
+
+        replay.step_back();
+        let feed = replay.step_back();
+        assert!(!feed.restart && feed.frames.is_empty());
+        assert_eq!(replay.time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_play_at_end_starts_over() {
+        let mut replay = replay();
+
+        replay.seek(Duration::from_secs(2));
+        let feed = replay.play();
+
+        assert!(feed.restart);
+        assert!(feed.frames.is_empty());
+        assert!(replay.is_playing());
+    }
+
+    #[test]
+    fn test_open_recording() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let recorder = SessionRecorder::new();
+
+        let path = recorder.start(&directory).unwrap();
+        recorder.opened();
+        recorder.frame(&[7]);
+        recorder.stop();
+
+        let replay = Replay::open(&path).unwrap();
+        assert_eq!(replay.len(), 1);
+
+        fs::write(&path, b"no recording").unwrap();
+        assert!(Replay::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(Duration::from_millis(65_900)), "1:05");
+        assert_eq!(format_time(Duration::ZERO), "0:00");
+    }
+}
@@ -0,0 +1,722 @@
+use std::collections::{BTreeMap, HashMap};
+
+use log::{debug, trace};
+
+use crate::{
+    application::model::liveview::{StrokeStore, StrokeUpdate},
+    remarkable::format::{
+        data::{Line, PenType, Point},
+        linesdata::{LiveSyncMessage, LiveViewUpdate},
+    },
+};
+
+/// Describes what has to be rendered after a message was applied.
+#[derive(Debug, Eq, PartialEq)]
+pub enum SceneChange {
+    /// Nothing visible changed
+    None,
+    /// A line was added or grew. Contains the index of the first new point
+    Draw {
+        page: u32,
+        layer: u32,
+        id: String,
+        from: usize,
+    },
+    /// Lines were removed or changed, so the page has to be drawn again
+    Redraw { page: u32 },
+}
+
+/// The opacity dimmed layers are drawn with.
+const DIMMED_OPACITY: f64 = 0.3;
+
+/// How the lines of a layer are shown in the view.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LayerVisibility {
+    #[default]
+    Visible,
+    /// Drawn translucent, e.g. to keep a sketch visible as a guide
+    Dimmed,
+    Hidden,
+}
+
+/// The lines of a single layer.
+#[derive(Debug, Default)]
+pub struct LayerScene {
+    pub name: Option<String>,
+    pub strokes: StrokeStore,
+}
+
+/// The layers of a single page, ordered from bottom to top.
+#[derive(Debug, Default)]
+pub struct PageScene {
+    pub layers: BTreeMap<u32, LayerScene>,
+}
+
+/// Remembers what an eraser stroke changed so it can be undone.
+#[derive(Debug, Default)]
+struct EraseRecord {
+    page: u32,
+    layer: u32,
+    /// The lines as they were before the eraser touched them
+    removed: Vec<(String, Line)>,
+    /// The pieces the eraser left behind
+    added: Vec<String>,
+    /// The number of points of the eraser path already applied
+    applied: usize,
+}
+
+/// The retained state of all pages shared during a live session. Erasers,
+/// undo and redo on the tablet are applied to it, so the view can always be
+/// rendered from it.
+#[derive(Debug, Default)]
+pub struct Scene {
+    pages: BTreeMap<u32, PageScene>,
+    erasures: HashMap<String, EraseRecord>,
+    /// Applies to the layer of that index on every page
+    visibility: BTreeMap<u32, LayerVisibility>,
+    current_page: u32,
+    pieces: usize,
+}
+
+impl LayerVisibility {
+    pub const ALL: [LayerVisibility; 3] = [
+        LayerVisibility::Visible,
+        LayerVisibility::Dimmed,
+        LayerVisibility::Hidden,
+    ];
+
+    /// The opacity the whole layer is drawn with.
+    pub fn opacity(&self) -> f64 {
+        match self {
+            LayerVisibility::Visible => 1.0,
+            LayerVisibility::Dimmed => DIMMED_OPACITY,
+            LayerVisibility::Hidden => 0.0,
+        }
+    }
+
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            LayerVisibility::Visible => "visible",
+            LayerVisibility::Dimmed => "dimmed",
+            LayerVisibility::Hidden => "hidden",
+        }
+    }
+
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|visibility| visibility.identifier() == identifier)
+            .copied()
+    }
+}
+
+impl LayerScene {
+    /// The name shown for the layer with the given index. Falls back to the
+    /// numbering the tablet uses for unnamed layers.
+    pub fn title(&self, index: u32) -> String {
+        match &self.name {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("Layer {}", index + 1),
+        }
+    }
+}
+
+impl PageScene {
+    /// Iterates the lines of all layers from bottom to top.
+    pub fn lines(&self) -> impl Iterator<Item = &Line> {
+        self.layers.values().flat_map(|layer| layer.strokes.lines())
+    }
+
+    fn layer(&mut self, layer: u32) -> &mut LayerScene {
+        self.layers.entry(layer).or_default()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Scene::default()
+    }
+
+    /// The page the tablet currently shows.
+    pub fn current_page(&self) -> u32 {
+        self.current_page
+    }
+
+    pub fn page(&self, page: u32) -> Option<&PageScene> {
+        self.pages.get(&page)
+    }
+
+    pub fn line(&self, page: u32, layer: u32, id: &str) -> Option<&Line> {
+        self.pages.get(&page)?.layers.get(&layer)?.strokes.get(id)
+    }
+
+    /// The lines drawn on top of the given line: those added to its layer
+    /// after it and those of the layers above that are not hidden.
+    pub fn lines_above(&self, page: u32, layer: u32, id: &str) -> Vec<&Line> {
+        let page = match self.pages.get(&page) {
+            Some(page) => page,
+            None => return vec![],
+        };
+
+        let mut lines = vec![];
+        for (index, scene) in page.layers.range(layer..) {
+            if self.visibility(*index) == LayerVisibility::Hidden {
+                continue;
+            }
+
+            if *index == layer {
+                let entries = scene.strokes.entries();
+                lines.extend(
+                    entries
+                        .skip_while(|(other, _)| other.as_str() != id)
+                        .skip(1)
+                        .map(|(_, line)| line),
+                );
+            } else {
+                lines.extend(scene.strokes.lines());
+            }
+        }
+
+        lines
+    }
+
+    pub fn visibility(&self, layer: u32) -> LayerVisibility {
+        self.visibility.get(&layer).copied().unwrap_or_default()
+    }
+
+    /// Forgets all pages, keeping how layers are shown.
+    pub fn clear(&mut self) {
+        *self = Scene {
+            visibility: std::mem::take(&mut self.visibility),
+            ..Scene::default()
+        };
+    }
+
+    /// Changes how the layer with the given index is shown on all pages.
+    /// Returns whether anything changed.
+    pub fn set_visibility(&mut self, layer: u32, visibility: LayerVisibility) -> bool {
+        let previous = self.visibility.insert(layer, visibility);
+        previous.unwrap_or_default() != visibility
+    }
+
+    /// Updates the scene with a message from the tablet.
+    pub fn apply(&mut self, message: LiveSyncMessage) -> SceneChange {
+        match message {
+            LiveSyncMessage::LineUpdated(update) => {
+                let page = update.page;
+                let switched = self.switch_page(page) != SceneChange::None;
+                let change = self.update_line(update);
+
+                if switched {
+                    SceneChange::Redraw { page }
+                } else {
+                    change
+                }
+            }
+            LiveSyncMessage::LineErased { page, layer, id } => self.erase_line(page, layer, &id),
+            LiveSyncMessage::PageSwitched { page } => self.switch_page(page),
+            LiveSyncMessage::LayerChanged { page, layer, name } => {
+                let scene = self.page_mut(page).layer(layer);
+                if name.is_some() {
+                    scene.name = name;
+                }
+                SceneChange::None
+            }
+            LiveSyncMessage::DocumentOpened { page, .. } => {
+                self.pages.clear();
+                self.erasures.clear();
+                self.visibility.clear();
+                self.current_page = page;
+                SceneChange::Redraw { page }
+            }
+            LiveSyncMessage::PageCleared { page } => {
+                self.pages.remove(&page);
+                SceneChange::Redraw { page }
+            }
+        }
+    }
+
+    fn switch_page(&mut self, page: u32) -> SceneChange {
+        if page == self.current_page {
+            return SceneChange::None;
+        }
+
+        self.current_page = page;
+        SceneChange::Redraw { page }
+    }
+
+    fn page_mut(&mut self, page: u32) -> &mut PageScene {
+        self.pages.entry(page).or_default()
+    }
+
+    fn update_line(&mut self, update: LiveViewUpdate) -> SceneChange {
+        let LiveViewUpdate {
+            page,
+            layer,
+            id,
+            line,
+        } = update;
+
+        match line.brush {
+            PenType::Eraser | PenType::EraseArea | PenType::EraseAll => {
+                self.apply_eraser(page, layer, id, line)
+            }
+            PenType::SelectionBrush => SceneChange::None,
+            _ => {
+                let strokes = &mut self.page_mut(page).layer(layer).strokes;
+
+                match strokes.update(id.clone(), line) {
+                    StrokeUpdate::Added => SceneChange::Draw {
+                        page,
+                        layer,
+                        id,
+                        from: 0,
+                    },
+                    StrokeUpdate::Extended(from) => SceneChange::Draw {
+                        page,
+                        layer,
+                        id,
+                        from,
+                    },
+                    StrokeUpdate::Replaced => SceneChange::Redraw { page },
+                }
+            }
+        }
+    }
+
+    /// Removes a line. If the line was drawn by an eraser, the tablet undid
+    /// it and the erased lines are restored.
+    fn erase_line(&mut self, page: u32, layer: u32, id: &str) -> SceneChange {
+        if let Some(record) = self.erasures.remove(id) {
+            debug!("Undoing eraser {}", id);
+
+            let strokes = &mut self.page_mut(record.page).layer(record.layer).strokes;
+            for piece in &record.added {
+                strokes.remove(piece);
+            }
+            for (id, line) in record.removed {
+                strokes.insert(id, line);
+            }
+
+            return SceneChange::Redraw { page: record.page };
+        }
+
+        let removed = self
+            .pages
+            .get_mut(&page)
+            .and_then(|scene| scene.layers.get_mut(&layer))
+            .and_then(|scene| scene.strokes.remove(id));
+
+        match removed {
+            Some(_) => SceneChange::Redraw { page },
+            None => {
+                trace!("Line {} to erase is unknown", id);
+                SceneChange::None
+            }
+        }
+    }
+
+    fn apply_eraser(&mut self, page: u32, layer: u32, id: String, line: Line) -> SceneChange {
+        let mut record = self.erasures.remove(&id).unwrap_or(EraseRecord {
+            page,
+            layer,
+            ..Default::default()
+        });
+
+        let strokes = &mut self.pages.entry(page).or_default().layer(layer).strokes;
+
+        // a growing eraser only needs to apply the part of its path not seen
+        // before, starting with the last segment
+        let path = &line.points[record.applied.saturating_sub(1).min(line.points.len())..];
+        record.applied = line.points.len();
+
+        let erased = |point: &Point| match line.brush {
+            PenType::EraseAll => true,
+            PenType::EraseArea => inside(point, &line.points),
+            _ => touches(point, path),
+        };
+
+        // only the ids of the lines hit are copied, the lines are then
+        // taken out of the store
+        let hits: Vec<String> = strokes
+            .entries()
+            .filter(|(_, target)| target.points.iter().any(erased))
+            .map(|(target_id, _)| target_id.clone())
+            .collect();
+
+        let changed = !hits.is_empty();
+
+        for target_id in hits {
+            let target = match strokes.remove(&target_id) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            let kept = split(&target.points, |point| !erased(point));
+            let (brush, color) = (target.brush, target.color);
+
+            if record.added.contains(&target_id) {
+                record.added.retain(|other| other != &target_id);
+            } else {
+                record.removed.push((target_id.clone(), target));
+            }
+
+            for points in kept {
+                self.pieces += 1;
+                let piece_id = format!("{}:{}", target_id, self.pieces);
+
+                strokes.insert(
+                    piece_id.clone(),
+                    Line {
+                        points,
+                        brush,
+                        color,
+                    },
+                );
+                record.added.push(piece_id);
+            }
+        }
+
+        self.erasures.insert(id, record);
+
+        if changed {
+            SceneChange::Redraw { page }
+        } else {
+            SceneChange::None
+        }
+    }
+}
+
+/// Splits the points into the runs of consecutive points to keep. Runs of a
+/// single point are dropped.
+fn split<F: Fn(&Point) -> bool>(points: &[Point], keep: F) -> Vec<Vec<Point>> {
+    let mut runs = Vec::new();
+    let mut run: Vec<Point> = Vec::new();
+
+    for point in points {
+        if keep(point) {
+            run.push(point.clone());
+        } else if !run.is_empty() {
+            runs.push(std::mem::take(&mut run));
+        }
+    }
+
+    runs.push(run);
+    runs.retain(|run| run.len() > 1);
+
+    runs
+}
+
+/// Whether a point lies under the tip of an eraser moved along the path.
+fn touches(point: &Point, path: &[Point]) -> bool {
+    match path {
+        [] => false,
+        [tip] => (point.x - tip.x).hypot(point.y - tip.y) <= tip.width / 2.0,
+        _ => path.windows(2).any(|segment| {
+            let (a, b) = (&segment[0], &segment[1]);
+            segment_distance(point, a, b) <= a.width.max(b.width) / 2.0
+        }),
+    }
+}
+
+/// Whether a point lies inside the polygon enclosed by the path.
+fn inside(point: &Point, polygon: &[Point]) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+
+    let mut inside = false;
+    let mut previous = &polygon[polygon.len() - 1];
+
+    for current in polygon {
+        if (current.y > point.y) != (previous.y > point.y) &&
+            point.x <
+                (previous.x - current.x) * (point.y - current.y) / (previous.y - current.y) +
+                    current.x
+        {
+            inside = !inside;
+        }
+        previous = current;
+    }
+
+    inside
+}
+
+fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx * dx + dy * dy;
+
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((p.x - a.x) * dx + (p.y - a.y) * dy) / length).clamp(0.0, 1.0)
+    };
+
+    (p.x - a.x - t * dx).hypot(p.y - a.y - t * dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::format::data::PenColor;
+
+    fn update(id: &str, brush: PenType, points: Vec<Point>) -> LiveSyncMessage {
+        LiveSyncMessage::LineUpdated(LiveViewUpdate {
+            page: 0,
+            layer: 0,
+            id: id.into(),
+            line: Line {
+                points,
+                brush,
+                color: PenColor::BLACK,
+            },
+        })
+    }
+
+    fn horizontal_line() -> Vec<Point> {
+        (0..=10)
+            .map(|i| Point::new(i as f64 * 10.0, 50.0, 2.0, 1.0))
+            .collect()
+    }
+
+    fn line_count(scene: &Scene) -> usize {
+        scene.page(0).map(|page| page.lines().count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_eraser_splits_line() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+
+        let change = scene.apply(update(
+            "eraser",
+            PenType::Eraser,
+            vec![
+                Point::new(50.0, 0.0, 12.0, 1.0),
+                Point::new(50.0, 100.0, 12.0, 1.0),
+            ],
+        ));
+
+        assert_eq!(change, SceneChange::Redraw { page: 0 });
+        assert_eq!(line_count(&scene), 2);
+
+        let lengths: Vec<usize> = scene
+            .page(0)
+            .unwrap()
+            .lines()
+            .map(|l| l.points.len())
+            .collect();
+        assert_eq!(lengths, vec![5, 5]);
+    }
+
+    #[test]
+    fn test_clear_keeps_visibility() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+        scene.set_visibility(0, LayerVisibility::Dimmed);
+
+        scene.clear();
+
+        assert_eq!(line_count(&scene), 0);
+        assert_eq!(scene.visibility(0), LayerVisibility::Dimmed);
+    }
+
+    #[test]
+    fn test_undo_eraser_restores_line() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+        scene.apply(update(
+            "eraser",
+            PenType::Eraser,
+            vec![
+                Point::new(50.0, 0.0, 12.0, 1.0),
+                Point::new(50.0, 100.0, 12.0, 1.0),
+            ],
+        ));
+
+        let change = scene.apply(LiveSyncMessage::LineErased {
+            page: 0,
+            layer: 0,
+            id: "eraser".into(),
+        });
+
+        assert_eq!(change, SceneChange::Redraw { page: 0 });
+        assert_eq!(line_count(&scene), 1);
+        assert_eq!(
+            scene.page(0).unwrap().lines().next().unwrap().points.len(),
+            11
+        );
+    }
+
+    #[test]
+    fn test_growing_eraser_keeps_original_for_undo() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+        scene.apply(update(
+            "eraser",
+            PenType::Eraser,
+            vec![Point::new(30.0, 50.0, 6.0, 1.0)],
+        ));
+        scene.apply(update(
+            "eraser",
+            PenType::Eraser,
+            vec![
+                Point::new(30.0, 50.0, 6.0, 1.0),
+                Point::new(70.0, 50.0, 6.0, 1.0),
+            ],
+        ));
+
+        assert_eq!(line_count(&scene), 2);
+
+        scene.apply(LiveSyncMessage::LineErased {
+            page: 0,
+            layer: 0,
+            id: "eraser".into(),
+        });
+
+        assert_eq!(line_count(&scene), 1);
+    }
+
+    #[test]
+    fn test_area_eraser_removes_inside() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+
+        scene.apply(update(
+            "area",
+            PenType::EraseArea,
+            vec![
+                Point::new(-5.0, 0.0, 2.0, 1.0),
+                Point::new(45.0, 0.0, 2.0, 1.0),
+                Point::new(45.0, 100.0, 2.0, 1.0),
+                Point::new(-5.0, 100.0, 2.0, 1.0),
+            ],
+        ));
+
+        let lines: Vec<&Line> = scene.page(0).unwrap().lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].points[0].x, 50.0);
+    }
+
+    #[test]
+    fn test_erase_all_and_removed_lines() {
+        let mut scene = Scene::new();
+        scene.apply(update("a", PenType::Fineliner, horizontal_line()));
+        scene.apply(update("b", PenType::Fineliner, horizontal_line()));
+
+        scene.apply(LiveSyncMessage::LineErased {
+            page: 0,
+            layer: 0,
+            id: "a".into(),
+        });
+        assert_eq!(line_count(&scene), 1);
+
+        scene.apply(update("clear", PenType::EraseAll, vec![]));
+        assert_eq!(line_count(&scene), 0);
+
+        scene.apply(LiveSyncMessage::LineErased {
+            page: 0,
+            layer: 0,
+            id: "clear".into(),
+        });
+        assert_eq!(line_count(&scene), 1);
+    }
+
+    #[test]
+    fn test_lines_are_drawn_incrementally() {
+        let mut scene = Scene::new();
+
+        assert_eq!(
+            scene.apply(update(
+                "line",
+                PenType::Fineliner,
+                horizontal_line()[..3].to_vec()
+            )),
+            SceneChange::Draw {
+                page: 0,
+                layer: 0,
+                id: "line".into(),
+                from: 0
+            }
+        );
+        assert_eq!(
+            scene.apply(update("line", PenType::Fineliner, horizontal_line())),
+            SceneChange::Draw {
+                page: 0,
+                layer: 0,
+                id: "line".into(),
+                from: 3
+            }
+        );
+        assert_eq!(
+            scene.apply(LiveSyncMessage::PageSwitched { page: 2 }),
+            SceneChange::Redraw { page: 2 }
+        );
+        assert_eq!(
+            scene.apply(update("other", PenType::Fineliner, horizontal_line())),
+            SceneChange::Redraw { page: 0 }
+        );
+        assert_eq!(scene.current_page(), 0);
+    }
+
+    #[test]
+    fn test_lines_above() {
+        let mut scene = Scene::new();
+        scene.apply(update("first", PenType::Fineliner, horizontal_line()));
+        scene.apply(update("second", PenType::Fineliner, horizontal_line()));
+        scene.apply(LiveSyncMessage::LineUpdated(LiveViewUpdate {
+            page: 0,
+            layer: 1,
+            id: "top".into(),
+            line: Line {
+                points: horizontal_line(),
+                brush: PenType::Marker,
+                color: PenColor::BLACK,
+            },
+        }));
+
+        assert_eq!(scene.lines_above(0, 0, "first").len(), 2);
+        assert_eq!(scene.lines_above(0, 0, "second").len(), 1);
+        assert!(scene.lines_above(0, 1, "top").is_empty());
+        assert!(scene.lines_above(1, 0, "first").is_empty());
+
+        scene.set_visibility(1, LayerVisibility::Hidden);
+        assert!(scene.lines_above(0, 0, "second").is_empty());
+    }
+
+    #[test]
+    fn test_layer_names_and_visibility() {
+        let mut scene = Scene::new();
+        scene.apply(update("line", PenType::Fineliner, horizontal_line()));
+        scene.apply(LiveSyncMessage::LayerChanged {
+            page: 0,
+            layer: 1,
+            name: Some("Clean".into()),
+        });
+
+        let titles: Vec<String> = scene
+            .page(0)
+            .unwrap()
+            .layers
+            .iter()
+            .map(|(index, layer)| layer.title(*index))
+            .collect();
+        assert_eq!(titles, vec!["Layer 1", "Clean"]);
+
+        assert!(scene.set_visibility(0, LayerVisibility::Hidden));
+        assert!(!scene.set_visibility(0, LayerVisibility::Hidden));
+        assert!(!scene.set_visibility(1, LayerVisibility::Visible));
+        assert_eq!(scene.visibility(0), LayerVisibility::Hidden);
+        assert_eq!(
+            LayerVisibility::from_identifier("dimmed"),
+            Some(LayerVisibility::Dimmed)
+        );
+
+        scene.apply(LiveSyncMessage::DocumentOpened {
+            id: "other".into(),
+            page: 0,
+            orientation: Default::default(),
+        });
+        assert_eq!(scene.visibility(0), LayerVisibility::Visible);
+    }
+}
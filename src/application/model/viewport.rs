@@ -0,0 +1,298 @@
+use crate::remarkable::format::device::Transform;
+
+/// The smallest and largest factor a page can be zoomed to.
+const MIN_ZOOM: f64 = 0.05;
+const MAX_ZOOM: f64 = 8.0;
+
+/// The factor a single zoom step changes the zoom by.
+const ZOOM_STEP: f64 = 1.25;
+
+/// How the page is sized to the view.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum FitMode {
+    /// The user chose the zoom and position
+    Free,
+    /// The page fills the width of the view and can be scrolled vertically
+    Width,
+    /// The whole page is visible
+    #[default]
+    Page,
+}
+
+/// Places a page in the live view. Keeps track of zoom, pan and rotation
+/// and derives the transform from page to view coordinates from them.
+#[derive(Debug, Clone)]
+pub struct Viewport {
+    fit: FitMode,
+    /// The zoom used in [FitMode::Free]
+    zoom: f64,
+    /// The position of the top left corner of the page in the view
+    offset: (f64, f64),
+    /// The number of clockwise quarter turns the page is rotated by
+    turns: u8,
+    view: (f64, f64),
+    /// The size of the page before it is rotated
+    page: (f64, f64),
+}
+
+impl Viewport {
+    /// Creates a viewport showing a page of the given size in a view of the
+    /// same size.
+    pub fn new(page: (f64, f64)) -> Self {
+        Viewport {
+            fit: FitMode::default(),
+            zoom: 1.0,
+            offset: (0.0, 0.0),
+            turns: 0,
+            view: page,
+            page,
+        }
+    }
+
+    pub fn fit(&self) -> FitMode {
+        self.fit
+    }
+
+    pub fn turns(&self) -> u8 {
+        self.turns
+    }
+
+    pub fn set_page_size(&mut self, width: f64, height: f64) {
+        self.page = (width, height);
+    }
+
+    /// Updates the size of the view. Returns whether it changed.
+    pub fn set_view_size(&mut self, width: f64, height: f64) -> bool {
+        let changed = self.view != (width, height);
+        self.view = (width, height);
+        changed
+    }
+
+    /// The size of the page as it is shown, before zooming.
+    fn rotated_page(&self) -> (f64, f64) {
+        let (width, height) = self.page;
+
+        match self.turns {
+            1 | 3 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    /// The factor page units are scaled by.
+    pub fn scale(&self) -> f64 {
+        let (width, height) = self.rotated_page();
+        let (view_width, view_height) = self.view;
+
+        let scale = match self.fit {
+            FitMode::Free => self.zoom,
+            FitMode::Width => view_width / width,
+            FitMode::Page => (view_width / width).min(view_height / height),
+        };
+
+        scale.clamp(MIN_ZOOM, MAX_ZOOM)
+    }
+
+    /// The position of the top left corner of the page in the view. Pages
+    /// smaller than the view are centered, larger ones can't be moved out of
+    /// it.
+    pub fn offset(&self) -> (f64, f64) {
+        let scale = self.scale();
+        let (width, height) = self.rotated_page();
+        let (view_width, view_height) = self.view;
+
+        let (x, y) = match self.fit {
+            FitMode::Page => (0.0, 0.0),
+            FitMode::Width => (0.0, self.offset.1),
+            FitMode::Free => self.offset,
+        };
+
+        (
+            clamp_axis(x, width * scale, view_width),
+            clamp_axis(y, height * scale, view_height),
+        )
+    }
+
+    /// The area the page covers in the view as (x, y, width, height).
+    pub fn page_rect(&self) -> (f64, f64, f64, f64) {
+        let scale = self.scale();
+        let (width, height) = self.rotated_page();
+        let (x, y) = self.offset();
+
+        (x, y, width * scale, height * scale)
+    }
+
+    /// Maps the scaled page into the view.
+    pub fn view_transform(&self) -> Transform {
+        let scale = self.scale();
+        let (width, height) = self.page;
+        let (x, y) = self.offset();
+
+        Transform::quarter_turns(self.turns, width * scale, height * scale)
+            .then(&Transform::translation(x, y))
+    }
+
+    /// Zooms by the given factor, keeping the given view position in place.
+    pub fn zoom_at(&mut self, factor: f64, (x, y): (f64, f64)) {
+        let old = self.scale();
+        let (offset_x, offset_y) = self.offset();
+        let new = (old * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+
+        self.offset = (
+            x - (x - offset_x) * new / old,
+            y - (y - offset_y) * new / old,
+        );
+        self.zoom = new;
+        self.fit = FitMode::Free;
+    }
+
+    /// Zooms by the given number of steps, e.g. scroll wheel notches.
+    /// Negative steps zoom out.
+    pub fn zoom_steps(&mut self, steps: f64, at: (f64, f64)) {
+        self.zoom_at(ZOOM_STEP.powf(steps), at);
+    }
+
+    /// Zooms in by one step around the center of the view.
+    pub fn zoom_in(&mut self) {
+        self.zoom_at(ZOOM_STEP, self.center());
+    }
+
+    /// Zooms out by one step around the center of the view.
+    pub fn zoom_out(&mut self) {
+        self.zoom_at(1.0 / ZOOM_STEP, self.center());
+    }
+
+    /// Moves the page by the given distance in view coordinates.
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let (x, y) = self.offset();
+
+        match self.fit {
+            // the whole page is visible already
+            FitMode::Page => {}
+            FitMode::Width => self.offset = (x, y + dy),
+            FitMode::Free => self.offset = (x + dx, y + dy),
+        }
+    }
+
+    /// Rotates the page by a quarter turn.
+    pub fn rotate(&mut self, clockwise: bool) {
+        self.turns = (self.turns + if clockwise { 1 } else { 3 }) % 4;
+    }
+
+    pub fn set_fit(&mut self, fit: FitMode) {
+        match fit {
+            FitMode::Free => {
+                self.zoom = self.scale();
+                self.offset = self.offset();
+            }
+            // starts at the top of the page
+            FitMode::Width => self.offset = (0.0, 0.0),
+            FitMode::Page => {}
+        }
+
+        self.fit = fit;
+    }
+
+    /// The center of the view.
+    pub fn center(&self) -> (f64, f64) {
+        (self.view.0 / 2.0, self.view.1 / 2.0)
+    }
+}
+
+/// Centers content smaller than the available space and keeps larger
+/// content covering it.
+fn clamp_axis(position: f64, size: f64, available: f64) -> f64 {
+    if size <= available {
+        (available - size) / 2.0
+    } else {
+        position.max(available - size).min(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::abs;
+
+    use super::*;
+
+    fn assert_close((x, y): (f64, f64), (ex, ey): (f64, f64)) {
+        assert!(
+            abs(x - ex) < 1e-9 && abs(y - ey) < 1e-9,
+            "Expected ({}, {}), got ({}, {})",
+            ex,
+            ey,
+            x,
+            y
+        );
+    }
+
+    #[test]
+    fn test_fit_page_centers() {
+        let mut viewport = Viewport::new((100.0, 200.0));
+        viewport.set_view_size(400.0, 200.0);
+
+        assert_eq!(viewport.scale(), 1.0);
+        assert_eq!(viewport.page_rect(), (150.0, 0.0, 100.0, 200.0));
+
+        viewport.pan(50.0, 50.0);
+        assert_eq!(viewport.offset(), (150.0, 0.0));
+    }
+
+    #[test]
+    fn test_fit_width_scrolls() {
+        let mut viewport = Viewport::new((100.0, 200.0));
+        viewport.set_view_size(200.0, 100.0);
+        viewport.set_fit(FitMode::Width);
+
+        assert_eq!(viewport.scale(), 2.0);
+        assert_eq!(viewport.offset(), (0.0, 0.0));
+
+        viewport.pan(30.0, -120.0);
+        assert_eq!(viewport.offset(), (0.0, -120.0));
+
+        // can't scroll past the bottom of the page
+        viewport.pan(0.0, -1000.0);
+        assert_eq!(viewport.offset(), (0.0, -300.0));
+    }
+
+    #[test]
+    fn test_zoom_keeps_point_in_place() {
+        let mut viewport = Viewport::new((100.0, 100.0));
+        viewport.set_view_size(100.0, 100.0);
+
+        viewport.zoom_at(2.0, (25.0, 25.0));
+
+        assert_eq!(viewport.fit(), FitMode::Free);
+        assert_eq!(viewport.scale(), 2.0);
+        let inverse = viewport.view_transform().invert().unwrap();
+        assert_close(inverse.apply(25.0, 25.0), (50.0, 50.0));
+    }
+
+    #[test]
+    fn test_zoom_is_limited() {
+        let mut viewport = Viewport::new((100.0, 100.0));
+
+        for _ in 0..100 {
+            viewport.zoom_in();
+        }
+
+        assert_eq!(viewport.scale(), MAX_ZOOM);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut viewport = Viewport::new((100.0, 200.0));
+        viewport.set_view_size(200.0, 100.0);
+
+        viewport.rotate(true);
+
+        assert_eq!(viewport.turns(), 1);
+        assert_eq!(viewport.scale(), 1.0);
+        assert_eq!(viewport.page_rect(), (0.0, 0.0, 200.0, 100.0));
+        // the top left corner of the page ends up top right
+        assert_close(viewport.view_transform().apply(0.0, 0.0), (200.0, 0.0));
+
+        viewport.rotate(false);
+        viewport.rotate(false);
+        assert_eq!(viewport.turns(), 3);
+    }
+}
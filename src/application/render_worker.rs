@@ -0,0 +1,666 @@
+//! Renders the pages of the live view on a thread of its own, so lines
+//! arriving quickly or a large redraw never keep the main loop from
+//! handling input. The thread keeps its own copy of the scene, fed the same
+//! messages as the window, and sends the pixels that changed back to the
+//! main loop, where they are copied into the image the window shows.
+
+use std::{path::PathBuf, sync::mpsc, thread};
+
+use cairo::{Context, Error, Format, ImageSurface};
+use log::{debug, trace, warn};
+
+use crate::{
+    application::{
+        main_channel,
+        model::{
+            damage::{Damage, FrameChanges, Rect},
+            scene::{LayerVisibility, Scene, SceneChange},
+        },
+    },
+    config::config_io::resolve_template_path,
+    remarkable::format::{
+        content::DocumentContent,
+        device::{DeviceProfile, Orientation, Transform},
+        linesdata::LiveSyncMessage,
+        scheme::ColorScheme,
+        template::PageTemplates,
+    },
+    render::{
+        document::SourceDocument, template::TemplateLibrary, InkStyle, OutlineRenderer,
+        StrokeRenderer,
+    },
+};
+
+/// The colour around the page in the light scheme.
+pub const BACKGROUND: (f64, f64, f64) = (0.85, 0.85, 0.85);
+
+/// The number of pages whose canvases are kept, so paging back and forth
+/// does not render them again. Other pages are rendered from the scene when
+/// they are shown.
+const KEPT_CANVASES: usize = 3;
+
+/// How pages are placed in the view and what they look like.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub orientation: Orientation,
+    /// The size of a tablet unit in the view
+    pub scale: f64,
+    /// Places the scaled page in the view
+    pub view: Transform,
+    /// The area covered by the page
+    pub page_rect: Rect,
+    /// The size of the view
+    pub size: (f64, f64),
+    /// The number of device pixels per view unit
+    pub device_scale: f64,
+    pub ink: InkStyle,
+    pub scheme: ColorScheme,
+}
+
+/// Pixels of the view in ARGB32, row after row without padding.
+#[derive(Debug)]
+pub struct Pixels {
+    /// The position in device pixels
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    device_scale: f64,
+    data: Vec<u8>,
+}
+
+/// What the thread rendered since its last frame.
+#[derive(Debug)]
+pub struct RenderedFrame {
+    /// The part of the view that changed
+    pub damage: Damage,
+    /// The pixels of the damaged area, or of the whole view if the damage is
+    /// full. Missing if the page shown could not be rendered.
+    pub pixels: Option<Pixels>,
+}
+
+/// The main loop's end of the render thread. The thread stops once this is
+/// dropped or nobody receives its frames anymore.
+pub struct RenderWorker {
+    jobs: mpsc::Sender<RenderJob>,
+}
+
+/// What the render thread is asked to do, in order.
+#[derive(Debug)]
+enum RenderJob {
+    Apply(LiveSyncMessage),
+    Place(Placement),
+    Show(u32),
+    SetVisibility(u32, LayerVisibility),
+    SetTemplates(PageTemplates),
+    /// Opens the PDF at the path to draw below the lines
+    SetSource(Option<(PathBuf, DocumentContent)>),
+    Restart,
+    /// Sends the whole view again
+    Refresh,
+    Stop,
+}
+
+/// The state of the render thread. Renderers, templates and the source
+/// document are created on the thread, as cairo and poppler objects cannot
+/// be sent between threads.
+struct Worker {
+    profile: DeviceProfile,
+    placement: Placement,
+    scene: Scene,
+    renderer: OutlineRenderer,
+    templates: PageTemplates,
+    template_library: TemplateLibrary,
+    source: Option<SourceDocument>,
+    canvases: PageCanvases,
+    /// The changes to render with the next frame
+    changes: FrameChanges,
+    /// The part of the view to send with the next frame
+    damage: Damage,
+    /// The page shown in the window
+    shown: u32,
+}
+
+/// The rendered canvases of the pages shown last. A canvas is rendered from
+/// the scene when its page is shown and then kept up to date as lines
+/// arrive, until it is dropped for a page shown later. Canvases cover the
+/// visible part of the view in device pixels, so lines are rendered crisp
+/// at any zoom.
+struct PageCanvases {
+    /// The canvases by page, the one shown last at the end
+    surfaces: Vec<(u32, ImageSurface)>,
+    /// The size of the view
+    size: (f64, f64),
+    /// The number of device pixels per view unit
+    device_scale: f64,
+    /// The area covered by the page
+    page_rect: Rect,
+}
+
+impl Pixels {
+    /// Copies the given part of an image, in device pixels, or all of it.
+    fn copy(surface: &ImageSurface, device_scale: f64, area: Option<Rect>) -> Option<Self> {
+        let (width, height) = (surface.width(), surface.height());
+
+        let (x, y, w, h) = match area {
+            Some((x, y, w, h)) => Damage::pixels((
+                x * device_scale,
+                y * device_scale,
+                w * device_scale,
+                h * device_scale,
+            )),
+            None => (0, 0, width, height),
+        };
+
+        let (left, top) = (x.clamp(0, width), y.clamp(0, height));
+        let (right, bottom) = ((x + w).clamp(0, width), (y + h).clamp(0, height));
+        if right <= left || bottom <= top {
+            return None;
+        }
+
+        let row = (right - left) as usize * 4;
+        let mut data = Vec::with_capacity(row * (bottom - top) as usize);
+        let stride = surface.stride() as usize;
+
+        let copied = surface.with_data(|source| {
+            for y in top..bottom {
+                let start = y as usize * stride + left as usize * 4;
+                data.extend_from_slice(&source[start..start + row]);
+            }
+        });
+
+        if let Err(e) = copied {
+            warn!("Failed to read the rendered view: {}", e);
+            return None;
+        }
+
+        Some(Pixels {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+            device_scale,
+            data,
+        })
+    }
+
+    /// Turns pixels of the whole view into an image.
+    pub fn into_surface(self) -> Result<ImageSurface, Error> {
+        let surface = ImageSurface::create_for_data(
+            self.data,
+            Format::ARgb32,
+            self.width,
+            self.height,
+            self.width * 4,
+        )?;
+        surface.set_device_scale(self.device_scale, self.device_scale);
+
+        Ok(surface)
+    }
+
+    /// Copies the pixels into an image of the whole view.
+    pub fn copy_into(&self, surface: &mut ImageSurface) -> Result<(), String> {
+        if self.x + self.width > surface.width() || self.y + self.height > surface.height() {
+            return Err("The pixels lie outside of the view".to_string());
+        }
+
+        let row = self.width as usize * 4;
+        let stride = surface.stride() as usize;
+        let mut data = surface.data().map_err(|e| e.to_string())?;
+
+        for (index, pixels) in self.data.chunks_exact(row).enumerate() {
+            let start = (self.y as usize + index) * stride + self.x as usize * 4;
+            data[start..start + row].copy_from_slice(pixels);
+        }
+
+        Ok(())
+    }
+}
+
+impl RenderWorker {
+    /// Starts rendering the pages of the given tablet the given way. The
+    /// rendered frames are published on the returned channel.
+    pub fn spawn(
+        profile: DeviceProfile,
+        placement: Placement,
+    ) -> Result<(Self, glib::Receiver<RenderedFrame>), String> {
+        let (jobs, receiver) = mpsc::channel();
+        let (sender, frames) = main_channel();
+
+        thread::Builder::new()
+            .name("live view renderer".to_string())
+            .spawn(move || Worker::new(profile, placement).run(receiver, sender))
+            .map_err(|e| e.to_string())?;
+
+        Ok((RenderWorker { jobs }, frames))
+    }
+
+    /// Applies a message to the scene and renders what changed.
+    pub fn apply(&self, message: LiveSyncMessage) {
+        self.send(RenderJob::Apply(message));
+    }
+
+    /// Places the pages differently. All pages are rendered again.
+    pub fn place(&self, placement: Placement) {
+        self.send(RenderJob::Place(placement));
+    }
+
+    /// Sends the given page with the next frame.
+    pub fn show(&self, page: u32) {
+        self.send(RenderJob::Show(page));
+    }
+
+    pub fn set_visibility(&self, layer: u32, visibility: LayerVisibility) {
+        self.send(RenderJob::SetVisibility(layer, visibility));
+    }
+
+    pub fn set_templates(&self, templates: PageTemplates) {
+        self.send(RenderJob::SetTemplates(templates));
+    }
+
+    /// Draws the PDF at the given path below the lines, placed according to
+    /// the content of its document.
+    pub fn set_source(&self, source: Option<(PathBuf, DocumentContent)>) {
+        self.send(RenderJob::SetSource(source));
+    }
+
+    /// Forgets the scene and the source document.
+    pub fn restart(&self) {
+        self.send(RenderJob::Restart);
+    }
+
+    /// Sends the whole view again, e.g. when the pixels sent before could not
+    /// be shown.
+    pub fn refresh(&self) {
+        self.send(RenderJob::Refresh);
+    }
+
+    /// Stops the thread once it has performed the jobs sent before.
+    pub fn stop(&self) {
+        self.send(RenderJob::Stop);
+    }
+
+    fn send(&self, job: RenderJob) {
+        if let Err(e) = self.jobs.send(job) {
+            warn!("The render thread has stopped, dropping {:?}", e.0);
+        }
+    }
+}
+
+impl Worker {
+    fn new(profile: DeviceProfile, placement: Placement) -> Self {
+        let template_library = match resolve_template_path() {
+            Ok(path) => TemplateLibrary::new(path),
+            Err(e) => {
+                warn!("Only using bundled templates: {}", e);
+                TemplateLibrary::bundled()
+            }
+        };
+
+        let mut worker = Worker {
+            profile,
+            placement,
+            scene: Scene::new(),
+            renderer: OutlineRenderer::new(profile, placement.orientation, placement.scale),
+            templates: PageTemplates::default(),
+            template_library,
+            source: None,
+            canvases: PageCanvases::new(&placement),
+            changes: FrameChanges::default(),
+            damage: Damage::default(),
+            shown: 0,
+        };
+        worker.place_pages();
+
+        worker
+    }
+
+    /// Performs jobs until the window is closed. Jobs arriving while a frame
+    /// is rendered are performed together and rendered with the next one.
+    fn run(mut self, jobs: mpsc::Receiver<RenderJob>, frames: glib::Sender<RenderedFrame>) {
+        while let Ok(job) = jobs.recv() {
+            let mut running = self.perform(job);
+            for job in jobs.try_iter() {
+                running = running && self.perform(job);
+            }
+
+            if !running {
+                break;
+            }
+
+            if let Some(frame) = self.render_frame() {
+                if frames.send(frame).is_err() {
+                    break;
+                }
+            }
+        }
+
+        debug!("Live view closed, stopping the render thread");
+    }
+
+    /// Performs a job and returns whether to go on.
+    fn perform(&mut self, job: RenderJob) -> bool {
+        match job {
+            RenderJob::Apply(message) => self.handle(message),
+            RenderJob::Place(placement) => {
+                self.placement = placement;
+                self.place_pages();
+            }
+            RenderJob::Show(page) => {
+                if page != self.shown {
+                    self.shown = page;
+                    self.damage.add_full();
+                }
+            }
+            RenderJob::SetVisibility(layer, visibility) => {
+                if self.scene.set_visibility(layer, visibility) {
+                    self.canvases.clear();
+                    self.damage.add_full();
+                }
+            }
+            RenderJob::SetTemplates(templates) => {
+                self.templates = templates;
+                self.canvases.clear();
+                self.damage.add_full();
+            }
+            RenderJob::SetSource(source) => {
+                self.source = source.and_then(|(path, content)| {
+                    SourceDocument::open(&path, content)
+                        .map_err(|e| warn!("Failed to open the source {:?}: {}", path, e))
+                        .ok()
+                });
+                self.place_pages();
+            }
+            RenderJob::Restart => {
+                self.scene.clear();
+                self.changes = FrameChanges::default();
+                self.source = None;
+                self.place_pages();
+            }
+            RenderJob::Refresh => self.damage.add_full(),
+            RenderJob::Stop => return false,
+        }
+
+        true
+    }
+
+    /// Renders pages the way they are placed.
+    fn place_pages(&mut self) {
+        let placement = &self.placement;
+
+        self.renderer = OutlineRenderer::new(self.profile, placement.orientation, placement.scale)
+            .with_view(placement.view)
+            .with_ink(placement.ink)
+            .with_scheme(placement.scheme)
+            .with_templates(self.template_library.clone())
+            .with_source(self.source.clone());
+
+        self.canvases.configure(placement);
+        self.damage.add_full();
+    }
+
+    /// Applies a message to the scene and records what has to be rendered
+    /// with the next frame.
+    fn handle(&mut self, message: LiveSyncMessage) {
+        match self.scene.apply(message) {
+            SceneChange::Draw {
+                page,
+                layer,
+                id,
+                from,
+            } => match self.scene.visibility(layer) {
+                LayerVisibility::Visible => self.changes.draw(page, layer, &id, from),
+                // dimmed lines can only be drawn as part of their layer
+                LayerVisibility::Dimmed => self.changes.redraw(page),
+                LayerVisibility::Hidden => {}
+            },
+            SceneChange::Redraw { page } => self.changes.redraw(page),
+            SceneChange::None => {}
+        }
+    }
+
+    /// Renders the changes collected since the last frame onto the canvases
+    /// and returns the pixels of the shown page that changed. The shown page
+    /// is rendered here if it has no canvas yet.
+    fn render_frame(&mut self) -> Option<RenderedFrame> {
+        let changes = self.changes.take();
+        let shown = self.shown;
+
+        for page in changes.pages() {
+            trace!("Redrawing page {}", page);
+            self.canvases
+                .redraw(page, &self.scene, &self.templates, &self.renderer);
+
+            if page == shown {
+                self.damage.add_full();
+            }
+        }
+
+        for (page, layer, id, from) in changes.lines() {
+            let bounds = self.canvases.draw(
+                (page, layer, id, from),
+                &self.scene,
+                &self.templates,
+                &self.renderer,
+            );
+
+            if let Some(bounds) = bounds.filter(|_| page == shown) {
+                self.damage.add(bounds);
+            }
+        }
+
+        if self
+            .canvases
+            .prepare(shown, &self.scene, &self.templates, &self.renderer)
+        {
+            self.damage.add_full();
+        }
+
+        let damage = self.damage.take();
+        let area = match damage {
+            Damage::None => return None,
+            Damage::Area(rect) => Some(rect),
+            Damage::Full => None,
+        };
+
+        let device_scale = self.canvases.device_scale;
+        let pixels = self
+            .canvases
+            .get(shown)
+            .and_then(|surface| Pixels::copy(surface, device_scale, area));
+
+        Some(RenderedFrame { damage, pixels })
+    }
+}
+
+impl PageCanvases {
+    fn new(placement: &Placement) -> Self {
+        PageCanvases {
+            surfaces: Vec::new(),
+            size: placement.size,
+            device_scale: placement.device_scale,
+            page_rect: placement.page_rect,
+        }
+    }
+
+    /// Places the page differently. All canvases are rendered again.
+    fn configure(&mut self, placement: &Placement) {
+        self.size = placement.size;
+        self.device_scale = placement.device_scale;
+        self.page_rect = placement.page_rect;
+        self.clear();
+    }
+
+    /// Renders the canvas of a page from the scene if there is none yet.
+    /// Returns whether it did.
+    fn prepare(
+        &mut self,
+        page: u32,
+        scene: &Scene,
+        templates: &PageTemplates,
+        renderer: &OutlineRenderer,
+    ) -> bool {
+        if let Some(index) = self.surfaces.iter().position(|(other, _)| *other == page) {
+            let canvas = self.surfaces.remove(index);
+            self.surfaces.push(canvas);
+            return false;
+        }
+
+        let (width, height) = self.size;
+        let surface = match ImageSurface::create(
+            Format::ARgb32,
+            (width * self.device_scale).ceil() as i32,
+            (height * self.device_scale).ceil() as i32,
+        ) {
+            Ok(surface) => surface,
+            Err(e) => {
+                warn!("Failed to create canvas for page {}: {}", page, e);
+                return false;
+            }
+        };
+        surface.set_device_scale(self.device_scale, self.device_scale);
+
+        if let Err(e) = self.render_area(&surface, page, scene, templates, renderer, None) {
+            warn!("Failed to render page {}: {}", page, e);
+        }
+        if self.surfaces.len() >= KEPT_CANVASES {
+            let (dropped, _) = self.surfaces.remove(0);
+            trace!("Dropping the canvas of page {}", dropped);
+        }
+        self.surfaces.push((page, surface));
+
+        true
+    }
+
+    /// Draws the new points of a line onto the canvas of its page and
+    /// returns the area they cover. Pages without a canvas are rendered
+    /// completely once they are shown.
+    fn draw(
+        &self,
+        (page, layer, id, from): (u32, u32, &str, usize),
+        scene: &Scene,
+        templates: &PageTemplates,
+        renderer: &OutlineRenderer,
+    ) -> Option<Rect> {
+        let surface = self.get(page)?;
+        let line = scene.line(page, layer, id)?;
+        let area = renderer.line_bounds(line, from)?;
+
+        trace!("Drawing {} points of {}", line.points.len() - from, id);
+
+        // the new points must not cover the lines above them, and
+        // translucent ink would add up with the points drawn before, so
+        // then the area is composed again in layer order
+        let covered = scene
+            .lines_above(page, layer, id)
+            .into_iter()
+            .any(|other| renderer.reaches(other, area));
+
+        if covered || (from > 0 && !renderer.draws_incrementally(line)) {
+            if let Err(e) = self.render_area(surface, page, scene, templates, renderer, Some(area))
+            {
+                warn!("Failed to render page {}: {}", page, e);
+            }
+            return Some(area);
+        }
+
+        self.page_context(surface)
+            .and_then(|context| renderer.draw_line_bounds(&context, line, from))
+            .unwrap_or_else(|e| {
+                warn!("Failed to draw {}: {}", id, e);
+                // the area is shown again in case the points were partly drawn
+                Some(area)
+            })
+    }
+
+    /// Renders the page again if it has a canvas.
+    fn redraw(
+        &self,
+        page: u32,
+        scene: &Scene,
+        templates: &PageTemplates,
+        renderer: &OutlineRenderer,
+    ) {
+        if let Some(surface) = self.get(page) {
+            if let Err(e) = self.render_area(surface, page, scene, templates, renderer, None) {
+                warn!("Failed to render page {}: {}", page, e);
+            }
+        }
+    }
+
+    fn get(&self, page: u32) -> Option<&ImageSurface> {
+        self.surfaces
+            .iter()
+            .find(|(other, _)| *other == page)
+            .map(|(_, surface)| surface)
+    }
+
+    fn clear(&mut self) {
+        self.surfaces.clear();
+    }
+
+    /// Creates a context that only draws onto the page.
+    fn page_context(&self, surface: &ImageSurface) -> Result<Context, Error> {
+        let (x, y, width, height) = self.page_rect;
+
+        let context = Context::new(surface)?;
+        context.rectangle(x, y, width, height);
+        context.clip();
+        Ok(context)
+    }
+
+    /// Renders the given area of the canvas, or all of it, from the scene.
+    fn render_area(
+        &self,
+        surface: &ImageSurface,
+        page: u32,
+        scene: &Scene,
+        templates: &PageTemplates,
+        renderer: &OutlineRenderer,
+        area: Option<Rect>,
+    ) -> Result<(), Error> {
+        let clip = |context: &Context| {
+            if let Some((x, y, width, height)) = area {
+                context.rectangle(x, y, width, height);
+                context.clip();
+            }
+        };
+
+        let (r, g, b) = renderer.scheme().color(BACKGROUND);
+        let background = Context::new(surface)?;
+        clip(&background);
+        background.set_source_rgb(r, g, b);
+        background.paint()?;
+
+        let context = self.page_context(surface)?;
+        clip(&context);
+        renderer.draw_background(&context, templates.name(page))?;
+        renderer.draw_source(&context, page)?;
+
+        let page = match scene.page(page) {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+
+        for (index, layer) in &page.layers {
+            let lines = layer
+                .strokes
+                .lines()
+                .filter(|line| area.is_none_or(|area| renderer.reaches(line, area)));
+
+            match scene.visibility(*index) {
+                LayerVisibility::Hidden => {}
+                LayerVisibility::Visible => renderer.draw_lines(&context, lines)?,
+                visibility => {
+                    // the layer is composed first so overlapping lines don't add up
+                    context.push_group();
+                    renderer.draw_lines(&context, lines)?;
+                    context.pop_group_to_source()?;
+                    context.paint_with_alpha(visibility.opacity())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use gio::Menu;
-use gtk::{prelude::*, AboutDialogExt, MenuItem};
+use gtk::{prelude::*, MenuItem};
 use log::debug;
 
 use crate::{
@@ -31,6 +31,7 @@ impl AppView {
 
         more_menu.append(Some("About"), Some("app.about"));
 
+        app_menu.append(Some("Open recording…"), Some("app.replay"));
         app_menu.append(Some("Quit"), Some("app.quit"));
         menu_bar.append_submenu(Some("?"), &more_menu);
 
@@ -98,7 +99,7 @@ pub fn build_about_dialog() -> gtk::AboutDialog {
 
 pub fn build_app_window(builder: &gtk::Builder) -> gtk::ApplicationWindow {
     let window: gtk::ApplicationWindow = builder
-        .get_object("mainWindow")
+        .object("mainWindow")
         .expect("Could not find main window");
 
     window.set_title(MAIN_WINDOW_NAME);
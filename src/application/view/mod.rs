@@ -13,3 +13,6 @@ pub const APPLICATION_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "+", en
 
 /// The string to use for the current app window
 pub const APP_WINDOWS_STRING: &str = include_str!("app_window.glade");
+
+/// The string to use for the live view window
+pub const LIVEVIEW_WINDOW_STRING: &str = include_str!("liveview_window.glade");
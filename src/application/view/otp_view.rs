@@ -1,12 +1,8 @@
-use gio::ActionMapExt;
-use glib::Receiver;
-use gtk::{
-    prelude::BuilderExtManual, Button, ButtonExt, Entry, EntryExt, GtkWindowExt, Inhibit, Label,
-    LabelExt, WidgetExt, WindowPosition,
-};
+use glib::{Propagation, Receiver};
+use gtk::{prelude::*, Button, Entry, Label, WindowPosition};
 use log::{debug, warn};
 
-use crate::view::APP_WINDOWS_STRING;
+use crate::{application::main_channel, view::APP_WINDOWS_STRING};
 
 pub struct OtpView {
     otp_dialog: gtk::Window,
@@ -19,9 +15,9 @@ pub struct OtpView {
 impl OtpView {
     pub fn new() -> Self {
         let builder = gtk::Builder::from_string(APP_WINDOWS_STRING);
-        let otp_entry = builder.get_object("otp_entry").unwrap();
-        let otp_ok_button: Button = builder.get_object("otp_ok_button").unwrap();
-        let otp_information_label: Label = builder.get_object("otp_information_label").unwrap();
+        let otp_entry = builder.object("otp_entry").unwrap();
+        let otp_ok_button: Button = builder.object("otp_ok_button").unwrap();
+        let otp_information_label: Label = builder.object("otp_information_label").unwrap();
 
         let otp_dialog = build_otp_dialog(None, &builder);
 
@@ -49,16 +45,16 @@ impl OtpView {
     }
 
     pub fn get_otp_text(&self) -> String {
-        self.otp_entry.get_text().to_string()
+        self.otp_entry.text().to_string()
     }
 
     /// Returns a channel where the value of the otp_entry will be sent when the
     /// button has been pressed
     pub fn connect_otp_channel(&self) -> Receiver<String> {
-        let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let (tx, rx) = main_channel();
         let entry = self.otp_entry.clone();
         self.otp_button.connect_clicked(move |_| {
-            let otp_text = entry.get_text().to_string();
+            let otp_text = entry.text().to_string();
             if let Err(e) = tx.send(otp_text) {
                 warn!("Error sending otp: {:?}", e);
             }
@@ -85,10 +81,10 @@ impl OtpView {
 
 fn build_otp_dialog(parent: Option<&gtk::Window>, builder: &gtk::Builder) -> gtk::Window {
     let window: gtk::Window = builder
-        .get_object("login_window")
+        .object("login_window")
         .expect("Could not find login_window in glade file");
 
-    window.connect_delete_event(|_, _| Inhibit(false));
+    window.connect_delete_event(|_, _| Propagation::Proceed);
     if parent.is_some() {
         window.set_transient_for(parent);
     }
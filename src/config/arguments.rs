@@ -0,0 +1,259 @@
+use std::path::PathBuf;
+
+use log::warn;
+
+use crate::{
+    config::config_io::resolve_recording_path,
+    remarkable::format::device::{DeviceModel, DeviceProfile},
+};
+
+/// Records every live session from its start.
+const RECORD_FLAG: &str = "--record";
+
+/// Sets the directory recordings are written to.
+const RECORDINGS_OPTION: &str = "--recordings";
+
+/// Plays back a recording.
+const REPLAY_OPTION: &str = "--replay";
+
+/// Selects the tablet sharing its screen.
+const DEVICE_OPTION: &str = "--device";
+
+/// Reads the templates of pages from a `.content` or `.pagedata` file.
+const TEMPLATES_OPTION: &str = "--templates";
+
+/// Exports pages into a file instead of starting the application.
+const EXPORT_OPTION: &str = "--export";
+
+/// Where live sessions are recorded to and whether they are recorded from
+/// their start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingOptions {
+    pub directory: PathBuf,
+    /// Whether sessions are recorded without turning it on in the window
+    pub record: bool,
+}
+
+/// Pages to render into a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    pub output: PathBuf,
+    /// The .rm files of the pages in order
+    pub pages: Vec<PathBuf>,
+}
+
+/// The options given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arguments {
+    pub recording: RecordingOptions,
+    /// A recording to play back on start
+    pub replay: Option<PathBuf>,
+    /// The tablet whose pages are shown
+    pub device: DeviceProfile,
+    /// The file the templates of pages are read from, for pages of
+    /// documents that are not downloaded
+    pub templates: Option<PathBuf>,
+    /// Pages to export instead of starting the application
+    pub export: Option<ExportOptions>,
+    /// The arguments left for GTK, starting with the program name
+    pub remaining: Vec<String>,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        let directory = match resolve_recording_path() {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Recording sessions into the temporary directory: {}", e);
+                std::env::temp_dir().join("review").join("recordings")
+            }
+        };
+
+        RecordingOptions {
+            directory,
+            record: false,
+        }
+    }
+}
+
+/// Picks the options of this application out of the command line. All other
+/// arguments are kept for GTK, which rejects options it does not know.
+pub fn parse_arguments<I: IntoIterator<Item = String>>(args: I) -> Result<Arguments, String> {
+    let mut recording = RecordingOptions::default();
+    let mut replay = None;
+    let mut device = DeviceProfile::default();
+    let mut templates = None;
+    let mut export = None;
+    let mut remaining = vec![];
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+        if arg == RECORD_FLAG {
+            recording.record = true;
+        } else if arg == RECORDINGS_OPTION {
+            let directory = args
+                .next()
+                .ok_or(format!("{} needs a directory", RECORDINGS_OPTION))?;
+            recording.directory = PathBuf::from(directory);
+        } else if let Some(directory) = arg.strip_prefix(&format!("{}=", RECORDINGS_OPTION)) {
+            recording.directory = PathBuf::from(directory);
+        } else if arg == REPLAY_OPTION {
+            let path = args
+                .next()
+                .ok_or(format!("{} needs a recording", REPLAY_OPTION))?;
+            replay = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix(&format!("{}=", REPLAY_OPTION)) {
+            replay = Some(PathBuf::from(path));
+        } else if arg == DEVICE_OPTION {
+            let identifier = args
+                .next()
+                .ok_or(format!("{} needs a device", DEVICE_OPTION))?;
+            device = parse_device(&identifier)?;
+        } else if let Some(identifier) = arg.strip_prefix(&format!("{}=", DEVICE_OPTION)) {
+            device = parse_device(identifier)?;
+        } else if arg == TEMPLATES_OPTION {
+            let path = args
+                .next()
+                .ok_or(format!("{} needs a file", TEMPLATES_OPTION))?;
+            templates = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix(&format!("{}=", TEMPLATES_OPTION)) {
+            templates = Some(PathBuf::from(path));
+        } else if arg == EXPORT_OPTION {
+            let output = args
+                .next()
+                .ok_or(format!("{} needs an output file", EXPORT_OPTION))?;
+
+            // the pages follow up to the next option
+            let mut pages = vec![];
+            while let Some(page) = args.next_if(|arg| !arg.starts_with("--")) {
+                pages.push(PathBuf::from(page));
+            }
+
+            export = Some(ExportOptions {
+                output: PathBuf::from(output),
+                pages,
+            });
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    Ok(Arguments {
+        recording,
+        replay,
+        device,
+        templates,
+        export,
+        remaining,
+    })
+}
+
+fn parse_device(identifier: &str) -> Result<DeviceProfile, String> {
+    DeviceModel::from_identifier(identifier)
+        .map(|model| model.profile())
+        .ok_or(format!(
+            "Unknown device {}, expected rm1, rm2 or paperpro",
+            identifier
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let arguments = parse_arguments(args(&["review", "--gapplication-service"])).unwrap();
+
+        assert!(!arguments.recording.record);
+        assert!(arguments.recording.directory.ends_with("recordings"));
+        assert_eq!(arguments.replay, None);
+        assert_eq!(
+            arguments.remaining,
+            args(&["review", "--gapplication-service"])
+        );
+    }
+
+    #[test]
+    fn test_recording_options() {
+        let arguments =
+            parse_arguments(args(&["review", "--record", "--recordings", "/tmp/a"])).unwrap();
+
+        assert!(arguments.recording.record);
+        assert_eq!(arguments.recording.directory, PathBuf::from("/tmp/a"));
+        assert_eq!(arguments.remaining, args(&["review"]));
+
+        let arguments = parse_arguments(args(&["review", "--recordings=/tmp/b"])).unwrap();
+        assert!(!arguments.recording.record);
+        assert_eq!(arguments.recording.directory, PathBuf::from("/tmp/b"));
+
+        assert!(parse_arguments(args(&["review", "--recordings"])).is_err());
+    }
+
+    #[test]
+    fn test_replay_option() {
+        let arguments = parse_arguments(args(&["review", "--replay", "a.rvsession"])).unwrap();
+        assert_eq!(arguments.replay, Some(PathBuf::from("a.rvsession")));
+
+        let arguments = parse_arguments(args(&["review", "--replay=b.rvsession"])).unwrap();
+        assert_eq!(arguments.replay, Some(PathBuf::from("b.rvsession")));
+        assert_eq!(arguments.remaining, args(&["review"]));
+
+        assert!(parse_arguments(args(&["review", "--replay"])).is_err());
+    }
+
+    #[test]
+    fn test_device_option() {
+        let arguments = parse_arguments(args(&["review"])).unwrap();
+        assert_eq!(arguments.device, DeviceProfile::default());
+
+        let arguments = parse_arguments(args(&["review", "--device", "paperpro"])).unwrap();
+        assert_eq!(arguments.device.model, DeviceModel::PaperPro);
+        assert_eq!(arguments.device.width, 1620.0);
+
+        let arguments = parse_arguments(args(&["review", "--device=rm1"])).unwrap();
+        assert_eq!(arguments.device.model, DeviceModel::Remarkable1);
+        assert_eq!(arguments.remaining, args(&["review"]));
+
+        assert!(parse_arguments(args(&["review", "--device", "kindle"])).is_err());
+        assert!(parse_arguments(args(&["review", "--device"])).is_err());
+    }
+
+    #[test]
+    fn test_templates_option() {
+        let arguments = parse_arguments(args(&["review", "--templates", "a.pagedata"])).unwrap();
+        assert_eq!(arguments.templates, Some(PathBuf::from("a.pagedata")));
+
+        let arguments = parse_arguments(args(&["review", "--templates=b.content"])).unwrap();
+        assert_eq!(arguments.templates, Some(PathBuf::from("b.content")));
+        assert_eq!(arguments.remaining, args(&["review"]));
+
+        assert_eq!(parse_arguments(args(&["review"])).unwrap().templates, None);
+        assert!(parse_arguments(args(&["review", "--templates"])).is_err());
+    }
+
+    #[test]
+    fn test_export_option() {
+        let arguments = parse_arguments(args(&[
+            "review", "--export", "out.pdf", "1.rm", "2.rm", "--device", "rm1",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            arguments.export,
+            Some(ExportOptions {
+                output: PathBuf::from("out.pdf"),
+                pages: vec![PathBuf::from("1.rm"), PathBuf::from("2.rm")],
+            })
+        );
+        assert_eq!(arguments.device.model, DeviceModel::Remarkable1);
+        assert_eq!(arguments.remaining, args(&["review"]));
+
+        assert_eq!(parse_arguments(args(&["review"])).unwrap().export, None);
+        assert!(parse_arguments(args(&["review", "--export"])).is_err());
+    }
+}
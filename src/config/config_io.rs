@@ -1,9 +1,9 @@
 use std::{
     fs,
-    fs::File,
-    io::Write,
     path::{Path, PathBuf},
 };
+#[cfg(not(test))]
+use std::{fs::File, io::Write};
 
 use directories::BaseDirs;
 
@@ -12,6 +12,9 @@ use crate::config::{config::Config as ConfigStruct, Serializable};
 const CONFIG_FILE_PATH: &str = "rmapi";
 const CONFIG_FILE_NAME: &str = "rmapi.conf";
 pub const CONFIG_PATH: [&str; 2] = [CONFIG_FILE_PATH, CONFIG_FILE_NAME];
+pub const TEMPLATE_PATH: [&str; 2] = ["review", "templates"];
+pub const DOCUMENT_CACHE_PATH: [&str; 2] = ["review", "documents"];
+pub const RECORDING_PATH: [&str; 2] = ["review", "recordings"];
 
 /// Resolves the config path relative
 /// by the home directory.
@@ -30,6 +33,36 @@ pub fn resolve_config_path() -> Result<PathBuf, String> {
     Err("Could not locate home path".into())
 }
 
+/// Resolves the directory users can put their own page templates into,
+/// relative to the data directory.
+pub fn resolve_template_path() -> Result<PathBuf, String> {
+    let dirs = BaseDirs::new().ok_or("Could not locate home path")?;
+
+    Ok(dirs
+        .data_dir()
+        .join(TEMPLATE_PATH.iter().collect::<PathBuf>()))
+}
+
+/// Resolves the directory downloaded documents are cached in, relative to
+/// the cache directory.
+pub fn resolve_document_cache_path() -> Result<PathBuf, String> {
+    let dirs = BaseDirs::new().ok_or("Could not locate home path")?;
+
+    Ok(dirs
+        .cache_dir()
+        .join(DOCUMENT_CACHE_PATH.iter().collect::<PathBuf>()))
+}
+
+/// Resolves the directory recorded live sessions are written to, relative to
+/// the data directory.
+pub fn resolve_recording_path() -> Result<PathBuf, String> {
+    let dirs = BaseDirs::new().ok_or("Could not locate home path")?;
+
+    Ok(dirs
+        .data_dir()
+        .join(RECORDING_PATH.iter().collect::<PathBuf>()))
+}
+
 /// Writes a Config struct to a file.
 pub fn write_config(_conf: &dyn Serializable, _path: &Path) -> Result<(), String> {
     #[cfg(not(test))]
@@ -64,4 +97,22 @@ mod tests {
         let path: PathBuf = CONFIG_PATH.iter().collect();
         assert_eq!(path, OsStr::new("rmapi/rmapi.conf"))
     }
+
+    #[test]
+    fn test_template_dir_ok() {
+        let path = resolve_template_path().unwrap();
+        assert!(path.ends_with("review/templates"))
+    }
+
+    #[test]
+    fn test_document_cache_dir_ok() {
+        let path = resolve_document_cache_path().unwrap();
+        assert!(path.ends_with("review/documents"))
+    }
+
+    #[test]
+    fn test_recording_dir_ok() {
+        let path = resolve_recording_path().unwrap();
+        assert!(path.ends_with("review/recordings"))
+    }
 }
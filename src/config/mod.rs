@@ -1,9 +1,11 @@
 use std::time::Duration;
 
+pub mod arguments;
+#[allow(clippy::module_inception)]
 pub mod config;
 pub mod config_io;
 
-pub trait UnserializableConfig: KeyStore + Identifiable + Expirable {}
+pub trait UnserializableConfig: KeyStore + Identifiable + Expirable + Send {}
 
 pub trait Config: KeyStore + Identifiable + Expirable + Serializable {}
 
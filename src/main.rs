@@ -1,3 +1,7 @@
+// the format, model and config modules offer more than the application uses
+// yet, e.g. the writers the tests check the parsers with
+#![allow(dead_code)]
+
 use std::{env::args, os::unix::prelude::OsStringExt};
 
 use gio::prelude::*;
@@ -6,14 +10,18 @@ use log::{debug, info};
 use crate::{
     application::{model, view, view::APPLICATION_IDENTIFIER},
     config::{
+        arguments::parse_arguments,
         config::Config,
         config_io::{load_config_from_file, resolve_config_path},
     },
+    remarkable::format::template::PageTemplates,
+    render::export::export_pages,
 };
 
 mod application;
 mod config;
 mod remarkable;
+mod render;
 
 fn main() {
     env_logger::init();
@@ -24,18 +32,42 @@ fn main() {
         env!("CARGO_PKG_VERSION")
     );
 
-    gtk::init().expect("Failed to init GTK+ application");
-
-    let application = gtk::Application::new(Some(APPLICATION_IDENTIFIER), Default::default());
-
-    let application = match application {
-        Ok(application) => application,
+    let arguments = match parse_arguments(args()) {
+        Ok(arguments) => arguments,
         Err(error) => {
-            view::error::show_error("Failed to INIT", error.to_string().as_str());
-            panic!("Failed to initialize GTK Application: {}", error);
+            eprintln!("{}", error);
+            std::process::exit(2);
         }
     };
 
+    let templates = match &arguments.templates {
+        Some(path) => match PageTemplates::from_file(path) {
+            Ok(templates) => templates,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            }
+        },
+        None => PageTemplates::default(),
+    };
+
+    if let Some(export) = &arguments.export {
+        if let Err(error) =
+            export_pages(&export.pages, &export.output, arguments.device, &templates)
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    gtk::init().expect("Failed to init GTK+ application");
+
+    let application = gtk::Application::new(Some(APPLICATION_IDENTIFIER), Default::default());
+
+    let recording = arguments.recording.clone();
+    let replay = arguments.replay.clone();
+    let device = arguments.device;
     application.connect_activate(move |app| {
         info!("Application activated");
         let app = app.clone();
@@ -58,13 +90,20 @@ fn main() {
         let app_model = model::app_model::AppModel::new(config);
 
         let mut app_controller =
-            model::app_controller::AppController::new(Box::new(app_model), app_view, otp_dialog);
+            model::app_controller::AppController::new(Box::new(app_model), app_view, otp_dialog)
+                .with_recording(recording.clone())
+                .with_device(device)
+                .with_templates(templates.clone());
 
         debug!("Running AppController");
         app_controller.connect_application(&app);
 
+        if let Some(path) = &replay {
+            app_controller.open_replay(path);
+        }
+
         app_controller.run();
     });
 
-    application.run(&args().collect::<Vec<_>>());
+    application.run_with_args(&arguments.remaining);
 }
@@ -1,4 +1,4 @@
-#[cfg(target_os = "other")]
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
 pub const REMARKABLE_DEVICE_DESCRIPTION: &str = "browser-chrome";
 
 #[cfg(target_os = "linux")]
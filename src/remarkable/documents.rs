@@ -0,0 +1,264 @@
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::PathBuf,
+};
+
+use log::{debug, trace, warn};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use crate::remarkable::format::{content::DocumentContent, template::PageTemplates};
+
+/// The name of the extracted `.content` file in the cache.
+const CONTENT_FILE: &str = "content.json";
+
+/// The name of the extracted source PDF in the cache.
+const SOURCE_FILE: &str = "source.pdf";
+
+/// The name of the extracted `.pagedata` file in the cache.
+const PAGEDATA_FILE: &str = "pagedata.txt";
+
+/// A document downloaded from the cloud.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedDocument {
+    pub id: String,
+    pub version: u32,
+    /// The PDF the document was created from, if any. EPUBs come with the
+    /// PDF the tablet converted them to.
+    pub source: Option<PathBuf>,
+    pub content: DocumentContent,
+}
+
+/// Keeps the files needed to render documents on disk, so every version of
+/// a document is only downloaded once. Only the latest version is kept.
+#[derive(Debug, Clone)]
+pub struct DocumentCache {
+    directory: PathBuf,
+}
+
+impl DocumentCache {
+    pub fn new(directory: PathBuf) -> Self {
+        DocumentCache { directory }
+    }
+
+    /// The directory all versions of a document are kept in. Documents are
+    /// identified by a UUID, anything else must not become a path.
+    fn versions_directory(&self, id: &str) -> Result<PathBuf, String> {
+        Uuid::parse_str(id)
+            .map(|uuid| self.directory.join(uuid.to_string()))
+            .map_err(|e| format!("Document id {:?} is not a UUID: {}", id, e))
+    }
+
+    fn document_directory(&self, id: &str, version: u32) -> Result<PathBuf, String> {
+        Ok(self.versions_directory(id)?.join(version.to_string()))
+    }
+
+    /// Returns the given version of a document if it has been stored before.
+    pub fn load(&self, id: &str, version: u32) -> Option<CachedDocument> {
+        let directory = match self.document_directory(id, version) {
+            Ok(directory) => directory,
+            Err(e) => {
+                warn!("{}", e);
+                return None;
+            }
+        };
+        let content = fs::read_to_string(directory.join(CONTENT_FILE)).ok()?;
+
+        let mut content = match DocumentContent::from_json(&content) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Ignoring broken cache entry {:?}: {}", directory, e);
+                return None;
+            }
+        };
+
+        // older documents only list their templates in the page data
+        if content.templates.is_empty() {
+            if let Ok(pagedata) = fs::read_to_string(directory.join(PAGEDATA_FILE)) {
+                content.templates = PageTemplates::from_pagedata(&pagedata);
+            }
+        }
+
+        let source = directory.join(SOURCE_FILE);
+
+        Some(CachedDocument {
+            id: id.to_string(),
+            version,
+            source: if source.is_file() { Some(source) } else { None },
+            content,
+        })
+    }
+
+    /// Extracts the files needed from the zip archive of a document and
+    /// replaces older versions with them.
+    pub fn store(&self, id: &str, version: u32, archive: &[u8]) -> Result<CachedDocument, String> {
+        debug!("Caching version {} of {}", version, id);
+
+        let document_directory = self.versions_directory(id)?;
+        let directory = self.document_directory(id, version)?;
+
+        let mut archive = ZipArchive::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+        let mut content = None;
+        let mut source = None;
+        let mut pagedata = None;
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(|e| e.to_string())?;
+            let name = file.name().to_string();
+            trace!("Archive contains {}", name);
+
+            // the pages are stored in a folder next to the metadata
+            let target = match name.strip_prefix(id) {
+                Some(".content") => &mut content,
+                Some(".pdf") => &mut source,
+                Some(".pagedata") => &mut pagedata,
+                _ => continue,
+            };
+
+            let mut data = vec![];
+            file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            *target = Some(data);
+        }
+
+        let content = content.ok_or_else(|| format!("The archive of {} has no content", id))?;
+        let content = String::from_utf8(content).map_err(|e| e.to_string())?;
+
+        if document_directory.is_dir() {
+            trace!("Removing older versions of {}", id);
+            fs::remove_dir_all(&document_directory).map_err(|e| e.to_string())?;
+        }
+
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        fs::write(directory.join(CONTENT_FILE), &content).map_err(|e| e.to_string())?;
+
+        if let Some(source) = source {
+            fs::write(directory.join(SOURCE_FILE), source).map_err(|e| e.to_string())?;
+        }
+
+        if let Some(pagedata) = pagedata {
+            fs::write(directory.join(PAGEDATA_FILE), pagedata).map_err(|e| e.to_string())?;
+        }
+
+        self.load(id, version)
+            .ok_or_else(|| format!("Failed to read back {}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use uuid::Uuid;
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::remarkable::format::{content::FileType, template::Template};
+
+    const ID: &str = "3f7e7b2e-5c1a-4d8e-9a6b-2f4c8d0e1a5b";
+
+    fn name(extension: &str) -> String {
+        format!("{}{}", ID, extension)
+    }
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn cache() -> DocumentCache {
+        DocumentCache::new(std::env::temp_dir().join(Uuid::new_v4().to_string()))
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let cache = cache();
+        let data = archive(&[
+            (&name(".content"), br#"{ "fileType": "pdf" }"#),
+            (&name(".pdf"), b"%PDF-1.4"),
+            (&name("/page.rm"), b"lines"),
+        ]);
+
+        let stored = cache.store(ID, 3, &data).unwrap();
+
+        assert_eq!(stored.content.file_type, FileType::Pdf);
+        assert_eq!(
+            fs::read(stored.source.as_ref().unwrap()).unwrap(),
+            b"%PDF-1.4"
+        );
+        assert_eq!(cache.load(ID, 3), Some(stored));
+        assert_eq!(cache.load(ID, 2), None);
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn test_newer_version_replaces_older() {
+        let cache = cache();
+        let notebook = archive(&[(&name(".content"), br#"{ "fileType": "notebook" }"#)]);
+
+        cache.store(ID, 1, &notebook).unwrap();
+        let stored = cache.store(ID, 2, &notebook).unwrap();
+
+        assert_eq!(stored.source, None);
+        assert_eq!(cache.load(ID, 1), None);
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn test_archive_without_content() {
+        let data = archive(&[("other.pdf", b"%PDF-1.4")]);
+
+        assert!(cache().store(ID, 1, &data).is_err());
+    }
+
+    #[test]
+    fn test_templates_from_pagedata() {
+        let cache = cache();
+        let data = archive(&[
+            (&name(".content"), br#"{ "fileType": "notebook" }"#),
+            (&name(".pagedata"), b"Blank\nP Grid medium\n"),
+        ]);
+
+        let stored = cache.store(ID, 1, &data).unwrap();
+
+        assert_eq!(stored.content.templates.template(0), Template::Blank);
+        assert_eq!(stored.content.templates.name(1), "P Grid medium");
+
+        let listed = archive(&[
+            (
+                &name(".content"),
+                br#"{ "fileType": "notebook", "pageTemplates": ["P Dots S"] }"#,
+            ),
+            (&name(".pagedata"), b"Blank\n"),
+        ]);
+
+        let stored = cache.store(ID, 2, &listed).unwrap();
+        assert_eq!(stored.content.templates.name(0), "P Dots S");
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_ids_other_than_uuids() {
+        let cache = cache();
+        let outside = cache.directory.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+
+        let data = archive(&[("../outside.content", br#"{ "fileType": "notebook" }"#)]);
+
+        assert!(cache.store("..", 1, &data).is_err());
+        assert!(cache.store("../outside", 1, &data).is_err());
+        assert_eq!(cache.load("../outside", 1), None);
+        assert!(outside.is_dir());
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+}
@@ -1,146 +1,236 @@
-use gtk::targets_include_rich_text;
-use log::{debug, info, trace};
-use std::borrow::Borrow;
+use log::{debug, trace};
 
-use crate::remarkable::constants::{
-    PROTOCOL, REMARKABLE_STORAGE_DISCOVERY_PATH, REMARKABLE_STORAGE_PATH,
+use crate::remarkable::{
+    constants::REMARKABLE_STORAGE_PATH,
+    documents::{CachedDocument, DocumentCache},
+    remarkable_tree::{File, Item},
 };
-use crate::remarkable::remarkable_tree;
-use crate::remarkable::remarkable_tree::{File, Item};
 
 pub struct APIConnection<'a> {
     pub storage_url: &'a str,
     pub session_key: &'a str,
 }
 
+/// Looks up the entry of a document in the storage API. With `with_blob`
+/// the entry contains a url to download the document from.
+async fn query_item(
+    connection: &APIConnection<'_>,
+    file_id: &str,
+    with_blob: bool,
+) -> Result<json::JsonValue, String> {
+    let mut url = connection.storage_url.to_string();
+    url.push_str(REMARKABLE_STORAGE_PATH);
+
+    trace!("Url: {:?}", url);
+
+    let client = reqwest::Client::new();
+    let mut builder = client
+        .get(&url)
+        .bearer_auth(connection.session_key)
+        .query(&[("doc", file_id)]);
+
+    if with_blob {
+        builder = builder.query(&[("withBlob", "true")]);
+    }
+
+    let response = builder.send().await.map_err(|e| e.to_string())?;
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    let mut json = json::parse(&body).map_err(|e| e.to_string())?;
+
+    let item = json[0].take();
+
+    if item["Success"].as_bool() == Some(false) {
+        return Err(match item["Message"].as_str() {
+            Some(message) if !message.is_empty() => message.to_string(),
+            _ => format!("Failed to look up {}", file_id),
+        });
+    }
+
+    Ok(item)
+}
+
 /**
- * transforms a remalable internal ID into a string presentable to the user
+ * transforms a remarkable internal ID into the document it refers to
  */
 pub async fn info_for_item(
     storage_url: &str,
     user_token: &str,
     file_id: &str,
-) -> Result<remarkable_tree::File<'static>, String> {
+) -> Result<File, String> {
     debug!("Looking up file");
     trace!("Name: {}", file_id);
     trace!("Auth: {}", user_token);
 
-    let mut url = PROTOCOL.to_string();
-    url.push_str(storage_url);
-    url.push_str(REMARKABLE_STORAGE_DISCOVERY_PATH);
-
-    trace!("Url: {:?}", url);
+    let connection = APIConnection {
+        storage_url,
+        session_key: user_token,
+    };
+    let item = query_item(&connection, file_id, false).await?;
+
+    let id = item["ID"].as_str();
+    let name = item["VissibleName"].as_str();
+    let page = item["CurrentPage"].as_u32();
+    let version = item["Version"].as_u32();
+
+    match (id, name, page, version) {
+        (Some(id), Some(name), Some(page), Some(version)) => Ok(File {
+            item: Item {
+                name: name.to_string(),
+                id: id.to_string(),
+                parent: item["Parent"]
+                    .as_str()
+                    .filter(|parent| !parent.is_empty())
+                    .map(String::from),
+            },
+            current_page: page,
+            version,
+        }),
+        _ => Err("Failed to parse".to_string()),
+    }
+}
 
-    let client = reqwest::Client::new();
-    let client = client
-        .post(&url)
-        .bearer_auth(user_token)
-        .query(&[("doc", file_id)]);
+/// Downloads the zip archive containing all files of a document.
+pub async fn download_blob(
+    connection: APIConnection<'_>,
+    file_id: &str,
+) -> Result<Vec<u8>, String> {
+    debug!("Downloading file");
+    trace!("Name: {}", file_id);
+    trace!("Auth: {}", connection.session_key);
 
-    match client.send().await {
-        Ok(d) => {
-            let json = json::parse(d.text().await.unwrap().as_str()).expect("");
+    let item = query_item(&connection, file_id, true).await?;
 
-            let item = json.borrow()[0].borrow();
+    let url = match item["BlobURLGet"].as_str() {
+        Some(url) if !url.is_empty() => url.to_string(),
+        _ => return Err(format!("No download url for {}", file_id)),
+    };
 
-            let success = item["Success"].as_bool();
-            let message = item["Message"].as_str();
+    // Download the file now
+    debug!("Got url: {}", url);
 
-            if message.is_some()
-                && message.unwrap().ne("")
-                && success.is_some()
-                && success.unwrap() != true
-            {
-                return Err(message.unwrap().to_string());
-            }
+    let response = reqwest::Client::new()
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
 
-            let id = item["ID"].borrow().as_str();
-            let name = item["VissibleName"].as_str();
-            let page = item["CurrentPage"].as_u32();
-            let version = item["Version"].as_u32();
+    if !response.status().is_success() {
+        return Err(format!("Download failed with {}", response.status()));
+    }
 
-            if id.is_none() || name.is_none() || page.is_none() || version.is_none() {
-                return Err("Failed to parse".to_string());
-            }
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+    trace!("Got {} bytes", body.len());
 
-            let item: Item = Item {
-                name: name.unwrap().to_string(),
-                id: id.unwrap().to_string(),
-                parent: None,
-            };
-
-            Ok(File {
-                item,
-                current_page: page.unwrap(),
-                version: version.unwrap(),
-            })
-        }
-        Err(e) => Err(e.to_string()),
-    }
+    Ok(body.to_vec())
 }
 
-pub async fn get_file_name(connection: APIConnection<'_>, file_id: &str) {}
+/// Returns the current version of a document, downloading it only if the
+/// cache doesn't contain it yet.
+pub async fn fetch_document(
+    connection: APIConnection<'_>,
+    cache: &DocumentCache,
+    file_id: &str,
+) -> Result<CachedDocument, String> {
+    let file = info_for_item(connection.storage_url, connection.session_key, file_id).await?;
+    debug!("Document {} is {:?}", file_id, file.item.name);
 
-pub async fn download_blob(connection: APIConnection<'_>, file_id: &str) -> Result<String, String> {
-    debug!("Looking up file");
-    trace!("Name: {}", file_id);
-    trace!("Auth: {}", connection.session_key);
+    if let Some(document) = cache.load(file_id, file.version) {
+        debug!("Using cached version {}", file.version);
+        return Ok(document);
+    }
 
-    let mut url = PROTOCOL.to_string();
-    url.push_str(connection.storage_url);
-    url.push_str(REMARKABLE_STORAGE_PATH);
+    let archive = download_blob(connection, file_id).await?;
 
-    trace!("Url: {:?}", url);
+    cache.store(file_id, file.version, &archive)
+}
 
-    let user_token = connection.session_key;
+#[cfg(test)]
+mod tests {
+    use httpmock::MockServer;
 
-    let client = reqwest::Client::new();
-    let client = client
-        .post(&url)
-        .bearer_auth(user_token)
-        .query(&[("doc", file_id), ("withBlob", "true")]);
-
-    let url = match client.send().await {
-        Ok(d) => {
-            let json = json::parse(d.text().await.unwrap().as_str()).expect("");
-
-            let item = json.borrow()[0].borrow();
-
-            let success = item["Success"].as_bool();
-            let message = item["Message"].as_str();
-
-            if message.is_some()
-                && message.unwrap().ne("")
-                && success.is_some()
-                && success.unwrap() != true
-            {
-                return Err(message.unwrap().to_string());
-            }
+    use super::*;
 
-            let url = item["BlobURLGet"].borrow().to_string();
+    fn mock_item(server: &MockServer, with_blob: bool) {
+        let blob_url = server.url("/blob");
 
-            Ok(url)
-        }
-        Err(e) => Err(e.to_string()),
-    };
+        server.mock(|when, then| {
+            let when = when
+                .method("GET")
+                .path(REMARKABLE_STORAGE_PATH)
+                .query_param("doc", "1234")
+                .header("authorization", "Bearer token");
+            if with_blob {
+                when.query_param("withBlob", "true");
+            }
 
-    if url.is_err() {
-        return url;
+            then.status(200)
+                .header("Content-Type", "application/json")
+                .body(
+                    json::array![json::object! {
+                        "ID": "1234",
+                        "Version": 3,
+                        "Message": "",
+                        "Success": true,
+                        "BlobURLGet": blob_url.as_str(),
+                        "VissibleName": "Contract",
+                        "CurrentPage": 2,
+                        "Parent": "",
+                    }]
+                    .dump(),
+                );
+        });
     }
-    let url = url.unwrap();
 
-    // Download the file now
-    debug!("Got url: {}", url);
+    #[tokio::test]
+    async fn test_info_for_item() {
+        let server = MockServer::start();
+        mock_item(&server, false);
 
-    let client = reqwest::Client::new();
-    let client = client.get(&url);
+        let file = info_for_item(&server.base_url(), "token", "1234")
+            .await
+            .unwrap();
 
-    match client.send().await {
-        Ok(res) => {
-            trace!("Got body: {:?}", res.bytes().await);
+        assert_eq!(file.item.name, "Contract");
+        assert_eq!(file.item.parent, None);
+        assert_eq!(file.current_page, 2);
+        assert_eq!(file.version, 3);
+    }
+
+    #[tokio::test]
+    async fn test_info_for_missing_item() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.path(REMARKABLE_STORAGE_PATH);
+            then.status(200).body(
+                json::array![json::object! {
+                    "ID": "1234",
+                    "Message": "Document not found",
+                    "Success": false,
+                }]
+                .dump(),
+            );
+        });
+
+        let result = info_for_item(&server.base_url(), "token", "1234").await;
+
+        assert_eq!(result, Err("Document not found".to_string()));
+    }
 
-            Ok("TEST".to_string())
-        }
-        Err(e) => Err(e.to_string()),
+    #[tokio::test]
+    async fn test_download_blob() {
+        let server = MockServer::start();
+        mock_item(&server, true);
+        server.mock(|when, then| {
+            when.method("GET").path("/blob");
+            then.status(200).body(vec![1u8, 2, 3]);
+        });
+
+        let storage_url = server.base_url();
+        let connection = APIConnection {
+            storage_url: &storage_url,
+            session_key: "token",
+        };
+
+        assert_eq!(download_blob(connection, "1234").await, Ok(vec![1, 2, 3]));
     }
 }
@@ -0,0 +1,289 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use crate::remarkable::format::data::{PenColor, PenType, Point};
+
+/// The speed above which a stroke does not get any thinner.
+const MAX_SPEED: f64 = 10.0;
+
+/// The width of a flat nib moved along its edge relative to its full width.
+const NIB_MIN_WIDTH: f64 = 0.25;
+
+/// How ink is combined with what is already on the page.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum BlendMode {
+    /// The ink covers the page
+    #[default]
+    Normal,
+    /// The ink darkens the page like a highlighter, so text below stays
+    /// readable
+    Multiply,
+    /// The ink lightens the page, used for highlighters on dark paper
+    Screen,
+}
+
+/// The shape of the ends of a stroke.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TipShape {
+    #[default]
+    Round,
+    /// A flat felt tip with square ends
+    Chisel,
+}
+
+/// The structure real ink of a tool has on paper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Texture {
+    /// Grains of graphite, the given number per square tablet unit at full
+    /// pressure
+    Grain(f64),
+    /// Streaks of the given number of bristles
+    Bristles(usize),
+}
+
+/// Describes how a tool turns the sampled pen state into rendered ink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrushModel {
+    /// Factor applied to the width reported by the tablet
+    pub width_scale: f64,
+    /// How much pressure thickens the stroke (0 = not at all, 1 = fully)
+    pub pressure_width: f64,
+    /// How much a fast pen thins the stroke
+    pub speed_width: f64,
+    /// The opacity of the ink at full pressure
+    pub opacity: f64,
+    /// How much pressure modulates the opacity
+    pub pressure_opacity: f64,
+    /// How much a tilted pen stretches the tip along the pen direction
+    pub tilt_width: f64,
+    /// The angle of a flat nib, if the tool has one
+    pub nib_angle: Option<f64>,
+    pub blend: BlendMode,
+    pub tip: TipShape,
+    /// Rendered when textures are enabled instead of solid ink
+    pub texture: Option<Texture>,
+}
+
+/// The rendered properties of a single point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrokeStyle {
+    pub width: f64,
+    pub opacity: f64,
+    /// The rotation of the tip in radians
+    pub angle: f64,
+    /// The ratio between the tip's minor and major axis
+    pub aspect: f64,
+}
+
+impl BrushModel {
+    const fn new(
+        width_scale: f64,
+        pressure_width: f64,
+        speed_width: f64,
+        opacity: f64,
+        pressure_opacity: f64,
+    ) -> Self {
+        BrushModel {
+            width_scale,
+            pressure_width,
+            speed_width,
+            opacity,
+            pressure_opacity,
+            tilt_width: 0.0,
+            nib_angle: None,
+            blend: BlendMode::Normal,
+            tip: TipShape::Round,
+            texture: None,
+        }
+    }
+
+    const fn with_tilt(mut self, tilt_width: f64) -> Self {
+        self.tilt_width = tilt_width;
+        self
+    }
+
+    const fn with_nib(mut self, nib_angle: f64) -> Self {
+        self.nib_angle = Some(nib_angle);
+        self
+    }
+
+    const fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    const fn with_tip(mut self, tip: TipShape) -> Self {
+        self.tip = tip;
+        self
+    }
+
+    const fn with_texture(mut self, texture: Texture) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    /// Whether the ink covers the page completely at any pressure, so parts
+    /// of a stroke can be drawn on top of each other.
+    pub fn is_opaque(&self) -> bool {
+        self.opacity >= 1.0 && self.pressure_opacity == 0.0 && self.blend == BlendMode::Normal
+    }
+
+    /// Computes the width and opacity a point is rendered with.
+    pub fn style(&self, point: &Point) -> StrokeStyle {
+        let pressure = point.pressure.clamp(0.0, 1.0);
+        let speed = (point.speed / MAX_SPEED).clamp(0.0, 1.0);
+
+        let mut width = point.width *
+            self.width_scale *
+            (1.0 - self.pressure_width + self.pressure_width * pressure) *
+            (1.0 - self.speed_width * speed);
+
+        let opacity =
+            self.opacity * (1.0 - self.pressure_opacity + self.pressure_opacity * pressure);
+
+        if let Some(nib_angle) = self.nib_angle {
+            // a flat nib draws thin lines along its edge and wide ones across it
+            let across = (point.direction - nib_angle).sin().abs();
+            width *= NIB_MIN_WIDTH + (1.0 - NIB_MIN_WIDTH) * across;
+        }
+
+        // a tilted tip touches the paper with an ellipse stretched along the pen
+        let stretch = 1.0 + self.tilt_width * point.tilt.unwrap_or(0.0).sin().abs();
+
+        StrokeStyle {
+            width: (width * stretch).max(0.0),
+            opacity: opacity.clamp(0.0, 1.0),
+            angle: point.direction,
+            aspect: 1.0 / stretch,
+        }
+    }
+}
+
+impl PenType {
+    /// Returns the stroke model of the tool.
+    pub fn model(&self) -> BrushModel {
+        match self {
+            PenType::BallPoint | PenType::Pen => BrushModel::new(1.0, 0.3, 0.0, 1.0, 0.2),
+            PenType::Fineliner => BrushModel::new(1.0, 0.0, 0.0, 1.0, 0.0),
+            PenType::Marker => BrushModel::new(1.0, 0.1, 0.0, 0.9, 0.1),
+            PenType::SharpPencil => {
+                BrushModel::new(1.0, 0.0, 0.0, 0.9, 0.5).with_texture(Texture::Grain(0.8))
+            }
+            PenType::TiltPencil => BrushModel::new(1.0, 0.0, 0.0, 0.9, 0.6)
+                .with_tilt(2.0)
+                .with_texture(Texture::Grain(0.5)),
+            PenType::Brush => BrushModel::new(1.0, 0.7, 0.4, 1.0, 0.3)
+                .with_tilt(1.0)
+                .with_texture(Texture::Bristles(12)),
+            PenType::Calligraphy => BrushModel::new(1.0, 0.5, 0.2, 1.0, 0.0).with_nib(FRAC_PI_4),
+            // the chisel is held upright, so horizontal strokes are the widest
+            PenType::Highlighter => BrushModel::new(1.0, 0.0, 0.0, 0.5, 0.0)
+                .with_nib(FRAC_PI_2)
+                .with_tip(TipShape::Chisel)
+                .with_blend(BlendMode::Multiply),
+            PenType::Eraser | PenType::EraseArea | PenType::EraseAll => {
+                BrushModel::new(1.0, 0.0, 0.0, 1.0, 0.0)
+            }
+            PenType::SelectionBrush => BrushModel::new(1.0, 0.0, 0.0, 0.0, 0.0),
+            PenType::UNKNWON => BrushModel::new(1.0, 0.0, 0.0, 1.0, 0.0),
+        }
+    }
+
+    /// The colour the tool draws in. Older page files store the default
+    /// highlighter colour as black.
+    pub fn ink(&self, color: PenColor) -> PenColor {
+        match (self, color) {
+            (PenType::Highlighter, PenColor::BLACK) => PenColor::HIGHLIGHT_YELLOW,
+            (_, color) => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(pressure: f64, speed: f64) -> Point {
+        Point {
+            speed,
+            ..Point::new(0.0, 0.0, 4.0, pressure)
+        }
+    }
+
+    #[test]
+    fn test_fineliner_is_constant() {
+        let model = PenType::Fineliner.model();
+
+        assert_eq!(model.style(&point(0.1, 0.0)), model.style(&point(1.0, 8.0)));
+        assert_eq!(model.style(&point(0.5, 2.0)).width, 4.0);
+    }
+
+    #[test]
+    fn test_brush_follows_pressure_and_speed() {
+        let model = PenType::Brush.model();
+
+        let light = model.style(&point(0.2, 0.0));
+        let heavy = model.style(&point(1.0, 0.0));
+        let fast = model.style(&point(1.0, 10.0));
+
+        assert!(light.width < heavy.width);
+        assert!(fast.width < heavy.width);
+    }
+
+    #[test]
+    fn test_pencil_opacity_follows_pressure() {
+        let model = PenType::TiltPencil.model();
+
+        assert!(model.style(&point(0.1, 0.0)).opacity < model.style(&point(0.9, 0.0)).opacity);
+    }
+
+    #[test]
+    fn test_highlighter_is_translucent() {
+        let model = PenType::Highlighter.model();
+
+        assert!(model.style(&point(1.0, 0.0)).opacity < 1.0);
+        assert!(!model.is_opaque());
+        assert!(PenType::Fineliner.model().is_opaque());
+        assert_eq!(model.blend, BlendMode::Multiply);
+        assert_eq!(model.tip, TipShape::Chisel);
+        assert_eq!(
+            PenType::Highlighter.ink(PenColor::BLACK),
+            PenColor::HIGHLIGHT_YELLOW
+        );
+        assert_eq!(PenType::Fineliner.ink(PenColor::BLACK), PenColor::BLACK);
+    }
+
+    #[test]
+    fn test_calligraphy_follows_direction() {
+        let model = PenType::Calligraphy.model();
+
+        let mut along = point(1.0, 0.0);
+        along.direction = FRAC_PI_4;
+        let mut across = point(1.0, 0.0);
+        across.direction = 3.0 * FRAC_PI_4;
+
+        assert!(model.style(&along).width < model.style(&across).width);
+    }
+
+    #[test]
+    fn test_tilt_stretches_pencil() {
+        let model = PenType::TiltPencil.model();
+
+        let upright = point(1.0, 0.0);
+        let mut tilted = point(1.0, 0.0);
+        tilted.tilt = Some(1.0);
+
+        assert_eq!(model.style(&upright).aspect, 1.0);
+        assert!(model.style(&tilted).aspect < 1.0);
+        assert!(model.style(&tilted).width > model.style(&upright).width);
+    }
+
+    #[test]
+    fn test_ballpoint_ignores_tilt() {
+        let model = PenType::BallPoint.model();
+
+        let mut tilted = point(1.0, 0.0);
+        tilted.tilt = Some(1.0);
+
+        assert_eq!(model.style(&tilted).aspect, 1.0);
+    }
+}
@@ -0,0 +1,244 @@
+use json::JsonValue;
+
+use crate::remarkable::format::{device::Transform, template::PageTemplates};
+
+/// What a document on the tablet was created from.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum FileType {
+    /// Only pages drawn on the tablet
+    #[default]
+    Notebook,
+    Pdf,
+    /// An EPUB, which the tablet converts to a PDF when importing it
+    Epub,
+}
+
+/// How the tablet sizes the pages of a PDF to its screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ZoomMode {
+    /// The whole page is visible
+    #[default]
+    BestFit,
+    /// The page fills the width of the screen, starting at its top
+    FitToWidth,
+    /// The page fills the height of the screen
+    FitToHeight,
+    /// Zoomed or cropped on the tablet. The scale is relative to
+    /// [ZoomMode::BestFit] and the center is the distance of the screen's
+    /// center from the center of the scaled page in tablet units.
+    Custom { scale: f64, center: (f64, f64) },
+}
+
+/// The metadata stored in the `.content` file of a document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentContent {
+    pub file_type: FileType,
+    pub zoom: ZoomMode,
+    /// The page of the source shown on every page of the document, if the
+    /// document says so
+    redirections: Option<Vec<Option<u32>>>,
+    pub templates: PageTemplates,
+}
+
+impl FileType {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "pdf" => FileType::Pdf,
+            "epub" => FileType::Epub,
+            _ => FileType::Notebook,
+        }
+    }
+}
+
+impl ZoomMode {
+    fn from_content(content: &JsonValue) -> Self {
+        match content["zoomMode"].as_str() {
+            Some("fitToWidth") => ZoomMode::FitToWidth,
+            Some("fitToHeight") => ZoomMode::FitToHeight,
+            Some("customFit") => ZoomMode::Custom {
+                scale: content["customZoomScale"].as_f64().unwrap_or(1.0),
+                center: (
+                    content["customZoomCenterX"].as_f64().unwrap_or(0.0),
+                    content["customZoomCenterY"].as_f64().unwrap_or(0.0),
+                ),
+            },
+            _ => ZoomMode::BestFit,
+        }
+    }
+}
+
+impl DocumentContent {
+    /// Parses the content of a `.content` file.
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let json = json::parse(content).map_err(|e| e.to_string())?;
+
+        Ok(DocumentContent {
+            file_type: FileType::from_name(json["fileType"].as_str().unwrap_or_default()),
+            zoom: ZoomMode::from_content(&json),
+            redirections: redirections(&json),
+            templates: PageTemplates::from_content(content)?,
+        })
+    }
+
+    /// Whether the pages of the document are drawn on top of a PDF.
+    pub fn has_source(&self) -> bool {
+        self.file_type != FileType::Notebook
+    }
+
+    /// The index of the source page shown on the given page of the document.
+    /// Pages inserted on the tablet show none.
+    pub fn source_page(&self, page: u32) -> Option<u32> {
+        if !self.has_source() {
+            return None;
+        }
+
+        match &self.redirections {
+            Some(redirections) => redirections.get(page as usize).copied().flatten(),
+            None => Some(page),
+        }
+    }
+
+    /// Places a source page of the given size in points on a page of the
+    /// given size in tablet units, the way the tablet shows it.
+    pub fn placement(&self, source: (f64, f64), page: (f64, f64)) -> Transform {
+        let (source_width, source_height) = source;
+        let (width, height) = page;
+
+        let best_fit = (width / source_width).min(height / source_height);
+
+        let (scale, center) = match self.zoom {
+            ZoomMode::BestFit => (best_fit, (0.0, 0.0)),
+            ZoomMode::FitToWidth => {
+                let scale = width / source_width;
+                // the top of the page at the top of the screen
+                (scale, (0.0, (height - source_height * scale) / 2.0))
+            }
+            ZoomMode::FitToHeight => (height / source_height, (0.0, 0.0)),
+            ZoomMode::Custom { scale, center } => (best_fit * scale, center),
+        };
+
+        Transform::scale(scale).then(&Transform::translation(
+            (width - source_width * scale) / 2.0 - center.0,
+            (height - source_height * scale) / 2.0 - center.1,
+        ))
+    }
+}
+
+/// Reads which source page every page shows. Newer firmware stores it with
+/// every page, older firmware as a separate list using -1 for inserted
+/// pages.
+fn redirections(content: &JsonValue) -> Option<Vec<Option<u32>>> {
+    let pages = &content["cPages"]["pages"];
+    if pages.is_array() {
+        return Some(
+            pages
+                .members()
+                .filter(|page| !page["deleted"].is_object())
+                .map(|page| page["redir"]["value"].as_u32())
+                .collect(),
+        );
+    }
+
+    let map = &content["redirectionPageMap"];
+    if map.is_array() {
+        return Some(map.members().map(JsonValue::as_u32).collect());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LETTER: (f64, f64) = (612.0, 792.0);
+    const PAGE: (f64, f64) = (1404.0, 1872.0);
+
+    fn pdf(zoom: ZoomMode) -> DocumentContent {
+        DocumentContent {
+            file_type: FileType::Pdf,
+            zoom,
+            ..DocumentContent::default()
+        }
+    }
+
+    #[test]
+    fn test_redirections() {
+        let content = DocumentContent::from_json(
+            r#"{
+                "fileType": "pdf",
+                "cPages": {
+                    "pages": [
+                        { "id": "a", "redir": { "timestamp": "1:2", "value": 0 } },
+                        { "id": "b" },
+                        { "id": "c", "deleted": { "timestamp": "1:3", "value": 1 } },
+                        { "id": "d", "redir": { "timestamp": "1:2", "value": 2 } }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(content.file_type, FileType::Pdf);
+        assert_eq!(content.source_page(0), Some(0));
+        assert_eq!(content.source_page(1), None);
+        assert_eq!(content.source_page(2), Some(2));
+
+        let old = DocumentContent::from_json(
+            r#"{ "fileType": "epub", "redirectionPageMap": [0, -1, 1] }"#,
+        )
+        .unwrap();
+        assert_eq!(old.source_page(1), None);
+        assert_eq!(old.source_page(2), Some(1));
+    }
+
+    #[test]
+    fn test_notebooks_have_no_source() {
+        let content = DocumentContent::from_json(r#"{ "fileType": "notebook" }"#).unwrap();
+
+        assert!(!content.has_source());
+        assert_eq!(content.source_page(0), None);
+    }
+
+    #[test]
+    fn test_best_fit_centers() {
+        let placement = pdf(ZoomMode::BestFit).placement(LETTER, PAGE);
+        let scale = 1404.0 / 612.0;
+
+        let (x, y) = placement.apply(0.0, 0.0);
+        assert!(x.abs() < 1e-9);
+        assert!((y - (1872.0 - 792.0 * scale) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fit_to_width_starts_at_top() {
+        let content = pdf(ZoomMode::FitToWidth);
+
+        // a wide page is scaled up to fill the width
+        let placement = content.placement((1000.0, 500.0), PAGE);
+        assert_eq!(placement.apply(0.0, 0.0), (0.0, 0.0));
+        assert!((placement.apply(1000.0, 0.0).0 - 1404.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_custom_zoom() {
+        let content = DocumentContent::from_json(
+            r#"{
+                "fileType": "pdf",
+                "zoomMode": "customFit",
+                "customZoomScale": 2.0,
+                "customZoomCenterX": 100.0,
+                "customZoomCenterY": 0.0
+            }"#,
+        )
+        .unwrap();
+
+        let placement = content.placement(LETTER, PAGE);
+        let (x, y) = placement.apply(306.0, 396.0);
+
+        // the center of the page moved left of the screen's center
+        assert!((x - 602.0).abs() < 1e-9);
+        assert!((y - 936.0).abs() < 1e-9);
+        assert!((placement.length_scale() - 2.0 * 1404.0 / 612.0).abs() < 1e-9);
+    }
+}
@@ -1,9 +1,8 @@
-use log::warn;
+#![allow(non_camel_case_types, clippy::upper_case_acronyms)]
 
-pub const DEVICE_WIDTH: f64 = 1404.0;
-pub const DEVICE_HEIGHT: f64 = 1872.0;
+use log::warn;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum PenType {
     BallPoint,
     Marker,
@@ -21,53 +20,252 @@ pub enum PenType {
     UNKNWON,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum PenColor {
     BLACK,
     GRAY,
     WHITE,
+    BLUE,
+    RED,
+    YELLOW,
+    GREEN,
+    PINK,
+    HIGHLIGHT_YELLOW,
+    HIGHLIGHT_GREEN,
+    HIGHLIGHT_PINK,
+    HIGHLIGHT_GRAY,
+    /// An arbitrary colour as (red, green, blue, alpha)
+    RGBA(u8, u8, u8, u8),
 }
 
 impl From<&String> for PenColor {
     fn from(color: &String) -> Self {
-        match color.as_str() {
-            "Black" => PenColor::BLACK,
-            "White" => PenColor::WHITE,
-            _ => PenColor::GRAY,
+        PenColor::from_name(color).unwrap_or_else(|| {
+            warn!("Could not identify color {}", color);
+            PenColor::GRAY
+        })
+    }
+}
+
+impl From<u32> for PenColor {
+    /// Maps the colour codes stored in .rm page files.
+    fn from(code: u32) -> Self {
+        match code {
+            0 => PenColor::BLACK,
+            1 | 8 => PenColor::GRAY,
+            2 => PenColor::WHITE,
+            3 | 13 => PenColor::YELLOW,
+            4 | 10 => PenColor::GREEN,
+            5 => PenColor::PINK,
+            6 => PenColor::BLUE,
+            7 => PenColor::RED,
+            9 => PenColor::HIGHLIGHT_YELLOW,
+            11 => PenColor::RGBA(0, 188, 212, 255),
+            12 => PenColor::RGBA(194, 24, 91, 255),
+            c => {
+                warn!("Could not identify color code {}", c);
+                PenColor::GRAY
+            }
         }
     }
 }
 
 impl PenColor {
+    /// Looks up a colour by the name the tablet uses or as a hex value.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Black" => Some(PenColor::BLACK),
+            "Grey" | "Gray" => Some(PenColor::GRAY),
+            "White" => Some(PenColor::WHITE),
+            "Blue" => Some(PenColor::BLUE),
+            "Red" => Some(PenColor::RED),
+            "Yellow" => Some(PenColor::YELLOW),
+            "Green" => Some(PenColor::GREEN),
+            "Pink" => Some(PenColor::PINK),
+            "HighlighterYellow" | "HighlightYellow" => Some(PenColor::HIGHLIGHT_YELLOW),
+            "HighlighterGreen" | "HighlightGreen" => Some(PenColor::HIGHLIGHT_GREEN),
+            "HighlighterPink" | "HighlightPink" => Some(PenColor::HIGHLIGHT_PINK),
+            "HighlighterGray" | "HighlighterGrey" | "HighlightGray" => {
+                Some(PenColor::HIGHLIGHT_GRAY)
+            }
+            c => PenColor::from_hex(c),
+        }
+    }
+
+    /// Creates a colour from a packed 0xAARRGGBB value.
+    pub fn from_argb(argb: u32) -> Self {
+        PenColor::RGBA(
+            (argb >> 16) as u8,
+            (argb >> 8) as u8,
+            argb as u8,
+            (argb >> 24) as u8,
+        )
+    }
+
+    /// Parses colours given as `#RRGGBB` or `#AARRGGBB`.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#')?;
+        let value = u32::from_str_radix(hex, 16).ok()?;
+
+        match hex.len() {
+            6 => Some(PenColor::from_argb(0xFF00_0000 | value)),
+            8 => Some(PenColor::from_argb(value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the name the tablet uses for this colour. Arbitrary colours
+    /// are written as `#AARRGGBB`.
+    pub fn identifier(&self) -> String {
+        match self {
+            PenColor::BLACK => "Black".into(),
+            PenColor::GRAY => "Gray".into(),
+            PenColor::WHITE => "White".into(),
+            PenColor::BLUE => "Blue".into(),
+            PenColor::RED => "Red".into(),
+            PenColor::YELLOW => "Yellow".into(),
+            PenColor::GREEN => "Green".into(),
+            PenColor::PINK => "Pink".into(),
+            PenColor::HIGHLIGHT_YELLOW => "HighlighterYellow".into(),
+            PenColor::HIGHLIGHT_GREEN => "HighlighterGreen".into(),
+            PenColor::HIGHLIGHT_PINK => "HighlighterPink".into(),
+            PenColor::HIGHLIGHT_GRAY => "HighlighterGray".into(),
+            PenColor::RGBA(r, g, b, a) => format!("#{:02X}{:02X}{:02X}{:02X}", a, r, g, b),
+        }
+    }
+
     pub fn as_rgb(&self) -> (f64, f64, f64) {
+        let (r, g, b, _) = self.as_rgba();
+        (r, g, b)
+    }
+
+    pub fn as_rgba(&self) -> (f64, f64, f64, f64) {
         match self {
-            PenColor::BLACK => (0.0, 0.0, 0.0),
-            PenColor::WHITE => (1.0, 1.0, 1.0),
-            PenColor::GRAY => (0.5, 0.5, 0.5),
+            PenColor::BLACK => (0.0, 0.0, 0.0, 1.0),
+            PenColor::WHITE => (1.0, 1.0, 1.0, 1.0),
+            PenColor::GRAY => (0.5, 0.5, 0.5, 1.0),
+            PenColor::BLUE => (0.306, 0.412, 0.788, 1.0),
+            PenColor::RED => (0.702, 0.243, 0.224, 1.0),
+            PenColor::YELLOW => (0.988, 0.773, 0.0, 1.0),
+            PenColor::GREEN => (0.0, 0.596, 0.275, 1.0),
+            PenColor::PINK => (0.906, 0.361, 0.627, 1.0),
+            PenColor::HIGHLIGHT_YELLOW => (1.0, 0.929, 0.459, 1.0),
+            PenColor::HIGHLIGHT_GREEN => (0.675, 1.0, 0.522, 1.0),
+            PenColor::HIGHLIGHT_PINK => (1.0, 0.522, 0.784, 1.0),
+            PenColor::HIGHLIGHT_GRAY => (0.788, 0.788, 0.788, 1.0),
+            PenColor::RGBA(r, g, b, a) => (
+                *r as f64 / 255.0,
+                *g as f64 / 255.0,
+                *b as f64 / 255.0,
+                *a as f64 / 255.0,
+            ),
         }
     }
 }
 
 impl From<&String> for PenType {
     fn from(identifier: &String) -> Self {
-        match identifier.as_str() {
-            "Pencilv2" => PenType::TiltPencil,
-            "SharpPencilv2" => PenType::SharpPencil,
-            e => {
-                warn!("Could not identify type {}", e);
+        PenType::from_identifier(identifier).unwrap_or_else(|| {
+            warn!("Could not identify type {}", identifier);
+            PenType::UNKNWON
+        })
+    }
+}
+
+impl PenType {
+    /// Maps the brush identifiers of the v1 and v2 tool sets to a pen type.
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        match identifier {
+            "Brush" | "Paintbrush" | "Brushv2" | "Paintbrushv2" => Some(PenType::Brush),
+            "Pencil" | "TiltPencil" | "Pencilv2" => Some(PenType::TiltPencil),
+            "SharpPencil" | "MechanicalPencil" | "SharpPencilv2" | "MechanicalPencilv2" => {
+                Some(PenType::SharpPencil)
+            }
+            "Ballpoint" | "BallPoint" | "Ballpointv2" | "BallPointv2" => Some(PenType::BallPoint),
+            "Marker" | "Markerv2" => Some(PenType::Marker),
+            "Fineliner" | "Finelinerv2" => Some(PenType::Fineliner),
+            "Highlighter" | "Highlighterv2" => Some(PenType::Highlighter),
+            "Calligraphy" | "CalligraphyPen" | "Calligraphyv2" => Some(PenType::Calligraphy),
+            "Pen" | "Penv2" => Some(PenType::Pen),
+            "Eraser" | "Eraserv2" => Some(PenType::Eraser),
+            "EraseArea" | "EraserArea" | "EraseAreav2" => Some(PenType::EraseArea),
+            "EraseAll" | "ClearPage" => Some(PenType::EraseAll),
+            "SelectionBrush" | "SelectionBrushv2" => Some(PenType::SelectionBrush),
+            _ => None,
+        }
+    }
+
+    /// Returns the identifier the v2 tool set uses for this pen.
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            PenType::BallPoint => "Ballpointv2",
+            PenType::Marker => "Markerv2",
+            PenType::Fineliner => "Finelinerv2",
+            PenType::SharpPencil => "SharpPencilv2",
+            PenType::TiltPencil => "Pencilv2",
+            PenType::Brush => "Paintbrushv2",
+            PenType::Highlighter => "Highlighterv2",
+            PenType::Eraser => "Eraser",
+            PenType::EraseArea => "EraseArea",
+            PenType::EraseAll => "EraseAll",
+            PenType::Calligraphy => "Calligraphy",
+            PenType::Pen => "Pen",
+            PenType::SelectionBrush => "SelectionBrush",
+            PenType::UNKNWON => "Unknown",
+        }
+    }
+}
+
+impl From<u32> for PenType {
+    /// Maps the tool codes stored in .rm page files.
+    fn from(code: u32) -> Self {
+        match code {
+            0 | 12 => PenType::Brush,
+            1 | 14 => PenType::TiltPencil,
+            2 | 15 => PenType::BallPoint,
+            3 | 16 => PenType::Marker,
+            4 | 17 => PenType::Fineliner,
+            5 | 18 => PenType::Highlighter,
+            6 => PenType::Eraser,
+            7 | 13 => PenType::SharpPencil,
+            8 => PenType::EraseArea,
+            9 => PenType::EraseAll,
+            10 | 11 => PenType::SelectionBrush,
+            21 => PenType::Calligraphy,
+            c => {
+                warn!("Could not identify tool code {}", c);
                 PenType::UNKNWON
             }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Point {
     pub speed: f64,
     pub width: f64,
     pub x: f64,
     pub y: f64,
     pub pressure: f64,
+    /// The direction the pen is pointing to in radians
+    pub direction: f64,
+    /// The angle between pen and the display normal in radians, if reported
+    pub tilt: Option<f64>,
+}
+
+impl Point {
+    /// Creates a point without speed, direction and tilt.
+    pub fn new(x: f64, y: f64, width: f64, pressure: f64) -> Self {
+        Point {
+            speed: 0.0,
+            width,
+            x,
+            y,
+            pressure,
+            direction: 0.0,
+            tilt: None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -82,9 +280,111 @@ impl From<Point> for Vector2D {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub points: Vec<Point>,
     pub brush: PenType,
     pub color: PenColor,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1_identifiers() {
+        let types = [
+            ("Paintbrush", PenType::Brush),
+            ("Pencil", PenType::TiltPencil),
+            ("SharpPencil", PenType::SharpPencil),
+            ("Ballpoint", PenType::BallPoint),
+            ("Marker", PenType::Marker),
+            ("Fineliner", PenType::Fineliner),
+            ("Highlighter", PenType::Highlighter),
+            ("Eraser", PenType::Eraser),
+            ("EraseArea", PenType::EraseArea),
+            ("EraseAll", PenType::EraseAll),
+        ];
+
+        for (identifier, expected) in types {
+            assert_eq!(PenType::from(&identifier.to_string()), expected);
+        }
+    }
+
+    #[test]
+    fn test_v2_identifiers() {
+        let types = [
+            ("Paintbrushv2", PenType::Brush),
+            ("Pencilv2", PenType::TiltPencil),
+            ("SharpPencilv2", PenType::SharpPencil),
+            ("Ballpointv2", PenType::BallPoint),
+            ("Markerv2", PenType::Marker),
+            ("Finelinerv2", PenType::Fineliner),
+            ("Highlighterv2", PenType::Highlighter),
+            ("Calligraphy", PenType::Calligraphy),
+        ];
+
+        for (identifier, expected) in types {
+            assert_eq!(PenType::from(&identifier.to_string()), expected);
+        }
+    }
+
+    #[test]
+    fn test_named_colors() {
+        assert_eq!(PenColor::from(&"Blue".to_string()), PenColor::BLUE);
+        assert_eq!(PenColor::from(&"Grey".to_string()), PenColor::GRAY);
+        assert_eq!(
+            PenColor::from(&"HighlighterYellow".to_string()),
+            PenColor::HIGHLIGHT_YELLOW
+        );
+    }
+
+    #[test]
+    fn test_hex_colors() {
+        assert_eq!(
+            PenColor::from(&"#FF8000".to_string()),
+            PenColor::RGBA(255, 128, 0, 255)
+        );
+        assert_eq!(
+            PenColor::from(&"#80FF8000".to_string()),
+            PenColor::RGBA(255, 128, 0, 128)
+        );
+        assert_eq!(PenColor::from(&"#FF80".to_string()), PenColor::GRAY);
+    }
+
+    #[test]
+    fn test_argb() {
+        let (r, g, b, a) = PenColor::from_argb(0x80FF_0000).as_rgba();
+
+        assert_eq!((r, g, b), (1.0, 0.0, 0.0));
+        assert!((a - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_identifiers_round_trip() {
+        let types = [
+            PenType::BallPoint,
+            PenType::Marker,
+            PenType::Fineliner,
+            PenType::SharpPencil,
+            PenType::TiltPencil,
+            PenType::Brush,
+            PenType::Highlighter,
+            PenType::Calligraphy,
+            PenType::Eraser,
+        ];
+
+        for pen in types {
+            assert_eq!(PenType::from(&pen.identifier().to_string()), pen);
+        }
+
+        for color in [PenColor::HIGHLIGHT_PINK, PenColor::RGBA(1, 2, 3, 4)] {
+            assert_eq!(PenColor::from(&color.identifier()), color);
+        }
+    }
+
+    #[test]
+    fn test_unknown_identifier() {
+        assert_eq!(PenType::from(&"Crayon".to_string()), PenType::UNKNWON);
+    }
+}
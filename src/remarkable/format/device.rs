@@ -0,0 +1,327 @@
+use std::f64::consts::FRAC_PI_2;
+
+use log::warn;
+
+/// The tablets that can share their screen.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DeviceModel {
+    Remarkable1,
+    Remarkable2,
+    PaperPro,
+}
+
+/// The size of the coordinate space a tablet reports its lines in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceProfile {
+    pub model: DeviceModel,
+    /// The width of a portrait page in tablet units
+    pub width: f64,
+    /// The height of a portrait page in tablet units
+    pub height: f64,
+    /// The pixel density of the screen
+    pub dpi: f64,
+}
+
+/// How a page is held while writing on it.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Orientation {
+    #[default]
+    Portrait,
+    /// The tablet is turned a quarter clockwise so the top of the screen
+    /// points to the right
+    Landscape,
+}
+
+/// An affine map from tablet coordinates to view coordinates, laid out like
+/// a cairo matrix: `x' = xx * x + xy * y + x0` and `y' = yx * x + yy * y + y0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub xx: f64,
+    pub yx: f64,
+    pub xy: f64,
+    pub yy: f64,
+    pub x0: f64,
+    pub y0: f64,
+}
+
+impl DeviceModel {
+    /// Maps the device names used in document metadata and by the cloud.
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        match identifier {
+            "reMarkable 1.0" | "reMarkable1" | "rm1" => Some(DeviceModel::Remarkable1),
+            "reMarkable 2.0" | "reMarkable2" | "rm2" => Some(DeviceModel::Remarkable2),
+            "reMarkable Ferrari" | "reMarkable Paper Pro" | "PaperPro" | "paperpro" => {
+                Some(DeviceModel::PaperPro)
+            }
+            d => {
+                warn!("Could not identify device {}", d);
+                None
+            }
+        }
+    }
+
+    pub fn profile(&self) -> DeviceProfile {
+        match self {
+            DeviceModel::Remarkable1 | DeviceModel::Remarkable2 => DeviceProfile {
+                model: *self,
+                width: 1404.0,
+                height: 1872.0,
+                dpi: 226.0,
+            },
+            DeviceModel::PaperPro => DeviceProfile {
+                model: *self,
+                width: 1620.0,
+                height: 2160.0,
+                dpi: 229.0,
+            },
+        }
+    }
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        DeviceModel::Remarkable2.profile()
+    }
+}
+
+impl DeviceProfile {
+    /// The size of a page in tablet units as it is shown to the user.
+    pub fn page_size(&self, orientation: Orientation) -> (f64, f64) {
+        match orientation {
+            Orientation::Portrait => (self.width, self.height),
+            Orientation::Landscape => (self.height, self.width),
+        }
+    }
+
+    /// Maps tablet coordinates onto a view showing the page in the given
+    /// orientation, scaled by the given factor.
+    pub fn transform(&self, orientation: Orientation, scale: f64) -> Transform {
+        let rotation = match orientation {
+            Orientation::Portrait => Transform::identity(),
+            // the top edge of the screen ends up on the right of the view
+            Orientation::Landscape => {
+                Transform::rotation(FRAC_PI_2).then(&Transform::translation(self.height, 0.0))
+            }
+        };
+
+        rotation.then(&Transform::scale(scale))
+    }
+}
+
+impl From<&str> for Orientation {
+    /// Parses the orientation stored in the document metadata.
+    fn from(orientation: &str) -> Self {
+        match orientation {
+            "landscape" => Orientation::Landscape,
+            "portrait" | "" => Orientation::Portrait,
+            o => {
+                warn!("Could not identify orientation {}", o);
+                Orientation::Portrait
+            }
+        }
+    }
+}
+
+impl Orientation {
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            Orientation::Portrait => "portrait",
+            Orientation::Landscape => "landscape",
+        }
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform::scale(1.0)
+    }
+
+    pub fn scale(factor: f64) -> Self {
+        Transform {
+            xx: factor,
+            yx: 0.0,
+            xy: 0.0,
+            yy: factor,
+            x0: 0.0,
+            y0: 0.0,
+        }
+    }
+
+    pub fn translation(x: f64, y: f64) -> Self {
+        Transform {
+            x0: x,
+            y0: y,
+            ..Transform::identity()
+        }
+    }
+
+    /// Rotates clockwise on screen by the given angle in radians.
+    pub fn rotation(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+
+        Transform {
+            xx: cos,
+            yx: sin,
+            xy: -sin,
+            yy: cos,
+            x0: 0.0,
+            y0: 0.0,
+        }
+    }
+
+    /// Turns a box of the given size clockwise by the given number of quarter
+    /// turns, keeping its top left corner at the origin.
+    pub fn quarter_turns(turns: u8, width: f64, height: f64) -> Self {
+        let (xx, yx, xy, yy, x0, y0) = match turns % 4 {
+            0 => (1.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+            1 => (0.0, 1.0, -1.0, 0.0, height, 0.0),
+            2 => (-1.0, 0.0, 0.0, -1.0, width, height),
+            _ => (0.0, -1.0, 1.0, 0.0, 0.0, width),
+        };
+
+        Transform {
+            xx,
+            yx,
+            xy,
+            yy,
+            x0,
+            y0,
+        }
+    }
+
+    /// Returns the transform applying `self` first and `next` afterwards.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            xx: next.xx * self.xx + next.xy * self.yx,
+            yx: next.yx * self.xx + next.yy * self.yx,
+            xy: next.xx * self.xy + next.xy * self.yy,
+            yy: next.yx * self.xy + next.yy * self.yy,
+            x0: next.xx * self.x0 + next.xy * self.y0 + next.x0,
+            y0: next.yx * self.x0 + next.yy * self.y0 + next.y0,
+        }
+    }
+
+    /// Maps a point from tablet to view coordinates.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.xx * x + self.xy * y + self.x0,
+            self.yx * x + self.yy * y + self.y0,
+        )
+    }
+
+    /// Returns the inverse transform, mapping view to tablet coordinates.
+    pub fn invert(&self) -> Option<Transform> {
+        let determinant = self.xx * self.yy - self.xy * self.yx;
+
+        if determinant.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let xx = self.yy / determinant;
+        let xy = -self.xy / determinant;
+        let yx = -self.yx / determinant;
+        let yy = self.xx / determinant;
+
+        Some(Transform {
+            xx,
+            yx,
+            xy,
+            yy,
+            x0: -(xx * self.x0 + xy * self.y0),
+            y0: -(yx * self.x0 + yy * self.y0),
+        })
+    }
+
+    /// The factor lengths such as stroke widths are scaled by.
+    pub fn length_scale(&self) -> f64 {
+        (self.xx * self.yy - self.xy * self.yx).abs().sqrt()
+    }
+
+    /// The angle directions are rotated by.
+    pub fn angle(&self) -> f64 {
+        self.yx.atan2(self.xx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::abs;
+
+    use super::*;
+
+    fn assert_close((x, y): (f64, f64), (ex, ey): (f64, f64)) {
+        assert!(
+            abs(x - ex) < 1e-9 && abs(y - ey) < 1e-9,
+            "Expected ({}, {}), got ({}, {})",
+            ex,
+            ey,
+            x,
+            y
+        );
+    }
+
+    #[test]
+    fn test_portrait_scales() {
+        let transform = DeviceModel::Remarkable2
+            .profile()
+            .transform(Orientation::Portrait, 0.5);
+
+        assert_close(transform.apply(1404.0, 1872.0), (702.0, 936.0));
+        assert_eq!(transform.length_scale(), 0.5);
+        assert_eq!(transform.angle(), 0.0);
+    }
+
+    #[test]
+    fn test_landscape_rotates_into_page() {
+        let profile = DeviceModel::Remarkable2.profile();
+        let transform = profile.transform(Orientation::Landscape, 1.0);
+
+        // the top left corner of the screen is the top right corner of the page
+        assert_close(transform.apply(0.0, 0.0), (1872.0, 0.0));
+        assert_close(transform.apply(0.0, 1872.0), (0.0, 0.0));
+        assert_close(transform.apply(1404.0, 1872.0), (0.0, 1404.0));
+        assert_eq!(profile.page_size(Orientation::Landscape), (1872.0, 1404.0));
+        assert!(abs(transform.angle() - FRAC_PI_2) < 1e-9);
+    }
+
+    #[test]
+    fn test_quarter_turns() {
+        let turned = Transform::quarter_turns(1, 100.0, 200.0);
+        assert_close(turned.apply(0.0, 0.0), (200.0, 0.0));
+        assert_close(turned.apply(100.0, 200.0), (0.0, 100.0));
+
+        let upside_down = Transform::quarter_turns(2, 100.0, 200.0);
+        assert_close(upside_down.apply(0.0, 0.0), (100.0, 200.0));
+
+        let back = Transform::quarter_turns(3, 100.0, 200.0);
+        assert_close(back.apply(0.0, 0.0), (0.0, 100.0));
+        assert_close(back.apply(100.0, 200.0), (200.0, 0.0));
+        assert_eq!(
+            Transform::quarter_turns(4, 100.0, 200.0),
+            Transform::identity()
+        );
+    }
+
+    #[test]
+    fn test_invert() {
+        let transform = DeviceModel::PaperPro
+            .profile()
+            .transform(Orientation::Landscape, 0.4);
+        let inverse = transform.invert().unwrap();
+
+        let (x, y) = transform.apply(300.0, 1200.0);
+        assert_close(inverse.apply(x, y), (300.0, 1200.0));
+        assert!(Transform::scale(0.0).invert().is_none());
+    }
+
+    #[test]
+    fn test_profiles() {
+        assert_eq!(DeviceModel::Remarkable1.profile().width, 1404.0);
+        assert_eq!(DeviceModel::PaperPro.profile().height, 2160.0);
+        assert_eq!(
+            DeviceModel::from_identifier("reMarkable Ferrari"),
+            Some(DeviceModel::PaperPro)
+        );
+        assert_eq!(Orientation::from("landscape"), Orientation::Landscape);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt::{Display, Formatter};
+
+/// How forgiving the parsers are about incomplete data.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParseMode {
+    /// Every expected field has to be present and known
+    Strict,
+    /// Missing fields and unknown identifiers are replaced by defaults
+    Lenient,
+}
+
+/// Describes where and why parsing data from the tablet failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+    /// The path of the offending field, e.g. `lines.points[3].x`
+    pub path: String,
+    /// What the parser expected to find
+    pub expected: String,
+    /// What has been found instead
+    pub actual: String,
+    /// The offset in bytes from the start of the data
+    pub offset: usize,
+}
+
+impl FormatError {
+    pub fn new(
+        path: impl Into<String>,
+        expected: impl Into<String>,
+        actual: impl Into<String>,
+        offset: usize,
+    ) -> Self {
+        FormatError {
+            path: path.into(),
+            expected: expected.into(),
+            actual: actual.into(),
+            offset,
+        }
+    }
+
+    /// Prefixes the path of the error with the given parent path.
+    pub fn within(mut self, parent: &str) -> Self {
+        self.path = join_path(parent, &self.path);
+        self
+    }
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() {
+            "<root>"
+        } else {
+            self.path.as_str()
+        };
+
+        write!(
+            f,
+            "{}: expected {}, got {} at byte {}",
+            path, self.expected, self.actual, self.offset
+        )
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Joins two parts of a field path. Array indices are appended directly.
+pub fn join_path(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_string()
+    } else if child.is_empty() {
+        parent.to_string()
+    } else if child.starts_with('[') {
+        format!("{}{}", parent, child)
+    } else {
+        format!("{}.{}", parent, child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = FormatError::new("x", "a number", "\"abc\"", 42).within("lines.points[3]");
+
+        assert_eq!(
+            error.to_string(),
+            "lines.points[3].x: expected a number, got \"abc\" at byte 42"
+        );
+    }
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(join_path("", "lines"), "lines");
+        assert_eq!(join_path("lines", "points"), "lines.points");
+        assert_eq!(join_path("lines.points", "[2]"), "lines.points[2]");
+        assert_eq!(join_path("lines", ""), "lines");
+    }
+}
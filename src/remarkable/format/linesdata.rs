@@ -1,113 +1,409 @@
-use std::io::{Error, ErrorKind};
+use log::{trace, warn};
 
-use log::{debug, trace, warn};
-use qt_json::{
-    elements::{JsonBaseValue, JsonValue},
-    QJSONDocument,
+use crate::remarkable::format::{
+    data::{Line, PenColor, PenColor::BLACK, PenType, PenType::TiltPencil, Point},
+    device::Orientation,
+    error::{join_path, FormatError, ParseMode},
+    qbjs::{from_binary, to_binary, QKind, QNode, QValue},
 };
 
-use crate::remarkable::format::data::{
-    Line, PenColor, PenColor::BLACK, PenType, PenType::TiltPencil, Point,
-};
+/// A line drawn on a given page and layer of the open document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveViewUpdate {
+    pub page: u32,
+    pub line: Line,
+    pub layer: u32,
+    pub id: String,
+}
 
-struct LiveViewUpdate {
-    page: u8,
-    line: Line,
-    layer: u8,
-    id: String,
+/// A message sent by the tablet during a live sync session. Only line
+/// updates have been captured from a tablet so far, see `example.bin`. The
+/// other messages are speculative: their types and fields are the ones
+/// [write_live_message] uses, until captures show what the tablet sends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveSyncMessage {
+    /// A line has been added or extended
+    LineUpdated(LiveViewUpdate),
+    /// A line has been removed from the page
+    LineErased { page: u32, layer: u32, id: String },
+    /// The tablet switched to another page
+    PageSwitched { page: u32 },
+    /// The active layer of a page has changed
+    LayerChanged {
+        page: u32,
+        layer: u32,
+        name: Option<String>,
+    },
+    /// A document has been opened on the tablet
+    DocumentOpened {
+        id: String,
+        page: u32,
+        orientation: Orientation,
+    },
+    /// All lines of a page have been removed
+    PageCleared { page: u32 },
 }
 
-pub fn parse_binary_live_lines(data: Vec<u8>) -> Result<Line, std::io::Error> {
-    let json = QJSONDocument::from_binary(data)?;
+/// Parses a complete live sync message from the Qt binary JSON sent by the
+/// tablet. Missing fields are replaced by defaults.
+pub fn parse_live_message(data: Vec<u8>) -> Result<LiveSyncMessage, FormatError> {
+    parse_live_message_with_mode(data, ParseMode::Lenient)
+}
 
-    debug!("Successfully parsed data");
+/// Parses a complete live sync message. In [ParseMode::Strict] every field
+/// the message type requires has to be present.
+pub fn parse_live_message_with_mode(
+    data: Vec<u8>,
+    mode: ParseMode,
+) -> Result<LiveSyncMessage, FormatError> {
+    let document = parse_base_object(&data)?;
+    let values = Fields::new(&document, mode);
+
+    let type_node = document.get("type");
+
+    let message_type = match type_node {
+        Some(QNode {
+            kind: QKind::String(t),
+            ..
+        }) => t.as_str(),
+        Some(t) => return Err(t.unexpected("the message type as a string")),
+        None => "line",
+    };
+
+    trace!("Parsing message of type {}", message_type);
+
+    match message_type {
+        "line" => Ok(LiveSyncMessage::LineUpdated(LiveViewUpdate {
+            page: values.index("page")?,
+            layer: values.index("layer")?,
+            // without an id, updates of different lines could not be told
+            // apart, so the message is dropped even in lenient mode
+            id: values
+                .string("id")?
+                .ok_or_else(|| values.missing("id", "the id of the line"))?,
+            line: parse_line(parse_lines_object(&document)?, mode)?,
+        })),
+        "erase" => Ok(LiveSyncMessage::LineErased {
+            page: values.index("page")?,
+            layer: values.index("layer")?,
+            id: values
+                .string("id")?
+                .ok_or_else(|| values.missing("id", "the id of the erased line"))?,
+        }),
+        "page" => Ok(LiveSyncMessage::PageSwitched {
+            page: values.index("page")?,
+        }),
+        "layer" => Ok(LiveSyncMessage::LayerChanged {
+            page: values.index("page")?,
+            layer: values.index("layer")?,
+            name: values.string("name")?,
+        }),
+        "document" => Ok(LiveSyncMessage::DocumentOpened {
+            id: values
+                .string("document")?
+                .ok_or_else(|| values.missing("document", "the id of the document"))?,
+            page: values.index("page")?,
+            orientation: values
+                .string("orientation")?
+                .map(|o| Orientation::from(o.as_str()))
+                .unwrap_or_default(),
+        }),
+        "clear" => Ok(LiveSyncMessage::PageCleared {
+            page: values.index("page")?,
+        }),
+        _ => Err(type_node.unwrap().unexpected("a known message type")),
+    }
+}
 
-    let base = match json.base {
-        JsonBaseValue::Array(a) => {
-            warn!("Did not expect an Array as JSON");
-            trace!("{:?}", a);
-            Err(Error::new(
-                ErrorKind::InvalidData,
-                "Did not expect an array",
-            ))
-        }
-        JsonBaseValue::Object(o) => Ok(o),
-    }?;
+/// Parses only the line contained in a live sync message.
+pub fn parse_binary_live_lines(data: Vec<u8>) -> Result<Line, FormatError> {
+    parse_binary_live_lines_with_mode(data, ParseMode::Lenient)
+}
 
-    let base_info = base.values;
+/// Parses only the line contained in a live sync message using the given
+/// mode.
+pub fn parse_binary_live_lines_with_mode(
+    data: Vec<u8>,
+    mode: ParseMode,
+) -> Result<Line, FormatError> {
+    let document = parse_base_object(&data)?;
 
-    let lines = match base_info.get("lines") {
-        Some(JsonValue::Object(a)) => Ok(a),
-        Some(a) => Err(Error::new(
-            ErrorKind::InvalidData,
-            format!("Expected an Object. Got: {:?}", a),
-        )),
-        None => Err(Error::new(
-            ErrorKind::InvalidData,
-            "Did not expect no lines",
-        )),
-    }?;
+    parse_line(parse_lines_object(&document)?, mode)
+}
 
-    let mut points = Vec::new();
+fn parse_base_object(data: &[u8]) -> Result<QNode, FormatError> {
+    let document = from_binary(data)?;
 
-    if let Some(JsonValue::Array(array_entries)) = lines.values.get("points") {
-        for line in array_entries {
-            let addable = match line {
-                JsonValue::Object(o) => {
-                    let vals = &(o).values;
-
-                    let _direction = parse_to_number(vals.get("direction"))?;
-                    let pressure = parse_to_number(vals.get("p"))?;
-                    let speed = parse_to_number(vals.get("speed"))?;
-                    let width = parse_to_number(vals.get("width"))?;
-                    let x = parse_to_number(vals.get("x"))?;
-                    let y = parse_to_number(vals.get("y"))?;
-
-                    Ok(Point {
-                        width: *width,
-                        speed: *speed,
-                        pressure: *pressure,
-                        y: *y,
-                        x: *x,
-                    })
-                }
-                _ => Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Expected points to be an object",
-                )),
-            }?;
-            points.push(addable);
+    trace!("Successfully parsed data");
+
+    match document.kind {
+        QKind::Object(_) => Ok(document),
+        _ => {
+            warn!("Did not expect {} as JSON", document.describe());
+            Err(document.unexpected("an object"))
         }
-    } else {
-        warn!("Could not parse points. Skipping");
     }
+}
 
-    let mut brush: Option<PenType> = None;
-
-    if let Some(JsonValue::String(brush_type)) = lines.values.get("brush") {
-        brush = Some(brush_type.into());
+fn parse_lines_object(document: &QNode) -> Result<&QNode, FormatError> {
+    match document.get("lines") {
+        Some(
+            lines @ QNode {
+                kind: QKind::Object(_),
+                ..
+            },
+        ) => Ok(lines),
+        Some(lines) => Err(lines.unexpected("an object")),
+        None => Err(FormatError::new(
+            "lines",
+            "an object",
+            "no value",
+            document.offset,
+        )),
     }
+}
 
-    let mut color: Option<PenColor> = None;
+fn parse_line(lines: &QNode, mode: ParseMode) -> Result<Line, FormatError> {
+    let values = Fields::new(lines, mode);
+    let mut points = Vec::new();
 
-    if let Some(JsonValue::String(color_type)) = lines.values.get("color") {
-        color = Some(color_type.into());
+    match lines.get("points") {
+        Some(QNode {
+            kind: QKind::Array(entries),
+            ..
+        }) => {
+            for point in entries {
+                if !matches!(point.kind, QKind::Object(_)) {
+                    return Err(point.unexpected("a point object"));
+                }
+
+                let point_values = Fields::new(point, mode);
+
+                points.push(Point {
+                    direction: point_values.number("direction")?,
+                    pressure: point_values.number("p")?,
+                    speed: point_values.number("speed")?,
+                    width: point_values.number("width")?,
+                    x: point_values.number("x")?,
+                    y: point_values.number("y")?,
+                    tilt: point_values.optional_number("tilt")?,
+                });
+            }
+        }
+        Some(p) => return Err(p.unexpected("an array of points")),
+        None if mode == ParseMode::Strict => {
+            return Err(values.missing("points", "an array of points"))
+        }
+        None => warn!("Could not parse points. Skipping"),
     }
 
+    let brush = match values.string("brush")? {
+        Some(identifier) => match PenType::from_identifier(&identifier) {
+            Some(brush) => brush,
+            None if mode == ParseMode::Strict => {
+                return Err(lines
+                    .get("brush")
+                    .map(|node| node.unexpected("a known brush"))
+                    .unwrap_or_else(|| values.missing("brush", "a known brush")))
+            }
+            None => PenType::from(&identifier),
+        },
+        None if mode == ParseMode::Strict => return Err(values.missing("brush", "a brush")),
+        None => TiltPencil,
+    };
+
+    let color = match lines.get("color") {
+        Some(QNode {
+            kind: QKind::String(name),
+            ..
+        }) => match PenColor::from_name(name) {
+            Some(color) => color,
+            None if mode == ParseMode::Strict => {
+                return Err(lines.get("color").unwrap().unexpected("a known color"))
+            }
+            None => PenColor::from(name),
+        },
+        Some(QNode {
+            kind: QKind::Number(argb),
+            ..
+        }) => PenColor::from_argb(*argb as u32),
+        Some(c) => return Err(c.unexpected("a color name or ARGB value")),
+        None if mode == ParseMode::Strict => return Err(values.missing("color", "a color")),
+        None => BLACK,
+    };
+
     Ok(Line {
         points,
-        brush: brush.unwrap_or(TiltPencil),
-        color: color.unwrap_or(BLACK),
+        brush,
+        color,
     })
 }
 
-fn parse_to_number(val: Option<&JsonValue>) -> Result<&f64, Error> {
-    match val.unwrap_or(&JsonValue::Number(0.0)) {
-        JsonValue::Number(n) => Ok(n),
-        v => Err(Error::new(
-            ErrorKind::InvalidData,
-            format!("Expected an f64. Got: {:?}", v),
-        )),
+/// Encodes a live sync message into the Qt binary JSON sent by the tablet.
+pub fn write_live_message(message: &LiveSyncMessage) -> Vec<u8> {
+    let entries: Vec<(&str, QValue)> = match message {
+        LiveSyncMessage::LineUpdated(update) => vec![
+            ("type", "line".into()),
+            ("id", update.id.as_str().into()),
+            ("page", update.page.to_string().into()),
+            ("layer", update.layer.to_string().into()),
+            ("lines", line_to_value(&update.line)),
+        ],
+        LiveSyncMessage::LineErased { page, layer, id } => vec![
+            ("type", "erase".into()),
+            ("id", id.as_str().into()),
+            ("page", page.to_string().into()),
+            ("layer", layer.to_string().into()),
+        ],
+        LiveSyncMessage::PageSwitched { page } => {
+            vec![("type", "page".into()), ("page", page.to_string().into())]
+        }
+        LiveSyncMessage::LayerChanged { page, layer, name } => {
+            let mut entries = vec![
+                ("type", "layer".into()),
+                ("page", page.to_string().into()),
+                ("layer", layer.to_string().into()),
+            ];
+            if let Some(name) = name {
+                entries.push(("name", name.as_str().into()));
+            }
+            entries
+        }
+        LiveSyncMessage::DocumentOpened {
+            id,
+            page,
+            orientation,
+        } => vec![
+            ("type", "document".into()),
+            ("document", id.as_str().into()),
+            ("page", page.to_string().into()),
+            ("orientation", orientation.identifier().into()),
+        ],
+        LiveSyncMessage::PageCleared { page } => {
+            vec![("type", "clear".into()), ("page", page.to_string().into())]
+        }
+    };
+
+    to_binary(&QValue::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    ))
+}
+
+/// Encodes a bare line the way [parse_binary_live_lines] expects it.
+pub fn write_binary_live_lines(line: &Line) -> Vec<u8> {
+    to_binary(&QValue::Object(vec![(
+        "lines".to_string(),
+        line_to_value(line),
+    )]))
+}
+
+fn line_to_value(line: &Line) -> QValue {
+    let points = line
+        .points
+        .iter()
+        .map(|p| {
+            let mut values = vec![
+                ("direction".to_string(), p.direction.into()),
+                ("p".to_string(), p.pressure.into()),
+                ("speed".to_string(), p.speed.into()),
+                ("width".to_string(), p.width.into()),
+                ("x".to_string(), p.x.into()),
+                ("y".to_string(), p.y.into()),
+            ];
+            if let Some(tilt) = p.tilt {
+                values.push(("tilt".to_string(), tilt.into()));
+            }
+            QValue::Object(values)
+        })
+        .collect();
+
+    QValue::Object(vec![
+        ("brush".to_string(), line.brush.identifier().into()),
+        ("color".to_string(), line.color.identifier().into()),
+        ("points".to_string(), QValue::Array(points)),
+    ])
+}
+
+/// Reads the fields of an object, applying the defaults of the parse mode
+/// to missing ones.
+struct Fields<'a> {
+    node: &'a QNode,
+    mode: ParseMode,
+}
+
+impl<'a> Fields<'a> {
+    fn new(node: &'a QNode, mode: ParseMode) -> Self {
+        Fields { node, mode }
+    }
+
+    fn missing(&self, key: &str, expected: &str) -> FormatError {
+        FormatError::new(
+            join_path(&self.node.path, key),
+            expected,
+            "no value",
+            self.node.offset,
+        )
+    }
+
+    /// Returns the default for a missing field in lenient mode.
+    fn default_for<T: Default>(&self, key: &str, expected: &str) -> Result<T, FormatError> {
+        match self.mode {
+            ParseMode::Strict => Err(self.missing(key, expected)),
+            ParseMode::Lenient => {
+                warn!("{}", self.missing(key, expected));
+                Ok(T::default())
+            }
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<f64, FormatError> {
+        match self.optional_number(key)? {
+            Some(n) => Ok(n),
+            None => self.default_for(key, "a number"),
+        }
+    }
+
+    fn optional_number(&self, key: &str) -> Result<Option<f64>, FormatError> {
+        match self.node.get(key) {
+            None => Ok(None),
+            Some(QNode {
+                kind: QKind::Number(n),
+                ..
+            }) => Ok(Some(*n)),
+            Some(v) => Err(v.unexpected("a number")),
+        }
+    }
+
+    /// Pages and layers are sent either as numbers or as numeric strings.
+    fn index(&self, key: &str) -> Result<u32, FormatError> {
+        match self.node.get(key) {
+            None => self.default_for(key, "an index"),
+            Some(QNode {
+                kind: QKind::Number(n),
+                ..
+            }) => Ok(*n as u32),
+            Some(
+                v @ QNode {
+                    kind: QKind::String(s),
+                    ..
+                },
+            ) => s.parse().map_err(|_| v.unexpected("a numeric index")),
+            Some(v) => Err(v.unexpected("an index")),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, FormatError> {
+        match self.node.get(key) {
+            None => Ok(None),
+            Some(QNode {
+                kind: QKind::String(s),
+                ..
+            }) => Ok(Some(s.clone())),
+            Some(v) => Err(v.unexpected("a string")),
+        }
     }
 }
 
@@ -147,6 +443,32 @@ mod tests {
 
         assert!(abs(p0.x - x) < 0.05, "X should be {}, is {}", x, p0.x);
         assert!(abs(p0.y - y) < 0.05, "X should be {}, is {}", y, p0.y);
+        assert!(abs(p0.direction - 1.0103) < 0.001);
+        assert_eq!(p0.tilt, None);
+    }
+
+    #[test]
+    fn test_example_message() {
+        let data = include_bytes!("example.bin");
+
+        let message = parse_live_message(data.to_vec());
+
+        assert!(
+            message.is_ok(),
+            "Message expected to be parsed: {:?}",
+            message
+        );
+
+        match message.unwrap() {
+            LiveSyncMessage::LineUpdated(update) => {
+                assert_eq!(update.page, 0);
+                assert_eq!(update.layer, 0);
+                assert_eq!(update.id, "7887393a-2882-445c-8f91-cddb1c5e046b");
+                assert_eq!(update.line.brush, PenType::TiltPencil);
+                assert_eq!(update.line.points.len(), 81);
+            }
+            m => panic!("Expected a line update. Got: {:?}", m),
+        }
     }
 
     #[test]
@@ -171,4 +493,201 @@ mod tests {
         assert!(abs(p0.x - x) < 0.05, "X should be {}, is {}", x, p0.x);
         assert!(abs(p0.y - y) < 0.05, "X should be {}, is {}", y, p0.y);
     }
+
+    fn round_trip(message: LiveSyncMessage) {
+        let data = write_live_message(&message);
+
+        let parsed = parse_live_message(data);
+
+        assert!(
+            parsed.is_ok(),
+            "Message expected to be parsed: {:?}",
+            parsed
+        );
+        assert_eq!(parsed.unwrap(), message);
+    }
+
+    #[test]
+    fn test_example_round_trip() {
+        let data = include_bytes!("example1.bin");
+
+        let message = parse_live_message(data.to_vec()).unwrap();
+
+        round_trip(message);
+    }
+
+    #[test]
+    fn test_line_round_trip() {
+        let mut line = parse_binary_live_lines(include_bytes!("example.bin").to_vec()).unwrap();
+        line.color = PenColor::RGBA(10, 20, 30, 128);
+        line.points[0].tilt = Some(0.5);
+
+        let parsed = parse_binary_live_lines(write_binary_live_lines(&line));
+
+        assert_eq!(parsed.unwrap(), line);
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        round_trip(LiveSyncMessage::LineErased {
+            page: 3,
+            layer: 1,
+            id: "line".into(),
+        });
+        round_trip(LiveSyncMessage::PageSwitched { page: 12 });
+        round_trip(LiveSyncMessage::LayerChanged {
+            page: 2,
+            layer: 4,
+            name: Some("Ränder".into()),
+        });
+        round_trip(LiveSyncMessage::LayerChanged {
+            page: 2,
+            layer: 4,
+            name: None,
+        });
+        round_trip(LiveSyncMessage::DocumentOpened {
+            id: "document".into(),
+            page: 7,
+            orientation: Orientation::Landscape,
+        });
+        round_trip(LiveSyncMessage::PageCleared { page: 1 });
+    }
+
+    #[test]
+    fn test_rejects_unknown_message_type() {
+        let data = to_binary(&QValue::Object(vec![
+            ("type".into(), "page_changed".into()),
+            ("page".into(), "2".into()),
+        ]));
+
+        let error = parse_live_message(data).unwrap_err();
+        assert_eq!(error.path, "type");
+        assert_eq!(error.expected, "a known message type");
+    }
+
+    #[test]
+    fn test_rejects_lines_without_id() {
+        let lines = [(1.0, 2.0), (30.0, 40.0)].map(|(x, y)| {
+            to_binary(&QValue::Object(vec![
+                ("type".into(), "line".into()),
+                ("page".into(), "0".into()),
+                ("layer".into(), "0".into()),
+                (
+                    "lines".into(),
+                    QValue::Object(vec![
+                        ("brush".into(), "Finelinerv2".into()),
+                        ("color".into(), "Black".into()),
+                        (
+                            "points".into(),
+                            QValue::Array(vec![QValue::Object(vec![
+                                ("x".into(), x.into()),
+                                ("y".into(), y.into()),
+                            ])]),
+                        ),
+                    ]),
+                ),
+            ]))
+        });
+
+        // neither line may end up as an update with an empty id, where the
+        // second would replace the first
+        for line in lines {
+            let error = parse_live_message(line).unwrap_err();
+            assert_eq!(error.path, "id");
+            assert_eq!(error.actual, "no value");
+        }
+    }
+
+    fn incomplete_line_message() -> Vec<u8> {
+        to_binary(&QValue::Object(vec![
+            ("type".into(), "line".into()),
+            ("page".into(), "1".into()),
+            ("layer".into(), "0".into()),
+            ("id".into(), "line".into()),
+            (
+                "lines".into(),
+                QValue::Object(vec![
+                    ("brush".into(), "Finelinerv2".into()),
+                    ("color".into(), "Black".into()),
+                    (
+                        "points".into(),
+                        QValue::Array(vec![
+                            QValue::Object(vec![
+                                ("x".into(), 1.5.into()),
+                                ("y".into(), 2.5.into()),
+                            ]),
+                            QValue::Object(vec![("x".into(), 3.5.into())]),
+                        ]),
+                    ),
+                ]),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn test_lenient_defaults_missing_fields() {
+        let message = parse_live_message(incomplete_line_message()).unwrap();
+
+        match message {
+            LiveSyncMessage::LineUpdated(update) => {
+                assert_eq!(update.page, 1);
+                assert_eq!(update.line.points.len(), 2);
+                assert_eq!(update.line.points[0].pressure, 0.0);
+                assert_eq!(update.line.points[1].y, 0.0);
+            }
+            m => panic!("Expected a line update. Got: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_strict_reports_missing_field() {
+        let data = incomplete_line_message();
+
+        let error = parse_live_message_with_mode(data.clone(), ParseMode::Strict).unwrap_err();
+
+        let document = from_binary(&data).unwrap();
+        let point = match &document.get("lines").unwrap().get("points").unwrap().kind {
+            QKind::Array(points) => points[0].clone(),
+            k => panic!("Expected points. Got: {:?}", k),
+        };
+
+        assert_eq!(error.path, "lines.points[0].direction");
+        assert_eq!(error.actual, "no value");
+        assert_eq!(error.offset, point.offset);
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_brush() {
+        let data = to_binary(&QValue::Object(vec![(
+            "lines".into(),
+            QValue::Object(vec![
+                ("brush".into(), "Crayon".into()),
+                ("color".into(), "Black".into()),
+                ("points".into(), QValue::Array(vec![])),
+            ]),
+        )]));
+
+        let lenient = parse_binary_live_lines(data.clone()).unwrap();
+        let strict = parse_binary_live_lines_with_mode(data, ParseMode::Strict);
+
+        assert_eq!(lenient.brush, PenType::UNKNWON);
+        assert_eq!(strict.unwrap_err().path, "lines.brush");
+    }
+
+    #[test]
+    fn test_type_mismatch_has_offset() {
+        let data = to_binary(&QValue::Object(vec![(
+            "lines".into(),
+            QValue::Object(vec![(
+                "points".into(),
+                QValue::Array(vec![QValue::Object(vec![("x".into(), "left".into())])]),
+            )]),
+        )]));
+
+        let error = parse_binary_live_lines(data.clone()).unwrap_err();
+
+        assert_eq!(error.path, "lines.points[0].x");
+        assert_eq!(error.expected, "a number");
+        assert_eq!(&data[error.offset + 2..error.offset + 6], b"left");
+    }
 }
@@ -1,2 +1,13 @@
+pub mod brush;
+pub mod content;
 pub mod data;
+pub mod device;
+pub mod error;
 pub mod linesdata;
+pub mod outline;
+pub mod qbjs;
+pub mod rmlines;
+pub mod scheme;
+pub mod smoothing;
+pub mod template;
+pub mod texture;
@@ -0,0 +1,488 @@
+use std::f64::consts::PI;
+
+use crate::remarkable::format::{
+    brush::{BrushModel, StrokeStyle, TipShape},
+    data::Point,
+    smoothing::bezier_segments,
+};
+
+/// The number of corners used to approximate a round cap.
+const CAP_SEGMENTS: usize = 8;
+
+/// Points closer than this are merged as they have no usable direction.
+const MIN_DISTANCE: f64 = 1e-6;
+
+/// Opacities closer than this are considered the same.
+const OPACITY_EPSILON: f64 = 1e-3;
+
+/// The filled shape of a stroke in tablet coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrokeOutline {
+    /// The corners of the closed outline polygon
+    pub polygon: Vec<(f64, f64)>,
+    /// The average opacity of the points of the stroke
+    pub opacity: f64,
+    /// The pieces between neighbouring points in stroke order, which
+    /// together cover the polygon
+    pub segments: Vec<OutlineSegment>,
+}
+
+/// The part of an outline between two points. Round tips reach past the
+/// second point, so the next segment covers the seam when it replaces the
+/// ink below it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineSegment {
+    pub polygon: Vec<(f64, f64)>,
+    /// The opacity of the ink between the points
+    pub opacity: f64,
+}
+
+impl StrokeOutline {
+    /// Whether every segment is filled with the same opacity.
+    pub fn is_uniform(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| (segment.opacity - self.opacity).abs() < OPACITY_EPSILON)
+    }
+}
+
+/// A piece of a closed path, continuing from the end of the previous one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathPiece {
+    Line((f64, f64)),
+    /// A cubic Bézier curve through two control points to the given point
+    Curve((f64, f64), (f64, f64), (f64, f64)),
+}
+
+/// The points of a stroke without duplicates, with the direction and
+/// distance of the sides of the stroke at each of them.
+struct Sides {
+    points: Vec<Point>,
+    styles: Vec<StrokeStyle>,
+    /// The unit normals pointing to the left side
+    normals: Vec<(f64, f64)>,
+    /// How far the tip reaches across the stroke
+    radii: Vec<f64>,
+    tip: TipShape,
+    /// The average opacity of all points
+    opacity: f64,
+}
+
+impl Sides {
+    fn new(points: &[Point], model: &BrushModel) -> Option<Self> {
+        let mut centers: Vec<Point> = Vec::with_capacity(points.len());
+        let mut styles = Vec::with_capacity(points.len());
+        let mut opacity = 0.0;
+
+        for point in points {
+            let style = model.style(point);
+            opacity += style.opacity;
+
+            match centers.last() {
+                Some(last) if (point.x - last.x).hypot(point.y - last.y) < MIN_DISTANCE => {}
+                _ => {
+                    centers.push(point.clone());
+                    styles.push(style);
+                }
+            }
+        }
+
+        if centers.is_empty() {
+            return None;
+        }
+
+        let normals: Vec<(f64, f64)> = (0..centers.len())
+            .map(|i| {
+                let a = &centers[i.saturating_sub(1)];
+                let b = &centers[(i + 1).min(centers.len() - 1)];
+                let length = (b.x - a.x).hypot(b.y - a.y);
+
+                if length == 0.0 {
+                    (0.0, 1.0)
+                } else {
+                    (-(b.y - a.y) / length, (b.x - a.x) / length)
+                }
+            })
+            .collect();
+
+        let radii = styles
+            .iter()
+            .zip(&normals)
+            .map(|(style, normal)| reach(style, *normal))
+            .collect();
+
+        Some(Sides {
+            points: centers,
+            styles,
+            normals,
+            radii,
+            tip: model.tip,
+            opacity: opacity / points.len() as f64,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Moves a point of the stroke to the given side at the given center.
+    fn offset(&self, i: usize, (x, y): (f64, f64), side: f64) -> (f64, f64) {
+        let (nx, ny) = self.normals[i];
+        let radius = side * self.radii[i];
+        (x + nx * radius, y + ny * radius)
+    }
+
+    fn left(&self, i: usize) -> (f64, f64) {
+        self.offset(i, (self.points[i].x, self.points[i].y), 1.0)
+    }
+
+    fn right(&self, i: usize) -> (f64, f64) {
+        self.offset(i, (self.points[i].x, self.points[i].y), -1.0)
+    }
+
+    /// The cap from the left to the right side reaching forward from the
+    /// given center.
+    fn end_cap(&self, i: usize) -> Vec<(f64, f64)> {
+        let (nx, ny) = self.normals[i];
+        let point = &self.points[i];
+        cap(self.tip, point.x, point.y, self.radii[i], ny.atan2(nx))
+    }
+
+    /// The cap from the right to the left side around the first center.
+    fn start_cap(&self) -> Vec<(f64, f64)> {
+        let (nx, ny) = self.normals[0];
+        let point = &self.points[0];
+        cap(self.tip, point.x, point.y, self.radii[0], (-ny).atan2(-nx))
+    }
+
+    /// The shape of a stroke of a single point.
+    fn dot(&self) -> Vec<(f64, f64)> {
+        let (point, style) = (&self.points[0], &self.styles[0]);
+        let (x, y) = (point.x, point.y);
+        let radius = style.width / 2.0;
+
+        match self.tip {
+            TipShape::Round => ellipse(x, y, radius, radius * style.aspect, style.angle),
+            TipShape::Chisel => vec![
+                (x - radius, y - radius),
+                (x + radius, y - radius),
+                (x + radius, y + radius),
+                (x - radius, y + radius),
+            ],
+        }
+    }
+}
+
+/// Computes the variable width outline of a stroke. The width at every point
+/// and the shape of the caps follow the brush model.
+pub fn stroke_outline(points: &[Point], model: &BrushModel) -> Option<StrokeOutline> {
+    let sides = Sides::new(points, model)?;
+    let opacity = sides.opacity;
+
+    if sides.len() == 1 {
+        let polygon = sides.dot();
+        let segments = vec![OutlineSegment {
+            polygon: polygon.clone(),
+            opacity,
+        }];
+
+        return Some(StrokeOutline {
+            polygon,
+            opacity,
+            segments,
+        });
+    }
+
+    let count = sides.len();
+    let mut polygon = Vec::with_capacity(2 * count + 2 * (CAP_SEGMENTS + 1));
+    polygon.extend((0..count).map(|i| sides.left(i)));
+    polygon.extend(sides.end_cap(count - 1));
+    polygon.extend((0..count).rev().map(|i| sides.right(i)));
+    polygon.extend(sides.start_cap());
+
+    let segments = (0..count - 1)
+        .map(|i| {
+            let mut polygon = vec![sides.left(i), sides.left(i + 1)];
+            polygon.extend(sides.end_cap(i + 1));
+            polygon.extend(&[sides.right(i + 1), sides.right(i)]);
+            if i == 0 {
+                polygon.extend(sides.start_cap());
+            }
+
+            OutlineSegment {
+                polygon,
+                opacity: (sides.styles[i].opacity + sides.styles[i + 1].opacity) / 2.0,
+            }
+        })
+        .collect();
+
+    Some(StrokeOutline {
+        polygon,
+        opacity,
+        segments,
+    })
+}
+
+/// Computes the outline of a stroke like [stroke_outline], but with sides
+/// that follow the Catmull-Rom spline through the points as Bézier curves.
+/// Vector backends draw these directly instead of sampling the spline.
+pub fn curved_outline(points: &[Point], model: &BrushModel) -> Option<Vec<PathPiece>> {
+    let sides = Sides::new(points, model)?;
+
+    if sides.len() == 1 {
+        return Some(sides.dot().into_iter().map(PathPiece::Line).collect());
+    }
+
+    // the control points are moved to the sides like their ends
+    let curves = bezier_segments(&sides.points);
+    let count = sides.len();
+    let mut path = Vec::with_capacity(2 * count + 2 * (CAP_SEGMENTS + 1));
+
+    path.push(PathPiece::Line(sides.left(0)));
+    for (i, curve) in curves.iter().enumerate() {
+        path.push(PathPiece::Curve(
+            sides.offset(i, curve.control1, 1.0),
+            sides.offset(i + 1, curve.control2, 1.0),
+            sides.left(i + 1),
+        ));
+    }
+
+    path.extend(sides.end_cap(count - 1).into_iter().map(PathPiece::Line));
+
+    path.push(PathPiece::Line(sides.right(count - 1)));
+    for (i, curve) in curves.iter().enumerate().rev() {
+        path.push(PathPiece::Curve(
+            sides.offset(i + 1, curve.control2, -1.0),
+            sides.offset(i, curve.control1, -1.0),
+            sides.right(i),
+        ));
+    }
+
+    path.extend(sides.start_cap().into_iter().map(PathPiece::Line));
+
+    Some(path)
+}
+
+/// How far an elliptic tip reaches along the given unit normal. The major
+/// axis of the tip is its width and points along its angle.
+fn reach(style: &StrokeStyle, (nx, ny): (f64, f64)) -> f64 {
+    let major = style.width / 2.0;
+    let minor = major * style.aspect;
+    let (sin, cos) = style.angle.sin_cos();
+
+    (major * (nx * cos + ny * sin)).hypot(minor * (ny * cos - nx * sin))
+}
+
+/// The corners of the cap around the given end of a stroke, starting at the
+/// given angle. Chisel tips end flat, so the sides are simply joined.
+fn cap(tip: TipShape, x: f64, y: f64, radius: f64, from: f64) -> Vec<(f64, f64)> {
+    match tip {
+        TipShape::Round => arc(x, y, radius, from, from - PI),
+        TipShape::Chisel => vec![],
+    }
+}
+
+/// The corners of an ellipse with the given half axes, rotated by the given
+/// angle.
+fn ellipse(x: f64, y: f64, major: f64, minor: f64, angle: f64) -> Vec<(f64, f64)> {
+    let (sin, cos) = angle.sin_cos();
+
+    (0..2 * CAP_SEGMENTS)
+        .map(|i| {
+            let t = PI * i as f64 / CAP_SEGMENTS as f64;
+            let (u, v) = (major * t.cos(), minor * t.sin());
+            (x + u * cos - v * sin, y + u * sin + v * cos)
+        })
+        .collect()
+}
+
+/// The corners of a circular arc from one angle to another.
+fn arc(x: f64, y: f64, radius: f64, from: f64, to: f64) -> Vec<(f64, f64)> {
+    (0..=CAP_SEGMENTS)
+        .map(|i| {
+            let angle = from + (to - from) * i as f64 / CAP_SEGMENTS as f64;
+            (x + radius * angle.cos(), y + radius * angle.sin())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use num::abs;
+
+    use super::*;
+    use crate::remarkable::format::data::PenType;
+
+    #[test]
+    fn test_straight_line_width() {
+        let points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+        ];
+
+        let outline = stroke_outline(&points, &PenType::Fineliner.model()).unwrap();
+
+        let ys: Vec<f64> = outline.polygon.iter().map(|(_, y)| *y).collect();
+        let max = ys.iter().cloned().fold(f64::MIN, f64::max);
+        let min = ys.iter().cloned().fold(f64::MAX, f64::min);
+
+        assert!(abs(max - 2.0) < 1e-9);
+        assert!(abs(min + 2.0) < 1e-9);
+        assert_eq!(outline.polygon.len(), 2 * 2 + 2 * (CAP_SEGMENTS + 1));
+    }
+
+    #[test]
+    fn test_caps_are_round() {
+        let points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+        ];
+
+        let outline = stroke_outline(&points, &PenType::Fineliner.model()).unwrap();
+
+        let max_x = outline
+            .polygon
+            .iter()
+            .map(|(x, _)| *x)
+            .fold(f64::MIN, f64::max);
+        let min_x = outline
+            .polygon
+            .iter()
+            .map(|(x, _)| *x)
+            .fold(f64::MAX, f64::min);
+
+        assert!(abs(max_x - 12.0) < 1e-9);
+        assert!(abs(min_x + 2.0) < 1e-9);
+    }
+
+    #[test]
+    fn test_chisel_ends_flat() {
+        let points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+        ];
+        let mut model = PenType::Fineliner.model();
+        model.tip = TipShape::Chisel;
+
+        let outline = stroke_outline(&points, &model).unwrap();
+
+        assert_eq!(
+            outline.polygon,
+            vec![(0.0, 2.0), (10.0, 2.0), (10.0, -2.0), (0.0, -2.0)]
+        );
+    }
+
+    #[test]
+    fn test_width_varies() {
+        let points = vec![
+            Point::new(0.0, 0.0, 2.0, 1.0),
+            Point::new(10.0, 0.0, 8.0, 1.0),
+        ];
+
+        let outline = stroke_outline(&points, &PenType::Fineliner.model()).unwrap();
+
+        assert_eq!(outline.polygon[0], (0.0, 1.0));
+        assert_eq!(outline.polygon[1], (10.0, 4.0));
+    }
+
+    #[test]
+    fn test_single_point_is_a_dot() {
+        let points = vec![
+            Point::new(5.0, 5.0, 2.0, 1.0),
+            Point::new(5.0, 5.0, 2.0, 1.0),
+        ];
+
+        let outline = stroke_outline(&points, &PenType::Fineliner.model()).unwrap();
+
+        assert!(outline
+            .polygon
+            .iter()
+            .all(|(x, y)| abs((x - 5.0).hypot(y - 5.0) - 1.0) < 1e-9));
+        assert!(stroke_outline(&[], &PenType::Fineliner.model()).is_none());
+    }
+
+    #[test]
+    fn test_segments_follow_pressure() {
+        let mut points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+            Point::new(20.0, 0.0, 4.0, 1.0),
+        ];
+        points[0].pressure = 0.2;
+
+        let model = PenType::SharpPencil.model();
+        let outline = stroke_outline(&points, &model).unwrap();
+
+        assert_eq!(outline.segments.len(), 2);
+        assert!(outline.segments[0].opacity < outline.segments[1].opacity);
+        assert_eq!(outline.segments[1].opacity, model.style(&points[2]).opacity);
+        assert!(!outline.is_uniform());
+
+        let outline = stroke_outline(&points, &PenType::Fineliner.model()).unwrap();
+        assert!(outline.is_uniform());
+    }
+
+    #[test]
+    fn test_tilted_tip_is_elliptic() {
+        let mut points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+        ];
+        for point in &mut points {
+            point.tilt = Some(FRAC_PI_2);
+        }
+        let model = PenType::TiltPencil.model();
+
+        // the tip is stretched along the stroke, which leaves its width
+        let along = stroke_outline(&points, &model).unwrap();
+        let max_y = along
+            .polygon
+            .iter()
+            .map(|(_, y)| *y)
+            .fold(f64::MIN, f64::max);
+        assert!(abs(max_y - 2.0) < 1e-9);
+
+        for point in &mut points {
+            point.direction = FRAC_PI_2;
+        }
+        let across = stroke_outline(&points, &model).unwrap();
+        let max_y = across
+            .polygon
+            .iter()
+            .map(|(_, y)| *y)
+            .fold(f64::MIN, f64::max);
+        assert!(abs(max_y - 6.0) < 1e-9);
+    }
+
+    #[test]
+    fn test_curved_outline_follows_points() {
+        let points = vec![
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 10.0, 4.0, 1.0),
+            Point::new(20.0, 0.0, 4.0, 1.0),
+        ];
+        let model = PenType::Fineliner.model();
+
+        let polygon = stroke_outline(&points, &model).unwrap().polygon;
+        let path = curved_outline(&points, &model).unwrap();
+
+        let curves: Vec<(f64, f64)> = path
+            .iter()
+            .filter_map(|piece| match piece {
+                PathPiece::Curve(_, _, to) => Some(*to),
+                PathPiece::Line(_) => None,
+            })
+            .collect();
+
+        // one curve per side between neighbouring points, ending on the
+        // corners of the polygon
+        assert_eq!(curves.len(), 4);
+        assert_eq!(path[0], PathPiece::Line(polygon[0]));
+        assert_eq!(curves[1], polygon[2]);
+
+        let dot = curved_outline(&points[..1], &model).unwrap();
+        assert!(dot.iter().all(|piece| matches!(piece, PathPiece::Line(_))));
+    }
+}
@@ -0,0 +1,628 @@
+//! Encoder and decoder for the Qt binary JSON format
+//! (`QJsonDocument::toBinaryData`) used by the tablet for live sync frames.
+
+use crate::remarkable::format::error::{join_path, FormatError};
+
+const TAG: &[u8; 4] = b"qbjs";
+const VERSION: u32 = 1;
+
+const TYPE_NULL: u32 = 0;
+const TYPE_BOOL: u32 = 1;
+const TYPE_DOUBLE: u32 = 2;
+const TYPE_STRING: u32 = 3;
+const TYPE_ARRAY: u32 = 4;
+const TYPE_OBJECT: u32 = 5;
+
+const LATIN_OR_INT: u32 = 1 << 3;
+const LATIN_KEY: u32 = 1 << 4;
+
+/// The size of the header of an array or object.
+const BASE_HEADER: usize = 12;
+
+/// The size of the document header preceding the top level array or object.
+const DOCUMENT_HEADER: usize = 8;
+
+/// The deepest nesting of arrays and objects decoded. Live sync frames are
+/// only a few levels deep, the limit keeps crafted data from exhausting the
+/// stack.
+const MAX_DEPTH: usize = 64;
+
+/// A JSON value that can be encoded as Qt binary JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<QValue>),
+    Object(Vec<(String, QValue)>),
+}
+
+impl From<f64> for QValue {
+    fn from(n: f64) -> Self {
+        QValue::Number(n)
+    }
+}
+
+impl From<&str> for QValue {
+    fn from(s: &str) -> Self {
+        QValue::String(s.to_string())
+    }
+}
+
+impl From<String> for QValue {
+    fn from(s: String) -> Self {
+        QValue::String(s)
+    }
+}
+
+/// A decoded value together with its location in the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QNode {
+    /// The path of the value, e.g. `lines.points[3].x`
+    pub path: String,
+    /// The offset of the value's data in bytes from the start of the document
+    pub offset: usize,
+    pub kind: QKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QKind {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<QNode>),
+    Object(Vec<(String, QNode)>),
+    Undefined,
+}
+
+impl QNode {
+    /// Looks up a key if this node is an object.
+    pub fn get(&self, key: &str) -> Option<&QNode> {
+        match &self.kind {
+            QKind::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// A short description of the node used in error messages.
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            QKind::Null => "null".into(),
+            QKind::Bool(b) => format!("the bool {}", b),
+            QKind::Number(n) => format!("the number {}", n),
+            QKind::String(s) => format!("the string {:?}", s),
+            QKind::Array(a) => format!("an array of {} values", a.len()),
+            QKind::Object(o) => format!("an object with {} keys", o.len()),
+            QKind::Undefined => "an undefined value".into(),
+        }
+    }
+
+    /// Creates an error stating that this node is not what has been expected.
+    pub fn unexpected(&self, expected: &str) -> FormatError {
+        FormatError::new(self.path.clone(), expected, self.describe(), self.offset)
+    }
+}
+
+/// Decodes a complete binary JSON document.
+pub fn from_binary(data: &[u8]) -> Result<QNode, FormatError> {
+    if data.len() < DOCUMENT_HEADER || &data[0..4] != TAG {
+        return Err(FormatError::new(
+            "",
+            "a qbjs header",
+            format!("{:?}", &data[..data.len().min(4)]),
+            0,
+        ));
+    }
+
+    let version = read_u32(data, 4, "")?;
+    if version != VERSION {
+        return Err(FormatError::new(
+            "",
+            format!("version {}", VERSION),
+            format!("version {}", version),
+            4,
+        ));
+    }
+
+    decode_base(data, DOCUMENT_HEADER, data.len(), 0, String::new())
+}
+
+/// Decodes the array or object at `base`, which has to end before `end`, the
+/// end of the surrounding array or object.
+fn decode_base(
+    data: &[u8],
+    base: usize,
+    end: usize,
+    depth: usize,
+    path: String,
+) -> Result<QNode, FormatError> {
+    if depth > MAX_DEPTH {
+        return Err(FormatError::new(
+            path,
+            format!("at most {} nested arrays and objects", MAX_DEPTH),
+            "deeper nesting",
+            base,
+        ));
+    }
+
+    let size = read_u32(data, base, &path)? as usize;
+    let is_object_and_length = read_u32(data, base + 4, &path)?;
+    let table_offset = read_u32(data, base + 8, &path)? as usize;
+
+    if size < BASE_HEADER {
+        return Err(FormatError::new(
+            path,
+            format!("at least {} bytes of data", BASE_HEADER),
+            format!("{} bytes", size),
+            base,
+        ));
+    }
+
+    if size > end.saturating_sub(base) {
+        return Err(FormatError::new(
+            path,
+            format!("{} bytes of data", size),
+            format!("{} bytes", end.saturating_sub(base)),
+            base,
+        ));
+    }
+
+    let length = (is_object_and_length >> 1) as usize;
+
+    // the table of a valid header lies within the data, which bounds the
+    // length before anything is allocated for it
+    if table_offset < BASE_HEADER || table_offset > size || length > (size - table_offset) / 4 {
+        return Err(FormatError::new(
+            path,
+            format!("a table of {} entries within {} bytes", length, size),
+            format!("a table at offset {}", table_offset),
+            base + 8,
+        ));
+    }
+
+    let table = base + table_offset;
+    let end = base + size;
+
+    let kind = if is_object_and_length & 1 == 1 {
+        let mut entries = Vec::with_capacity(length);
+
+        for i in 0..length {
+            let position = table + 4 * i;
+            let entry = read_u32(data, position, &path)? as usize;
+            let entry = child_offset(base, end, entry, position, &path)?;
+            let header = read_u32(data, entry, &path)?;
+
+            let key = if header & LATIN_KEY != 0 {
+                read_latin1(data, entry + 4, &path)?
+            } else {
+                read_utf16(data, entry + 4, &path)?
+            };
+
+            let value_path = join_path(&path, &key);
+            let value = decode_value(data, (base, end), depth, header, entry, value_path)?;
+            entries.push((key, value));
+        }
+
+        QKind::Object(entries)
+    } else {
+        let mut items = Vec::with_capacity(length);
+
+        for i in 0..length {
+            let position = table + 4 * i;
+            let header = read_u32(data, position, &path)?;
+            let item_path = join_path(&path, &format!("[{}]", i));
+            items.push(decode_value(
+                data,
+                (base, end),
+                depth,
+                header,
+                position,
+                item_path,
+            )?);
+        }
+
+        QKind::Array(items)
+    };
+
+    Ok(QNode {
+        path,
+        offset: base,
+        kind,
+    })
+}
+
+/// Resolves an offset relative to an array or object, which has to point
+/// behind its header and before its end.
+fn child_offset(
+    base: usize,
+    end: usize,
+    relative: usize,
+    position: usize,
+    path: &str,
+) -> Result<usize, FormatError> {
+    if relative < BASE_HEADER || relative >= end - base {
+        return Err(FormatError::new(
+            path,
+            format!("an offset from {} to {}", BASE_HEADER, end - base - 1),
+            format!("offset {}", relative),
+            position,
+        ));
+    }
+
+    Ok(base + relative)
+}
+
+/// Decodes a value of the array or object spanning `base` to `end`.
+fn decode_value(
+    data: &[u8],
+    (base, end): (usize, usize),
+    depth: usize,
+    header: u32,
+    header_offset: usize,
+    path: String,
+) -> Result<QNode, FormatError> {
+    let value = header >> 5;
+    let offset = base + value as usize;
+    let inline = header & LATIN_OR_INT != 0;
+
+    let (offset, kind) = match header & 0x7 {
+        TYPE_NULL => (header_offset, QKind::Null),
+        TYPE_BOOL => (header_offset, QKind::Bool(value != 0)),
+        TYPE_DOUBLE if inline => {
+            // the integer is stored sign extended in the upper 27 bits
+            (header_offset, QKind::Number(((header as i32) >> 5) as f64))
+        }
+        TYPE_DOUBLE => {
+            let bytes = read_bytes(data, offset, 8, &path)?;
+            let mut number = [0u8; 8];
+            number.copy_from_slice(bytes);
+            (offset, QKind::Number(f64::from_le_bytes(number)))
+        }
+        TYPE_STRING if inline => (offset, QKind::String(read_latin1(data, offset, &path)?)),
+        TYPE_STRING => (offset, QKind::String(read_utf16(data, offset, &path)?)),
+        TYPE_ARRAY | TYPE_OBJECT => {
+            let offset = child_offset(base, end, value as usize, header_offset, &path)?;
+            return decode_base(data, offset, end, depth + 1, path);
+        }
+        _ => (header_offset, QKind::Undefined),
+    };
+
+    Ok(QNode { path, offset, kind })
+}
+
+fn read_bytes<'a>(
+    data: &'a [u8],
+    offset: usize,
+    count: usize,
+    path: &str,
+) -> Result<&'a [u8], FormatError> {
+    data.get(offset..offset + count).ok_or_else(|| {
+        FormatError::new(
+            path,
+            format!("{} more bytes", count),
+            "the end of the data",
+            offset,
+        )
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, path: &str) -> Result<u32, FormatError> {
+    let bytes = read_bytes(data, offset, 4, path)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_latin1(data: &[u8], offset: usize, path: &str) -> Result<String, FormatError> {
+    let length = read_bytes(data, offset, 2, path)?;
+    let length = u16::from_le_bytes([length[0], length[1]]) as usize;
+
+    Ok(read_bytes(data, offset + 2, length, path)?
+        .iter()
+        .map(|c| *c as char)
+        .collect())
+}
+
+fn read_utf16(data: &[u8], offset: usize, path: &str) -> Result<String, FormatError> {
+    let length = read_u32(data, offset, path)? as usize;
+    let units: Vec<u16> = read_bytes(data, offset + 4, 2 * length, path)?
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// Encodes an object or array into a complete binary JSON document.
+pub fn to_binary(value: &QValue) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    data.extend_from_slice(TAG);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+
+    match value {
+        QValue::Array(items) => data.extend(encode_array(items)),
+        QValue::Object(entries) => data.extend(encode_object(entries)),
+        v => data.extend(encode_array(std::slice::from_ref(v))),
+    }
+
+    data
+}
+
+fn encode_object(entries: &[(String, QValue)]) -> Vec<u8> {
+    // Qt looks up keys with a binary search, so entries have to be sorted
+    let mut sorted: Vec<&(String, QValue)> = entries.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+    let mut data = vec![0u8; BASE_HEADER];
+    let mut table = Vec::with_capacity(sorted.len());
+
+    for (key, value) in sorted {
+        let entry_start = data.len();
+        table.push(entry_start as u32);
+
+        let (key_bytes, key_flag) = match encode_latin1(key) {
+            Some(bytes) => (bytes, LATIN_KEY),
+            None => (encode_utf16(key), 0),
+        };
+
+        let value_offset = entry_start + align(4 + key_bytes.len());
+        let (header, value_data) = encode_value(value, value_offset);
+
+        data.extend_from_slice(&(header | key_flag).to_le_bytes());
+        data.extend(key_bytes);
+        pad(&mut data);
+        data.extend(value_data);
+    }
+
+    finish_base(data, true, &table)
+}
+
+fn encode_array(items: &[QValue]) -> Vec<u8> {
+    let mut data = vec![0u8; BASE_HEADER];
+    let mut table = Vec::with_capacity(items.len());
+
+    for item in items {
+        let (header, value_data) = encode_value(item, data.len());
+        table.push(header);
+        data.extend(value_data);
+    }
+
+    finish_base(data, false, &table)
+}
+
+/// Appends the table and fills in the header of an array or object.
+fn finish_base(mut data: Vec<u8>, is_object: bool, table: &[u32]) -> Vec<u8> {
+    let table_offset = data.len() as u32;
+
+    for entry in table {
+        data.extend_from_slice(&entry.to_le_bytes());
+    }
+
+    let size = data.len() as u32;
+    let is_object_and_length = (table.len() as u32) << 1 | is_object as u32;
+
+    data[0..4].copy_from_slice(&size.to_le_bytes());
+    data[4..8].copy_from_slice(&is_object_and_length.to_le_bytes());
+    data[8..12].copy_from_slice(&table_offset.to_le_bytes());
+
+    data
+}
+
+/// Returns the value header and the data stored at the given offset of the
+/// surrounding array or object.
+fn encode_value(value: &QValue, offset: usize) -> (u32, Vec<u8>) {
+    let offset = (offset as u32) << 5;
+
+    match value {
+        QValue::Null => (TYPE_NULL, Vec::new()),
+        QValue::Bool(b) => (TYPE_BOOL | (*b as u32) << 5, Vec::new()),
+        QValue::Number(n) => match compressed_number(*n) {
+            Some(i) => (TYPE_DOUBLE | LATIN_OR_INT | (i as u32) << 5, Vec::new()),
+            None => (TYPE_DOUBLE | offset, n.to_le_bytes().to_vec()),
+        },
+        QValue::String(s) => match encode_latin1(s) {
+            Some(mut bytes) => {
+                pad(&mut bytes);
+                (TYPE_STRING | LATIN_OR_INT | offset, bytes)
+            }
+            None => {
+                let mut bytes = encode_utf16(s);
+                pad(&mut bytes);
+                (TYPE_STRING | offset, bytes)
+            }
+        },
+        QValue::Array(items) => (TYPE_ARRAY | offset, encode_array(items)),
+        QValue::Object(entries) => (TYPE_OBJECT | offset, encode_object(entries)),
+    }
+}
+
+/// Integers in the range [1, 2^26) are stored inside the value header.
+fn compressed_number(n: f64) -> Option<i32> {
+    let bits = n.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i32 - 1023;
+
+    if !(0..=25).contains(&exponent) || bits & (0x000F_FFFF_FFFF_FFFF >> exponent) != 0 {
+        return None;
+    }
+
+    Some(n as i32)
+}
+
+fn encode_latin1(s: &str) -> Option<Vec<u8>> {
+    if s.chars().count() >= 0x8000 || s.chars().any(|c| c as u32 > 0xFF) {
+        return None;
+    }
+
+    let mut bytes = (s.chars().count() as u16).to_le_bytes().to_vec();
+    bytes.extend(s.chars().map(|c| c as u8));
+
+    Some(bytes)
+}
+
+fn encode_utf16(s: &str) -> Vec<u8> {
+    let units: Vec<u16> = s.encode_utf16().collect();
+
+    let mut bytes = (units.len() as u32).to_le_bytes().to_vec();
+    for unit in units {
+        bytes.extend_from_slice(&unit.to_le_bytes());
+    }
+
+    bytes
+}
+
+fn align(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+fn pad(data: &mut Vec<u8>) {
+    data.resize(align(data.len()), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_object() {
+        let data = to_binary(&QValue::Object(Vec::new()));
+
+        assert_eq!(
+            data,
+            vec![b'q', b'b', b'j', b's', 1, 0, 0, 0, 12, 0, 0, 0, 1, 0, 0, 0, 12, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_compressed_numbers() {
+        assert_eq!(compressed_number(2.0), Some(2));
+        assert_eq!(compressed_number(-5.0), Some(-5));
+        assert_eq!(compressed_number(0.0), None);
+        assert_eq!(compressed_number(1.5), None);
+        assert_eq!(compressed_number(2f64.powi(26)), None);
+    }
+
+    #[test]
+    fn test_keys_are_sorted() {
+        let data = to_binary(&QValue::Object(vec![
+            ("b".into(), QValue::Null),
+            ("a".into(), QValue::Null),
+        ]));
+
+        // the first entry follows the header and is referenced first in the table
+        assert_eq!(&data[20..24], &[0x10, 0, 0, 0]);
+        assert_eq!(data[26], b'a');
+    }
+
+    #[test]
+    fn test_decode_example() {
+        let data = include_bytes!("example.bin");
+
+        let document = from_binary(data).unwrap();
+
+        let x = document
+            .get("lines")
+            .and_then(|l| l.get("points"))
+            .and_then(|p| match &p.kind {
+                QKind::Array(points) => points.first(),
+                _ => None,
+            })
+            .and_then(|p| p.get("x"))
+            .unwrap();
+
+        assert_eq!(x.path, "lines.points[0].x");
+        assert_eq!(x.kind, QKind::Number(1105.065673828125));
+        assert_eq!(
+            &data[x.offset..x.offset + 8],
+            &1105.065673828125f64.to_le_bytes()
+        );
+
+        let thickness = document.get("lines").and_then(|l| l.get("thickness"));
+        assert_eq!(thickness.unwrap().kind, QKind::Number(2.0));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = QValue::Object(vec![
+            ("negative".into(), QValue::Number(-12.0)),
+            ("fraction".into(), QValue::Number(0.25)),
+            ("unicode".into(), QValue::String("€uro".into())),
+            ("latin".into(), QValue::String("Grün".into())),
+            ("flag".into(), QValue::Bool(true)),
+            ("empty".into(), QValue::Null),
+            (
+                "list".into(),
+                QValue::Array(vec![QValue::Number(1.0), QValue::String("a".into())]),
+            ),
+        ]);
+
+        let document = from_binary(&to_binary(&value)).unwrap();
+
+        assert_eq!(document.get("negative").unwrap().kind, QKind::Number(-12.0));
+        assert_eq!(document.get("fraction").unwrap().kind, QKind::Number(0.25));
+        assert_eq!(
+            document.get("unicode").unwrap().kind,
+            QKind::String("€uro".into())
+        );
+        assert_eq!(
+            document.get("latin").unwrap().kind,
+            QKind::String("Grün".into())
+        );
+        assert_eq!(document.get("flag").unwrap().kind, QKind::Bool(true));
+        assert_eq!(document.get("empty").unwrap().kind, QKind::Null);
+        assert_eq!(document.get("list").unwrap().path, "list");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = include_bytes!("example.bin");
+
+        let error = from_binary(&data[..200]).unwrap_err();
+
+        assert_eq!(error.offset, 8);
+        assert!(from_binary(b"json").is_err());
+    }
+
+    /// A document holding a single array with the given header and table.
+    fn crafted(is_object_and_length: u32, table: &[u32]) -> Vec<u8> {
+        let size = (BASE_HEADER + 4 * table.len()) as u32;
+
+        let mut data = TAG.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(&is_object_and_length.to_le_bytes());
+        data.extend_from_slice(&(BASE_HEADER as u32).to_le_bytes());
+        for entry in table {
+            data.extend_from_slice(&entry.to_le_bytes());
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_self_reference() {
+        // an array containing itself at offset 0
+        let error = from_binary(&crafted(1 << 1, &[TYPE_ARRAY])).unwrap_err();
+        assert_eq!(error.path, "[0]");
+        assert_eq!(error.offset, DOCUMENT_HEADER + BASE_HEADER);
+
+        // an object whose only entry is its header
+        assert!(from_binary(&crafted(1 << 1 | 1, &[0])).is_err());
+    }
+
+    #[test]
+    fn test_length_beyond_size() {
+        let error = from_binary(&crafted(u32::MAX - 1, &[TYPE_NULL])).unwrap_err();
+        assert_eq!(error.offset, DOCUMENT_HEADER + 8);
+
+        assert!(from_binary(&crafted(u32::MAX, &[0])).is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth| (0..depth).fold(QValue::Null, |v, _| QValue::Array(vec![v]));
+
+        assert!(from_binary(&to_binary(&nested(MAX_DEPTH))).is_ok());
+        assert!(from_binary(&to_binary(&nested(MAX_DEPTH + 2))).is_err());
+    }
+}
@@ -0,0 +1,623 @@
+use std::{collections::HashMap, f64::consts::PI};
+
+use log::{debug, trace, warn};
+
+use crate::remarkable::format::{
+    data::{Line, PenColor, PenType, Point},
+    error::FormatError,
+};
+
+const HEADER_PREFIX: &[u8] = b"reMarkable .lines file, version=";
+const HEADER_LENGTH: usize = 43;
+
+const BLOCK_TREE_NODE: u8 = 0x02;
+const BLOCK_LINE_ITEM: u8 = 0x05;
+
+const TAG_BYTE4: u8 = 0x4;
+const TAG_BYTE8: u8 = 0x8;
+const TAG_LENGTH4: u8 = 0xC;
+const TAG_ID: u8 = 0xF;
+
+/// The smallest number of bytes a layer, a line and a point take in files of
+/// version 3 and 5.
+const LEGACY_LAYER_SIZE: usize = 4;
+const LEGACY_LINE_SIZE: usize = 20;
+const LEGACY_POINT_SIZE: usize = 24;
+
+/// The item type of a line inside a v6 scene item block.
+const ITEM_TYPE_LINE: u8 = 0x03;
+
+/// A page read from an .rm lines file.
+#[derive(Debug)]
+pub struct RmPage {
+    pub version: u32,
+    pub layers: Vec<RmLayer>,
+}
+
+/// A layer of a page. Older versions do not store layer names.
+#[derive(Debug, Default)]
+pub struct RmLayer {
+    pub name: Option<String>,
+    pub lines: Vec<Line>,
+}
+
+/// Parses an .rm lines file of version 3, 5 or 6.
+pub fn parse_rm_file(data: &[u8]) -> Result<RmPage, FormatError> {
+    let version = parse_version(data)?;
+
+    debug!("Parsing .rm file of version {}", version);
+
+    let mut reader = Reader::new(data);
+    reader.pos = HEADER_LENGTH;
+
+    let layers = match version {
+        3 | 5 => parse_legacy_layers(&mut reader, version)?,
+        6 => parse_scene_layers(&mut reader)?,
+        v => {
+            return Err(FormatError::new(
+                "version",
+                "version 3, 5 or 6",
+                format!("version {}", v),
+                HEADER_PREFIX.len(),
+            ))
+        }
+    };
+
+    Ok(RmPage { version, layers })
+}
+
+fn parse_version(data: &[u8]) -> Result<u32, FormatError> {
+    if data.len() < HEADER_LENGTH || !data.starts_with(HEADER_PREFIX) {
+        return Err(FormatError::new(
+            "header",
+            "a reMarkable .lines header",
+            format!(
+                "{:?}",
+                String::from_utf8_lossy(&data[..data.len().min(HEADER_LENGTH)])
+            ),
+            0,
+        ));
+    }
+
+    let version = String::from_utf8_lossy(&data[HEADER_PREFIX.len()..HEADER_LENGTH]);
+
+    version.trim().parse().map_err(|_| {
+        FormatError::new(
+            "version",
+            "a version number",
+            format!("{:?}", version.trim()),
+            HEADER_PREFIX.len(),
+        )
+    })
+}
+
+fn parse_legacy_layers(reader: &mut Reader, version: u32) -> Result<Vec<RmLayer>, FormatError> {
+    let layer_count = reader.read_count("layers", LEGACY_LAYER_SIZE)?;
+    let mut layers = Vec::with_capacity(layer_count);
+
+    for i in 0..layer_count {
+        let layer_path = format!("layers[{}]", i);
+        let line_count = reader
+            .read_count("lines", LEGACY_LINE_SIZE)
+            .map_err(|e| e.within(&layer_path))?;
+        let mut layer = RmLayer::default();
+
+        for j in 0..line_count {
+            let line = parse_legacy_line(reader, version)
+                .map_err(|e| e.within(&format!("{}.lines[{}]", layer_path, j)))?;
+            layer.lines.push(line);
+        }
+
+        layers.push(layer);
+    }
+
+    Ok(layers)
+}
+
+fn parse_legacy_line(reader: &mut Reader, version: u32) -> Result<Line, FormatError> {
+    let brush = PenType::from(reader.read_u32()?);
+    let color = PenColor::from(reader.read_u32()?);
+    let _selected = reader.read_u32()?;
+    let _base_width = reader.read_f32()?;
+    if version >= 5 {
+        let _unknown = reader.read_u32()?;
+    }
+
+    let point_count = reader.read_count("points", LEGACY_POINT_SIZE)?;
+    let mut points = Vec::with_capacity(point_count);
+
+    for k in 0..point_count {
+        points.push(parse_legacy_point(reader).map_err(|e| e.within(&format!("points[{}]", k)))?);
+    }
+
+    Ok(Line {
+        points,
+        brush,
+        color,
+    })
+}
+
+fn parse_legacy_point(reader: &mut Reader) -> Result<Point, FormatError> {
+    let x = reader.read_f32()? as f64;
+    let y = reader.read_f32()? as f64;
+    let speed = reader.read_f32()? as f64;
+    let direction = reader.read_f32()? as f64;
+    let width = reader.read_f32()? as f64;
+    let pressure = reader.read_f32()? as f64;
+
+    Ok(Point {
+        speed,
+        width,
+        x,
+        y,
+        pressure,
+        direction,
+        tilt: None,
+    })
+}
+
+/// Version 6 files consist of typed blocks. Lines reference the layer (a tree
+/// node) they belong to as their parent.
+fn parse_scene_layers(reader: &mut Reader) -> Result<Vec<RmLayer>, FormatError> {
+    let mut order: Vec<CrdtId> = Vec::new();
+    let mut layers: HashMap<CrdtId, RmLayer> = HashMap::new();
+
+    let mut index = 0;
+
+    while reader.remaining() > 0 {
+        let block_path = format!("blocks[{}]", index);
+        index += 1;
+
+        let start = reader.pos;
+        let length = reader.read_u32().map_err(|e| e.within(&block_path))? as usize;
+        let header = reader.read_bytes(4).map_err(|e| e.within(&block_path))?;
+        let (current_version, block_type) = (header[2], header[3]);
+
+        let end = reader.pos + length;
+        if end > reader.data.len() {
+            return Err(FormatError::new(
+                block_path,
+                format!("a block of {} bytes", length),
+                format!("{} bytes", reader.remaining()),
+                start,
+            ));
+        }
+
+        trace!("Block of type {} with {} bytes", block_type, length);
+
+        let mut block = Reader::new(&reader.data[..end]);
+        block.pos = reader.pos;
+
+        match block_type {
+            BLOCK_TREE_NODE => {
+                let (id, name) = parse_tree_node(&mut block).map_err(|e| e.within(&block_path))?;
+                if !layers.contains_key(&id) {
+                    order.push(id);
+                }
+                layers.entry(id).or_default().name = name;
+            }
+            BLOCK_LINE_ITEM => {
+                let item = parse_line_item(&mut block, current_version)
+                    .map_err(|e| e.within(&block_path))?;
+                if let Some((parent, line)) = item {
+                    if !layers.contains_key(&parent) {
+                        order.push(parent);
+                    }
+                    layers.entry(parent).or_default().lines.push(line);
+                }
+            }
+            t => {
+                trace!("Skipping block of type {}", t);
+            }
+        }
+
+        reader.pos = end;
+    }
+
+    Ok(order
+        .iter()
+        .filter_map(|id| layers.remove(id))
+        .filter(|layer| !layer.lines.is_empty() || layer.name.is_some())
+        .collect())
+}
+
+fn parse_tree_node(block: &mut Reader) -> Result<(CrdtId, Option<String>), FormatError> {
+    let id = block.read_tagged_id(1)?;
+
+    let mut name = None;
+
+    if block.has_tag(2, TAG_LENGTH4) {
+        let end = block.read_subblock(2)?;
+        let _timestamp = block.read_tagged_id(1)?;
+        if block.has_tag(2, TAG_LENGTH4) {
+            let _string_end = block.read_subblock(2)?;
+            name = Some(block.read_string()?);
+        }
+        block.pos = end;
+    }
+
+    Ok((id, name))
+}
+
+fn parse_line_item(block: &mut Reader, version: u8) -> Result<Option<(CrdtId, Line)>, FormatError> {
+    let parent = block.read_tagged_id(1)?;
+    let _item = block.read_tagged_id(2)?;
+    let _left = block.read_tagged_id(3)?;
+    let _right = block.read_tagged_id(4)?;
+    let deleted = block.read_tagged_u32(5)?;
+
+    if deleted > 0 || !block.has_tag(6, TAG_LENGTH4) {
+        trace!("Skipping deleted line");
+        return Ok(None);
+    }
+
+    let _value_end = block.read_subblock(6)?;
+    let item_type = block.read_u8()?;
+
+    if item_type != ITEM_TYPE_LINE {
+        warn!("Expected a line item. Got type {}", item_type);
+        return Ok(None);
+    }
+
+    let brush = PenType::from(block.read_tagged_u32(1)?);
+    let mut color = PenColor::from(block.read_tagged_u32(2)?);
+    let _thickness_scale = block.read_tagged_f64(3)?;
+    let _starting_length = block.read_tagged_f32(4)?;
+
+    let points_end = block.read_subblock(5)?;
+    let mut points = Vec::new();
+
+    while block.pos < points_end {
+        points.push(
+            if version >= 2 {
+                let x = block.read_f32()? as f64;
+                let y = block.read_f32()? as f64;
+                let speed = block.read_u16()? as f64 / 4.0;
+                let width = block.read_u16()? as f64 / 4.0;
+                let direction = block.read_u8()? as f64 * 2.0 * PI / 255.0;
+                let pressure = block.read_u8()? as f64 / 255.0;
+
+                Point {
+                    speed,
+                    width,
+                    x,
+                    y,
+                    pressure,
+                    direction,
+                    tilt: None,
+                }
+            } else {
+                let x = block.read_f32()? as f64;
+                let y = block.read_f32()? as f64;
+                let speed = block.read_f32()? as f64;
+                let direction = block.read_f32()? as f64;
+                let width = block.read_f32()? as f64;
+                let pressure = block.read_f32()? as f64;
+
+                Point {
+                    speed,
+                    width,
+                    x,
+                    y,
+                    pressure,
+                    direction,
+                    tilt: None,
+                }
+            },
+        );
+    }
+
+    block.pos = points_end;
+
+    let _timestamp = block.read_tagged_id(6)?;
+
+    if block.has_tag(7, TAG_ID) {
+        let _move_id = block.read_tagged_id(7)?;
+    }
+
+    if block.has_tag(8, TAG_BYTE4) {
+        color = PenColor::from_argb(block.read_tagged_u32(8)?);
+    }
+
+    Ok(Some((
+        parent,
+        Line {
+            points,
+            brush,
+            color,
+        },
+    )))
+}
+
+/// Identifies items of the v6 scene tree.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct CrdtId(u8, u64);
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], FormatError> {
+        if self.remaining() < count {
+            return Err(FormatError::new(
+                "",
+                format!("{} more bytes", count),
+                format!("{} bytes", self.remaining()),
+                self.pos,
+            ));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, FormatError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, FormatError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads the number of records that follow, each taking at least the
+    /// given number of bytes. Counts that cannot fit into the rest of the
+    /// data are rejected before anything is allocated for them.
+    fn read_count(&mut self, path: &str, record_size: usize) -> Result<usize, FormatError> {
+        let pos = self.pos;
+        let count = self.read_u32()? as usize;
+
+        if count.saturating_mul(record_size) > self.remaining() {
+            return Err(FormatError::new(
+                path,
+                format!("at most {} records", self.remaining() / record_size),
+                format!("{} records", count),
+                pos,
+            ));
+        }
+
+        Ok(count)
+    }
+
+    fn read_f32(&mut self) -> Result<f32, FormatError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, FormatError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn read_varuint(&mut self) -> Result<u64, FormatError> {
+        let start = self.pos;
+        let mut result = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.read_u8()?;
+            result |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+            if shift >= 64 {
+                return Err(FormatError::new(
+                    "",
+                    "a variable length integer",
+                    format!("more than {} bytes", self.pos - start),
+                    start,
+                ));
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, FormatError> {
+        let length = self.read_varuint()? as usize;
+        let _is_ascii = self.read_u8()?;
+        let bytes = self.read_bytes(length)?;
+
+        Ok(String::from_utf8_lossy(bytes).to_string())
+    }
+
+    /// Checks whether the next value carries the given tag without consuming
+    /// it.
+    fn has_tag(&mut self, index: u64, tag_type: u8) -> bool {
+        let pos = self.pos;
+        let result = self.read_varuint();
+        self.pos = pos;
+
+        matches!(result, Ok(tag) if tag == (index << 4) | tag_type as u64)
+    }
+
+    fn read_tag(&mut self, index: u64, tag_type: u8) -> Result<(), FormatError> {
+        let pos = self.pos;
+        let tag = self.read_varuint()?;
+
+        if tag != (index << 4) | tag_type as u64 {
+            return Err(FormatError::new(
+                "",
+                format!("tag {} of type {:#x}", index, tag_type),
+                format!("tag {} of type {:#x}", tag >> 4, tag & 0xF),
+                pos,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn read_tagged_id(&mut self, index: u64) -> Result<CrdtId, FormatError> {
+        self.read_tag(index, TAG_ID)?;
+        Ok(CrdtId(self.read_u8()?, self.read_varuint()?))
+    }
+
+    fn read_tagged_u32(&mut self, index: u64) -> Result<u32, FormatError> {
+        self.read_tag(index, TAG_BYTE4)?;
+        self.read_u32()
+    }
+
+    fn read_tagged_f32(&mut self, index: u64) -> Result<f32, FormatError> {
+        self.read_tag(index, TAG_BYTE4)?;
+        self.read_f32()
+    }
+
+    fn read_tagged_f64(&mut self, index: u64) -> Result<f64, FormatError> {
+        self.read_tag(index, TAG_BYTE8)?;
+        self.read_f64()
+    }
+
+    /// Reads the header of a sub block and returns the offset of its end.
+    fn read_subblock(&mut self, index: u64) -> Result<usize, FormatError> {
+        self.read_tag(index, TAG_LENGTH4)?;
+        let length = self.read_u32()? as usize;
+
+        if self.remaining() < length {
+            return Err(FormatError::new(
+                "",
+                format!("a sub block of {} bytes", length),
+                format!("{} bytes", self.remaining()),
+                self.pos,
+            ));
+        }
+
+        Ok(self.pos + length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::abs;
+
+    use super::*;
+
+    fn assert_legacy(page: RmPage, version: u32) {
+        assert_eq!(page.version, version);
+        assert_eq!(page.layers.len(), 1);
+
+        let lines = &page.layers[0].lines;
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0].brush, PenType::TiltPencil);
+        assert_eq!(lines[0].color, PenColor::BLACK);
+        assert_eq!(lines[0].points.len(), 4);
+
+        let p1 = &lines[0].points[1];
+        assert!(abs(p1.x - 110.0) < 0.001, "X should be 110, is {}", p1.x);
+        assert!(abs(p1.y - 205.0) < 0.001, "Y should be 205, is {}", p1.y);
+        assert!(abs(p1.pressure - 0.5) < 0.001);
+
+        assert_eq!(lines[1].brush, PenType::Highlighter);
+        assert_eq!(lines[1].color, PenColor::YELLOW);
+        assert_eq!(lines[1].points.len(), 2);
+    }
+
+    #[test]
+    fn test_v3() {
+        let data = include_bytes!("example_v3.rm");
+
+        let page = parse_rm_file(data);
+
+        assert!(page.is_ok(), "Page expected to be parsed: {:?}", page);
+        assert_legacy(page.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_v5() {
+        let data = include_bytes!("example_v5.rm");
+
+        let page = parse_rm_file(data);
+
+        assert!(page.is_ok(), "Page expected to be parsed: {:?}", page);
+        assert_legacy(page.unwrap(), 5);
+    }
+
+    #[test]
+    fn test_v6() {
+        let data = include_bytes!("example_v6.rm");
+
+        let page = parse_rm_file(data);
+
+        assert!(page.is_ok(), "Page expected to be parsed: {:?}", page);
+
+        let page = page.unwrap();
+        assert_eq!(page.version, 6);
+        assert_eq!(page.layers.len(), 1);
+
+        let layer = &page.layers[0];
+        assert_eq!(layer.name, Some("Sketch".into()));
+        assert_eq!(layer.lines.len(), 2);
+
+        let line = &layer.lines[0];
+        assert_eq!(line.brush, PenType::BallPoint);
+        assert_eq!(line.color, PenColor::BLACK);
+        assert_eq!(line.points.len(), 2);
+
+        let p1 = &line.points[1];
+        assert!(abs(p1.x - 30.0) < 0.001);
+        assert!(abs(p1.width - 2.0) < 0.001);
+        assert!(abs(p1.speed - 1.5) < 0.001);
+        assert!(abs(p1.pressure - 1.0) < 0.01);
+        assert!(abs(p1.direction - PI) < 0.05);
+
+        let highlight = &layer.lines[1];
+        assert_eq!(highlight.brush, PenType::Highlighter);
+        assert_eq!(highlight.color, PenColor::RGBA(255, 237, 117, 255));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(parse_rm_file(b"reMarkable .lines").is_err());
+        assert!(parse_rm_file(&[0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = include_bytes!("example_v5.rm");
+
+        let error = parse_rm_file(&data[..data.len() - 3]).unwrap_err();
+
+        // the two points of the last line no longer fit
+        assert_eq!(error.path, "layers[0].lines[1].points");
+        assert_eq!(error.offset, data.len() - 2 * LEGACY_POINT_SIZE - 4);
+    }
+
+    #[test]
+    fn test_huge_counts() {
+        let data = include_bytes!("example_v5.rm");
+
+        // the layer count directly follows the header
+        let mut layers = data.to_vec();
+        layers[HEADER_LENGTH..HEADER_LENGTH + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let error = parse_rm_file(&layers).unwrap_err();
+        assert_eq!(error.path, "layers");
+        assert_eq!(error.offset, HEADER_LENGTH);
+
+        // the point count of the first line follows the layer count, the
+        // line count and five values of the line
+        let offset = HEADER_LENGTH + 4 + 4 + 5 * 4;
+        let mut points = data.to_vec();
+        points[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let error = parse_rm_file(&points).unwrap_err();
+        assert_eq!(error.path, "layers[0].lines[0].points");
+        assert_eq!(error.offset, offset);
+    }
+}
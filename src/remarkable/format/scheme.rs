@@ -0,0 +1,307 @@
+use crate::remarkable::format::{brush::BlendMode, data::PenColor};
+
+/// The lightness range used by [ColorScheme::Dark], so the page is not pure
+/// black and ink not glaring white.
+const DARK_LIGHTNESS: (f64, f64) = (0.1, 0.9);
+
+/// The lightness of coloured ink in [ColorScheme::HighContrast], giving
+/// every colour a contrast of at least 7:1 against the black paper.
+const HIGH_CONTRAST_INK: f64 = 0.75;
+
+/// The lightness of highlighters in [ColorScheme::HighContrast].
+const HIGH_CONTRAST_HIGHLIGHT: f64 = 0.35;
+
+/// Colours with less saturation than this count as grey.
+const GREY_SATURATION: f64 = 0.1;
+
+/// How the colours of a page are shown.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ColorScheme {
+    /// The colours of the tablet
+    #[default]
+    Light,
+    /// Dark paper and light ink of the same hues
+    Dark,
+    /// Every colour is inverted
+    Inverted,
+    /// Black paper with white and vivid ink, e.g. for projectors
+    HighContrast,
+}
+
+impl ColorScheme {
+    pub const ALL: [ColorScheme; 4] = [
+        ColorScheme::Light,
+        ColorScheme::Dark,
+        ColorScheme::Inverted,
+        ColorScheme::HighContrast,
+    ];
+
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+            ColorScheme::Inverted => "inverted",
+            ColorScheme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        ColorScheme::ALL
+            .iter()
+            .copied()
+            .find(|scheme| scheme.identifier() == identifier)
+    }
+
+    /// The name shown to the user.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ColorScheme::Light => "Light",
+            ColorScheme::Dark => "Dark",
+            ColorScheme::Inverted => "Inverted",
+            ColorScheme::HighContrast => "High contrast",
+        }
+    }
+
+    /// Whether the paper is dark, so images and PDF pages have to be
+    /// inverted to fit in.
+    pub fn is_dark(&self) -> bool {
+        *self != ColorScheme::Light
+    }
+
+    /// Maps a colour of the page that is not ink, like the paper or
+    /// template lines.
+    pub fn color(&self, (r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+        match self {
+            ColorScheme::Light => (r, g, b),
+            ColorScheme::Inverted => (1.0 - r, 1.0 - g, 1.0 - b),
+            ColorScheme::Dark => {
+                let (h, s, l) = to_hsl((r, g, b));
+                from_hsl((h, s, dark_lightness(l)))
+            }
+            ColorScheme::HighContrast => {
+                let (_, _, l) = to_hsl((r, g, b));
+                // only the paper stays black, everything else is dimmed
+                let l = (1.0 - l) * 0.5;
+                (l, l, l)
+            }
+        }
+    }
+
+    /// The colour of the paper.
+    pub fn paper(&self) -> (f64, f64, f64) {
+        self.color((1.0, 1.0, 1.0))
+    }
+
+    /// Maps the colour of ink and how it is blended. Hues are kept so
+    /// colours mean the same in every scheme. Highlighters darken light
+    /// paper, so they lighten dark paper instead.
+    pub fn ink(&self, color: PenColor, blend: BlendMode) -> ((f64, f64, f64, f64), BlendMode) {
+        let (r, g, b, a) = color.as_rgba();
+
+        let blend = match (self.is_dark(), blend) {
+            (true, BlendMode::Multiply) => BlendMode::Screen,
+            (_, blend) => blend,
+        };
+
+        let (r, g, b) = match self {
+            ColorScheme::HighContrast => {
+                let (h, s, l) = to_hsl((r, g, b));
+
+                if blend == BlendMode::Screen {
+                    let s = if s < GREY_SATURATION { 0.0 } else { 1.0 };
+                    from_hsl((h, s, HIGH_CONTRAST_HIGHLIGHT))
+                } else if s < GREY_SATURATION {
+                    // black, grey and white stay apart
+                    let l = if l < 0.25 {
+                        1.0
+                    } else if l > 0.9 {
+                        0.0
+                    } else {
+                        0.7
+                    };
+                    (l, l, l)
+                } else {
+                    from_hsl((h, 1.0, HIGH_CONTRAST_INK))
+                }
+            }
+            scheme => scheme.color((r, g, b)),
+        };
+
+        ((r, g, b, a), blend)
+    }
+}
+
+/// Maps a lightness of the light scheme to the dark one.
+fn dark_lightness(l: f64) -> f64 {
+    let (min, max) = DARK_LIGHTNESS;
+    min + (1.0 - l) * (max - min)
+}
+
+/// Converts RGB to hue in degrees, saturation and lightness.
+fn to_hsl((r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+
+    if delta < f64::EPSILON {
+        return (0.0, 0.0, l);
+    }
+
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    (h, s, l)
+}
+
+/// Converts hue in degrees, saturation and lightness to RGB.
+fn from_hsl((h, s, l): (f64, f64, f64)) -> (f64, f64, f64) {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = chroma * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = l - chroma / 2.0;
+
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    (r + m, g + m, b + m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The contrast ratio of two colours as defined by WCAG.
+    fn contrast(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+        let luminance = |(r, g, b): (f64, f64, f64)| {
+            let linear = |c: f64| {
+                if c <= 0.03928 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            };
+            0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+        };
+
+        let (a, b) = (luminance(a), luminance(b));
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    fn rgb(((r, g, b, _), _): ((f64, f64, f64, f64), BlendMode)) -> (f64, f64, f64) {
+        (r, g, b)
+    }
+
+    #[test]
+    fn test_hsl_round_trip() {
+        for color in [PenColor::BLUE, PenColor::RED, PenColor::HIGHLIGHT_GREEN] {
+            let (r, g, b) = color.as_rgb();
+            let (r2, g2, b2) = from_hsl(to_hsl((r, g, b)));
+
+            assert!((r - r2).abs() < 1e-9 && (g - g2).abs() < 1e-9 && (b - b2).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_light_keeps_colors() {
+        let scheme = ColorScheme::Light;
+
+        assert_eq!(scheme.paper(), (1.0, 1.0, 1.0));
+        assert_eq!(
+            scheme.ink(PenColor::HIGHLIGHT_YELLOW, BlendMode::Multiply),
+            (PenColor::HIGHLIGHT_YELLOW.as_rgba(), BlendMode::Multiply)
+        );
+    }
+
+    #[test]
+    fn test_dark_keeps_hues() {
+        let scheme = ColorScheme::Dark;
+        let (r, g, b) = rgb(scheme.ink(PenColor::BLUE, BlendMode::Normal));
+
+        assert!(b > r && b > g);
+        assert!(
+            contrast(
+                rgb(scheme.ink(PenColor::BLACK, BlendMode::Normal)),
+                scheme.paper()
+            ) > 7.0
+        );
+
+        let (_, blend) = scheme.ink(PenColor::HIGHLIGHT_PINK, BlendMode::Multiply);
+        assert_eq!(blend, BlendMode::Screen);
+    }
+
+    #[test]
+    fn test_inverted() {
+        let scheme = ColorScheme::Inverted;
+
+        assert_eq!(scheme.paper(), (0.0, 0.0, 0.0));
+        assert_eq!(
+            rgb(scheme.ink(PenColor::BLACK, BlendMode::Normal)),
+            (1.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_high_contrast_is_readable() {
+        let scheme = ColorScheme::HighContrast;
+        let inks = [
+            PenColor::BLACK,
+            PenColor::GRAY,
+            PenColor::BLUE,
+            PenColor::RED,
+            PenColor::GREEN,
+            PenColor::PINK,
+            PenColor::YELLOW,
+        ];
+
+        for ink in inks {
+            let color = rgb(scheme.ink(ink, BlendMode::Normal));
+            assert!(contrast(color, scheme.paper()) >= 7.0, "{:?}", ink);
+        }
+    }
+
+    #[test]
+    fn test_highlighters_stay_distinguishable() {
+        let highlighters = [
+            PenColor::HIGHLIGHT_YELLOW,
+            PenColor::HIGHLIGHT_GREEN,
+            PenColor::HIGHLIGHT_PINK,
+            PenColor::HIGHLIGHT_GRAY,
+        ];
+
+        for scheme in ColorScheme::ALL {
+            let colors: Vec<(f64, f64, f64)> = highlighters
+                .iter()
+                .map(|color| rgb(scheme.ink(*color, BlendMode::Multiply)))
+                .collect();
+
+            for (i, a) in colors.iter().enumerate() {
+                for b in &colors[i + 1..] {
+                    let distance = (a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs();
+                    assert!(distance > 0.2, "{:?}: {:?} {:?}", scheme, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_identifiers() {
+        for scheme in ColorScheme::ALL {
+            assert_eq!(
+                ColorScheme::from_identifier(scheme.identifier()),
+                Some(scheme)
+            );
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::remarkable::format::data::{Line, Point};
+
+/// The number of points before the first new one that are processed again
+/// when a growing line is extended.
+const TAIL_CONTEXT: usize = 2;
+
+/// How the points of a line are connected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveFitting {
+    /// Points are connected by straight segments
+    Linear,
+    /// A Catmull-Rom spline through all points, sampled the given number of
+    /// times per segment
+    CatmullRom { samples: usize },
+    /// The same spline kept as Bézier curves, for backends drawing curves
+    /// themselves. The points are left as they are
+    Bezier,
+}
+
+/// The processing applied to the raw points of a line before it is rendered
+/// or exported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrokePipeline {
+    /// The maximum distance in tablet units a removed point may have from the
+    /// simplified line. `None` keeps every point
+    pub simplify_tolerance: Option<f64>,
+    /// The number of neighbouring points pressure and width are averaged
+    /// over. 0 disables smoothing
+    pub pressure_window: usize,
+    pub curve: CurveFitting,
+}
+
+/// A cubic Bézier segment between two points of a line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubicSegment {
+    pub start: Point,
+    pub control1: (f64, f64),
+    pub control2: (f64, f64),
+    pub end: Point,
+}
+
+impl Default for StrokePipeline {
+    fn default() -> Self {
+        StrokePipeline {
+            simplify_tolerance: Some(0.5),
+            pressure_window: 2,
+            curve: CurveFitting::CatmullRom { samples: 4 },
+        }
+    }
+}
+
+impl StrokePipeline {
+    /// A pipeline leaving the points untouched.
+    pub fn raw() -> Self {
+        StrokePipeline {
+            simplify_tolerance: None,
+            pressure_window: 0,
+            curve: CurveFitting::Linear,
+        }
+    }
+
+    /// Like the default pipeline, but for vector exports, which draw the
+    /// spline as curves instead of sampling it.
+    pub fn vector() -> Self {
+        StrokePipeline {
+            curve: CurveFitting::Bezier,
+            ..StrokePipeline::default()
+        }
+    }
+
+    /// Runs all stages on the points of the line.
+    pub fn process(&self, line: &Line) -> Line {
+        let mut points = match self.simplify_tolerance {
+            Some(tolerance) => simplify(&line.points, tolerance),
+            None => line.points.clone(),
+        };
+
+        points = smooth_pressure(&points, self.pressure_window);
+
+        if let CurveFitting::CatmullRom { samples } = self.curve {
+            points = catmull_rom(&points, samples);
+        }
+
+        Line {
+            points,
+            brush: line.brush,
+            color: line.color,
+        }
+    }
+
+    /// Processes only the points of a growing line starting at the given
+    /// index, so they can be drawn on top of what has been rendered before.
+    /// The result starts at the last point already drawn to connect to it.
+    /// Simplification is skipped as it would move points already drawn.
+    pub fn process_tail(&self, line: &Line, from: usize) -> Vec<Point> {
+        let start = from.saturating_sub(TAIL_CONTEXT).min(line.points.len());
+        let context = from.min(line.points.len()) - start;
+
+        let points = smooth_pressure(&line.points[start..], self.pressure_window);
+
+        let (fitted, samples) = match self.curve {
+            CurveFitting::CatmullRom { samples } => {
+                let fitted = catmull_rom(&points, samples);
+                let samples = if fitted.len() > points.len() {
+                    samples
+                } else {
+                    1
+                };
+                (fitted, samples)
+            }
+            CurveFitting::Linear | CurveFitting::Bezier => (points, 1),
+        };
+
+        fitted
+            .into_iter()
+            .skip(context.saturating_sub(1) * samples)
+            .collect()
+    }
+}
+
+/// Removes points closer than the tolerance to the line through their
+/// neighbours (Ramer–Douglas–Peucker). The first and last point are kept.
+pub fn simplify(points: &[Point], tolerance: f64) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+
+    while let Some((first, last)) = ranges.pop() {
+        let mut farthest = None;
+        let mut max_distance = tolerance;
+
+        for i in first + 1..last {
+            let distance = segment_distance(&points[i], &points[first], &points[last]);
+            if distance > max_distance {
+                max_distance = distance;
+                farthest = Some(i);
+            }
+        }
+
+        if let Some(i) = farthest {
+            keep[i] = true;
+            ranges.push((first, i));
+            ranges.push((i, last));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+/// Averages pressure and width over the given number of neighbours on each
+/// side.
+pub fn smooth_pressure(points: &[Point], window: usize) -> Vec<Point> {
+    if window == 0 {
+        return points.to_vec();
+    }
+
+    (0..points.len())
+        .map(|i| {
+            let neighbours = &points[i.saturating_sub(window)..(i + window + 1).min(points.len())];
+            let count = neighbours.len() as f64;
+
+            Point {
+                pressure: neighbours.iter().map(|p| p.pressure).sum::<f64>() / count,
+                width: neighbours.iter().map(|p| p.width).sum::<f64>() / count,
+                ..points[i].clone()
+            }
+        })
+        .collect()
+}
+
+/// Samples a uniform Catmull-Rom spline through the points. All other
+/// properties are interpolated linearly.
+pub fn catmull_rom(points: &[Point], samples: usize) -> Vec<Point> {
+    if points.len() < 3 || samples < 2 {
+        return points.to_vec();
+    }
+
+    let mut result = Vec::with_capacity((points.len() - 1) * samples + 1);
+
+    for i in 0..points.len() - 1 {
+        let p0 = &points[i.saturating_sub(1)];
+        let p1 = &points[i];
+        let p2 = &points[i + 1];
+        let p3 = &points[(i + 2).min(points.len() - 1)];
+
+        for step in 0..samples {
+            let t = step as f64 / samples as f64;
+
+            result.push(Point {
+                x: catmull_rom_value(p0.x, p1.x, p2.x, p3.x, t),
+                y: catmull_rom_value(p0.y, p1.y, p2.y, p3.y, t),
+                ..interpolate(p1, p2, t)
+            });
+        }
+    }
+
+    result.push(points[points.len() - 1].clone());
+
+    result
+}
+
+/// Converts the Catmull-Rom spline through the points into cubic Bézier
+/// segments, which follow the samples of [catmull_rom].
+pub fn bezier_segments(points: &[Point]) -> Vec<CubicSegment> {
+    if points.len() < 2 {
+        return Vec::new();
+    }
+
+    (0..points.len() - 1)
+        .map(|i| {
+            let p0 = &points[i.saturating_sub(1)];
+            let p1 = &points[i];
+            let p2 = &points[i + 1];
+            let p3 = &points[(i + 2).min(points.len() - 1)];
+
+            CubicSegment {
+                start: p1.clone(),
+                control1: (p1.x + (p2.x - p0.x) / 6.0, p1.y + (p2.y - p0.y) / 6.0),
+                control2: (p2.x - (p3.x - p1.x) / 6.0, p2.y - (p3.y - p1.y) / 6.0),
+                end: p2.clone(),
+            }
+        })
+        .collect()
+}
+
+fn catmull_rom_value(p0: f64, p1: f64, p2: f64, p3: f64, t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * (2.0 * p1 +
+        (p2 - p0) * t +
+        (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2 +
+        (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+fn interpolate(a: &Point, b: &Point, t: f64) -> Point {
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+    Point {
+        speed: lerp(a.speed, b.speed),
+        width: lerp(a.width, b.width),
+        x: lerp(a.x, b.x),
+        y: lerp(a.y, b.y),
+        pressure: lerp(a.pressure, b.pressure),
+        direction: if t < 0.5 { a.direction } else { b.direction },
+        tilt: match (a.tilt, b.tilt) {
+            (Some(a), Some(b)) => Some(lerp(a, b)),
+            (a, b) => a.or(b),
+        },
+    }
+}
+
+/// The distance of a point to the segment between two others.
+fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length = dx * dx + dy * dy;
+
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((p.x - a.x) * dx + (p.y - a.y) * dy) / length).clamp(0.0, 1.0)
+    };
+
+    ((p.x - a.x - t * dx).powi(2) + (p.y - a.y - t * dy).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use num::abs;
+
+    use super::*;
+    use crate::remarkable::format::data::{PenColor, PenType};
+
+    fn line(points: Vec<Point>) -> Line {
+        Line {
+            points,
+            brush: PenType::Fineliner,
+            color: PenColor::BLACK,
+        }
+    }
+
+    #[test]
+    fn test_simplify_removes_collinear_points() {
+        let points: Vec<Point> = (0..10)
+            .map(|i| Point::new(i as f64, 0.01 * (i % 2) as f64, 2.0, 1.0))
+            .collect();
+
+        let simplified = simplify(&points, 0.5);
+
+        assert_eq!(simplified.len(), 2);
+        assert_eq!(simplified[0].x, 0.0);
+        assert_eq!(simplified[1].x, 9.0);
+    }
+
+    #[test]
+    fn test_simplify_keeps_corners() {
+        let points = vec![
+            Point::new(0.0, 0.0, 2.0, 1.0),
+            Point::new(5.0, 0.0, 2.0, 1.0),
+            Point::new(10.0, 0.0, 2.0, 1.0),
+            Point::new(10.0, 5.0, 2.0, 1.0),
+            Point::new(10.0, 10.0, 2.0, 1.0),
+        ];
+
+        let xs: Vec<(f64, f64)> = simplify(&points, 0.5).iter().map(|p| (p.x, p.y)).collect();
+
+        assert_eq!(xs, vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+    }
+
+    #[test]
+    fn test_smooth_pressure() {
+        let points = vec![
+            Point::new(0.0, 0.0, 2.0, 0.0),
+            Point::new(1.0, 0.0, 2.0, 1.0),
+            Point::new(2.0, 0.0, 2.0, 0.0),
+        ];
+
+        let smoothed = smooth_pressure(&points, 1);
+
+        assert_eq!(smoothed.len(), 3);
+        assert!(abs(smoothed[1].pressure - 1.0 / 3.0) < 1e-9);
+        assert_eq!(smoothed[0].pressure, 0.5);
+        assert_eq!(smoothed[1].x, 1.0);
+    }
+
+    #[test]
+    fn test_catmull_rom_passes_through_points() {
+        let points = vec![
+            Point::new(0.0, 0.0, 2.0, 1.0),
+            Point::new(10.0, 10.0, 2.0, 1.0),
+            Point::new(20.0, 0.0, 2.0, 1.0),
+        ];
+
+        let curve = catmull_rom(&points, 4);
+
+        assert_eq!(curve.len(), 9);
+        assert_eq!((curve[4].x, curve[4].y), (10.0, 10.0));
+        assert_eq!((curve[8].x, curve[8].y), (20.0, 0.0));
+        assert!(curve[3].y > 5.0);
+    }
+
+    #[test]
+    fn test_bezier_segments_join() {
+        let points = vec![
+            Point::new(0.0, 0.0, 2.0, 1.0),
+            Point::new(10.0, 10.0, 2.0, 1.0),
+            Point::new(20.0, 0.0, 2.0, 1.0),
+            Point::new(30.0, 10.0, 2.0, 1.0),
+        ];
+
+        let segments = bezier_segments(&points);
+
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].end, segments[1].start);
+        assert_eq!(segments[2].end.x, 30.0);
+    }
+
+    #[test]
+    fn test_tail_matches_full_line() {
+        let points: Vec<Point> = (0..8)
+            .map(|i| Point::new(i as f64, (i * i) as f64, 2.0, 0.1 * i as f64))
+            .collect();
+        let line = line(points);
+        let pipeline = StrokePipeline {
+            simplify_tolerance: None,
+            pressure_window: 0,
+            curve: CurveFitting::CatmullRom { samples: 4 },
+        };
+
+        let full = pipeline.process(&line).points;
+        let tail = pipeline.process_tail(&line, 5);
+
+        assert_eq!(&full[full.len() - tail.len()..], tail.as_slice());
+        assert_eq!(tail.len(), 3 * 4 + 1);
+        assert_eq!(tail[0].x, 4.0);
+        assert_eq!(pipeline.process_tail(&line, 8).len(), 1);
+        assert_eq!(pipeline.process_tail(&line, 0), full);
+    }
+
+    #[test]
+    fn test_vector_keeps_points_for_curves() {
+        let points: Vec<Point> = (0..8)
+            .map(|i| Point::new(i as f64, (i * i) as f64, 2.0, 1.0))
+            .collect();
+        let line = line(points);
+
+        let sampled = StrokePipeline::default().process(&line).points;
+        let vector = StrokePipeline::vector().process(&line).points;
+
+        assert!(vector.len() <= line.points.len());
+        assert!(sampled.len() > line.points.len());
+        assert_eq!(StrokePipeline::vector().process_tail(&line, 5).len(), 4);
+    }
+
+    #[test]
+    fn test_raw_keeps_points() {
+        let line = line(vec![
+            Point::new(0.0, 0.0, 2.0, 1.0),
+            Point::new(1.0, 1.0, 2.0, 1.0),
+            Point::new(2.0, 0.0, 2.0, 1.0),
+        ]);
+
+        assert_eq!(StrokePipeline::raw().process(&line), line);
+    }
+}
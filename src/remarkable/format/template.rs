@@ -0,0 +1,389 @@
+use std::{fs, path::Path};
+
+use json::JsonValue;
+use log::{debug, warn};
+
+/// The name the tablet uses for pages without a template.
+pub const BLANK: &str = "Blank";
+
+/// The line width of template lines in tablet units.
+const LINE_WIDTH: f64 = 2.0;
+
+/// The radius of template dots in tablet units.
+const DOT_RADIUS: f64 = 2.5;
+
+/// The space above the first line of lined templates in tablet units.
+const HEADER: f64 = 160.0;
+
+/// The distance of the margin line from the left edge in tablet units.
+const MARGIN: f64 = 150.0;
+
+/// A page background the tablet draws below the lines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Template {
+    Blank,
+    /// Horizontal lines below a header, optionally with a margin line
+    Lines {
+        spacing: f64,
+        margin: bool,
+    },
+    Grid {
+        spacing: f64,
+    },
+    Dots {
+        spacing: f64,
+    },
+    /// Lines with a box to tick in front of each
+    Checklist {
+        spacing: f64,
+    },
+    /// A planner page with a line per hour
+    Day,
+    /// A planner page with a box per week day
+    Week,
+    /// A template without a vector version, looked up by its name
+    Custom(String),
+}
+
+/// A part of a template in page coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateShape {
+    Line {
+        from: (f64, f64),
+        to: (f64, f64),
+        width: f64,
+    },
+    Dot {
+        center: (f64, f64),
+        radius: f64,
+    },
+    Rectangle {
+        origin: (f64, f64),
+        size: (f64, f64),
+        width: f64,
+    },
+}
+
+/// The template chosen for every page of a document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageTemplates {
+    pages: Vec<String>,
+}
+
+impl Template {
+    /// Maps the template names stored in document metadata. The prefix
+    /// telling portrait ("P") and landscape ("LS") versions apart is
+    /// ignored, as the template is drawn for the page's orientation anyway.
+    pub fn from_name(name: &str) -> Self {
+        let base = name
+            .strip_prefix("P ")
+            .or_else(|| name.strip_prefix("LS "))
+            .unwrap_or(name);
+
+        match base {
+            "" | BLANK => Template::Blank,
+            "Lines small" => Template::Lines {
+                spacing: 44.0,
+                margin: false,
+            },
+            "Lines medium" => Template::Lines {
+                spacing: 66.0,
+                margin: false,
+            },
+            "Lines large" => Template::Lines {
+                spacing: 88.0,
+                margin: false,
+            },
+            "Margin small" => Template::Lines {
+                spacing: 44.0,
+                margin: true,
+            },
+            "Margin medium" => Template::Lines {
+                spacing: 66.0,
+                margin: true,
+            },
+            "Margin large" => Template::Lines {
+                spacing: 88.0,
+                margin: true,
+            },
+            "Grid small" => Template::Grid { spacing: 44.0 },
+            "Grid medium" => Template::Grid { spacing: 66.0 },
+            "Grid large" => Template::Grid { spacing: 88.0 },
+            "Dots S" | "Dots small" => Template::Dots { spacing: 44.0 },
+            "Dots" | "Dots medium" => Template::Dots { spacing: 66.0 },
+            "Dots large" => Template::Dots { spacing: 88.0 },
+            "Checklist" | "Checklist double" => Template::Checklist { spacing: 88.0 },
+            "Day" | "Daily planner" => Template::Day,
+            "Week" | "Weekplanner" | "Week US" => Template::Week,
+            _ => {
+                debug!("No vector version of template {}", name);
+                Template::Custom(name.into())
+            }
+        }
+    }
+
+    /// The shapes making up the template on a page of the given size.
+    pub fn shapes(&self, width: f64, height: f64) -> Vec<TemplateShape> {
+        let horizontal = |y: f64, from: f64| TemplateShape::Line {
+            from: (from, y),
+            to: (width, y),
+            width: LINE_WIDTH,
+        };
+        let vertical = |x: f64, from: f64| TemplateShape::Line {
+            from: (x, from),
+            to: (x, height),
+            width: LINE_WIDTH,
+        };
+
+        match self {
+            Template::Blank | Template::Custom(_) => vec![],
+            Template::Lines { spacing, margin } => {
+                let mut shapes: Vec<TemplateShape> = steps(HEADER, height, *spacing)
+                    .map(|y| horizontal(y, 0.0))
+                    .collect();
+
+                if *margin {
+                    shapes.push(vertical(MARGIN, 0.0));
+                }
+
+                shapes
+            }
+            Template::Grid { spacing } => steps(*spacing, height, *spacing)
+                .map(|y| horizontal(y, 0.0))
+                .chain(steps(*spacing, width, *spacing).map(|x| vertical(x, 0.0)))
+                .collect(),
+            Template::Dots { spacing } => steps(*spacing, height, *spacing)
+                .flat_map(|y| {
+                    steps(*spacing, width, *spacing).map(move |x| TemplateShape::Dot {
+                        center: (x, y),
+                        radius: DOT_RADIUS,
+                    })
+                })
+                .collect(),
+            Template::Checklist { spacing } => steps(HEADER, height, *spacing)
+                .skip(1)
+                .flat_map(|y| {
+                    let size = spacing / 2.0;
+                    vec![
+                        horizontal(y, MARGIN),
+                        TemplateShape::Rectangle {
+                            origin: ((MARGIN - size) / 2.0, y - size * 1.5),
+                            size: (size, size),
+                            width: LINE_WIDTH,
+                        },
+                    ]
+                })
+                .collect(),
+            Template::Day => {
+                // a line per hour from 8 to 20 below the date
+                let spacing = (height - HEADER) / 13.0;
+                let mut shapes = vec![horizontal(HEADER, 0.0), vertical(MARGIN, HEADER)];
+                shapes.extend(steps(HEADER + spacing, height, spacing).map(|y| horizontal(y, 0.0)));
+                shapes
+            }
+            Template::Week => {
+                // two columns of days with the last cell left for notes
+                let rows = 4.0;
+                let row_height = (height - HEADER) / rows;
+                let mut shapes = vec![vertical(width / 2.0, HEADER)];
+                shapes.extend(
+                    (0..rows as usize).map(|row| horizontal(HEADER + row as f64 * row_height, 0.0)),
+                );
+                shapes
+            }
+        }
+    }
+}
+
+impl PageTemplates {
+    /// Uses the given template names, one per page.
+    pub fn new(pages: Vec<String>) -> Self {
+        PageTemplates { pages }
+    }
+
+    /// Reads the `.pagedata` file of a document, which lists one template
+    /// name per line.
+    pub fn from_pagedata(pagedata: &str) -> Self {
+        PageTemplates {
+            pages: pagedata
+                .lines()
+                .map(|line| line.trim().to_string())
+                .collect(),
+        }
+    }
+
+    /// Reads the templates from the `.content` or `.pagedata` file of a
+    /// document, told apart by the extension.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let data =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("pagedata") => Ok(PageTemplates::from_pagedata(&data)),
+            _ => PageTemplates::from_content(&data)
+                .map_err(|e| format!("Failed to parse {:?}: {}", path, e)),
+        }
+    }
+
+    /// Reads the templates from the `.content` file of a document. Newer
+    /// firmware stores them with every page, older firmware as a separate
+    /// list.
+    pub fn from_content(content: &str) -> Result<Self, String> {
+        let content = json::parse(content).map_err(|e| e.to_string())?;
+
+        let pages = &content["cPages"]["pages"];
+        if pages.is_array() {
+            return Ok(PageTemplates {
+                pages: pages
+                    .members()
+                    .filter(|page| !page["deleted"].is_object())
+                    .map(|page| template_value(&page["template"]))
+                    .collect(),
+            });
+        }
+
+        let templates = &content["pageTemplates"];
+        if templates.is_array() {
+            return Ok(PageTemplates {
+                pages: templates.members().map(template_value).collect(),
+            });
+        }
+
+        warn!("Document content lists no templates");
+        Ok(PageTemplates::default())
+    }
+
+    /// Whether no page has a template chosen.
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The name of the template of the given page.
+    pub fn name(&self, page: u32) -> &str {
+        self.pages
+            .get(page as usize)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+            .unwrap_or(BLANK)
+    }
+
+    pub fn template(&self, page: u32) -> Template {
+        Template::from_name(self.name(page))
+    }
+}
+
+/// Reads a template name stored either directly or as a timestamped value.
+fn template_value(value: &JsonValue) -> String {
+    value
+        .as_str()
+        .or_else(|| value["value"].as_str())
+        .unwrap_or(BLANK)
+        .to_string()
+}
+
+/// The positions from `start` to below `end` in the given steps.
+fn steps(start: f64, end: f64, step: f64) -> impl Iterator<Item = f64> {
+    (0..)
+        .map(move |i| start + i as f64 * step)
+        .take_while(move |position| *position < end)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_template_names() {
+        assert_eq!(Template::from_name("Blank"), Template::Blank);
+        assert_eq!(
+            Template::from_name("P Grid medium"),
+            Template::Grid { spacing: 66.0 }
+        );
+        assert_eq!(
+            Template::from_name("LS Lines small"),
+            Template::Lines {
+                spacing: 44.0,
+                margin: false
+            }
+        );
+        assert_eq!(
+            Template::from_name("P Isometric"),
+            Template::Custom("P Isometric".into())
+        );
+    }
+
+    #[test]
+    fn test_grid_covers_page() {
+        let shapes = Template::Grid { spacing: 100.0 }.shapes(300.0, 450.0);
+
+        // 4 horizontal and 2 vertical lines
+        assert_eq!(shapes.len(), 6);
+        assert!(shapes.contains(&TemplateShape::Line {
+            from: (0.0, 400.0),
+            to: (300.0, 400.0),
+            width: LINE_WIDTH
+        }));
+    }
+
+    #[test]
+    fn test_dots() {
+        let shapes = Template::Dots { spacing: 100.0 }.shapes(250.0, 250.0);
+
+        assert_eq!(shapes.len(), 4);
+    }
+
+    #[test]
+    fn test_pagedata() {
+        let templates = PageTemplates::from_pagedata("Blank\nP Grid small\n\n");
+
+        assert_eq!(templates.name(1), "P Grid small");
+        assert_eq!(templates.name(2), BLANK);
+        assert_eq!(templates.template(7), Template::Blank);
+    }
+
+    #[test]
+    fn test_content() {
+        let content = r#"{
+            "cPages": {
+                "pages": [
+                    { "id": "a", "template": { "timestamp": "1:2", "value": "P Dots S" } },
+                    { "id": "b", "deleted": { "timestamp": "1:3", "value": 1 } },
+                    { "id": "c", "template": { "timestamp": "1:2", "value": "P Lines medium" } }
+                ]
+            }
+        }"#;
+
+        let templates = PageTemplates::from_content(content).unwrap();
+
+        assert_eq!(templates.name(0), "P Dots S");
+        assert_eq!(templates.name(1), "P Lines medium");
+
+        let old = PageTemplates::from_content(r#"{ "pageTemplates": ["Blank", "P Day"] }"#);
+        assert_eq!(old.unwrap().template(1), Template::Day);
+    }
+
+    #[test]
+    fn test_templates_from_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&directory).unwrap();
+
+        let pagedata = directory.join("notes.pagedata");
+        fs::write(&pagedata, "Blank\nP Lines small\n").unwrap();
+        let content = directory.join("notes.content");
+        fs::write(&content, r#"{ "pageTemplates": ["P Dots S"] }"#).unwrap();
+
+        assert_eq!(
+            PageTemplates::from_file(&pagedata).unwrap().name(1),
+            "P Lines small"
+        );
+        assert_eq!(
+            PageTemplates::from_file(&content).unwrap().name(0),
+            "P Dots S"
+        );
+        assert!(PageTemplates::from_file(&directory.join("missing.content")).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
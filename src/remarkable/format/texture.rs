@@ -0,0 +1,219 @@
+use crate::remarkable::format::{brush::BrushModel, data::Point};
+
+/// The radius of a single grain of graphite in tablet units.
+pub const GRAIN_RADIUS: f64 = 0.6;
+
+/// The share of the bristles that still touch the paper at no pressure.
+const LIGHT_BRISTLES: f64 = 0.4;
+
+/// A streak left by a single bristle of a brush.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BristleStroke {
+    /// The centre line of the streak
+    pub points: Vec<(f64, f64)>,
+    pub width: f64,
+}
+
+/// Scatters grains over the area covered by the stroke. The number of grains
+/// on each segment follows its pressure, so light strokes look grainy and
+/// heavy ones almost solid. Grains are placed pseudo-randomly but depend only
+/// on the first point and the index of the segment. Given the raw points of
+/// a line, the grains already drawn stay where they are as the line grows.
+pub fn grain(points: &[Point], model: &BrushModel, density: f64) -> Vec<(f64, f64)> {
+    let mut grains = vec![];
+
+    let line_seed = match points.first() {
+        Some(first) => hash(first.x.to_bits() ^ hash(first.y.to_bits())),
+        None => return grains,
+    };
+
+    for (index, segment) in points.windows(2).enumerate() {
+        let (a, b) = (&segment[0], &segment[1]);
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let length = dx.hypot(dy);
+
+        if length < f64::EPSILON {
+            continue;
+        }
+
+        let width = (model.style(a).width + model.style(b).width) / 2.0;
+        let pressure = ((a.pressure + b.pressure) / 2.0).clamp(0.0, 1.0);
+        let count = (length * width * density * pressure).round() as u64;
+        let (nx, ny) = (-dy / length, dx / length);
+
+        let seed = hash(line_seed ^ hash(index as u64));
+        for i in 0..count {
+            let along = unit(hash(seed ^ (2 * i)));
+            let across = unit(hash(seed ^ (2 * i + 1))) - 0.5;
+
+            grains.push((
+                a.x + dx * along + nx * across * width,
+                a.y + dy * along + ny * across * width,
+            ));
+        }
+    }
+
+    grains
+}
+
+/// Splits the stroke into the streaks of the given number of bristles laid
+/// out across its width. Every bristle only touches the paper above its own
+/// pressure threshold, so light strokes show gaps between the streaks.
+pub fn bristles(points: &[Point], model: &BrushModel, count: usize) -> Vec<BristleStroke> {
+    let mut strokes = vec![];
+
+    if points.len() < 2 || count == 0 {
+        return strokes;
+    }
+
+    for bristle in 0..count {
+        let position = (bristle as f64 + 0.5) / count as f64 - 0.5;
+        let jitter = unit(hash(bristle as u64));
+        let threshold = jitter - LIGHT_BRISTLES;
+
+        let mut streak: Vec<(f64, f64)> = vec![];
+        let mut widths = 0.0;
+
+        for (i, point) in points.iter().enumerate() {
+            let touches = point.pressure.clamp(0.0, 1.0) >= threshold;
+
+            if touches {
+                let (nx, ny) = normal(points, i);
+                let width = model.style(point).width;
+
+                streak.push((
+                    point.x + nx * position * width,
+                    point.y + ny * position * width,
+                ));
+                widths += width;
+            }
+
+            if !touches || i == points.len() - 1 {
+                if streak.len() > 1 {
+                    strokes.push(BristleStroke {
+                        width: widths / streak.len() as f64 / count as f64 * (1.0 + 0.5 * jitter),
+                        points: streak.clone(),
+                    });
+                }
+
+                streak.clear();
+                widths = 0.0;
+            }
+        }
+    }
+
+    strokes
+}
+
+/// The unit normal of the stroke at the given point.
+fn normal(points: &[Point], i: usize) -> (f64, f64) {
+    let a = &points[i.saturating_sub(1)];
+    let b = &points[(i + 1).min(points.len() - 1)];
+    let length = (b.x - a.x).hypot(b.y - a.y);
+
+    if length < f64::EPSILON {
+        (0.0, 0.0)
+    } else {
+        (-(b.y - a.y) / length, (b.x - a.x) / length)
+    }
+}
+
+/// Mixes the bits of a value (splitmix64).
+fn hash(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Maps a hash onto [0, 1).
+fn unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::format::data::PenType;
+
+    #[test]
+    fn test_grain_follows_pressure() {
+        let model = PenType::SharpPencil.model();
+
+        let light = grain(
+            &[
+                Point::new(0.0, 0.0, 4.0, 0.2),
+                Point::new(100.0, 0.0, 4.0, 0.2),
+            ],
+            &model,
+            1.0,
+        );
+        let heavy = grain(
+            &[
+                Point::new(0.0, 0.0, 4.0, 1.0),
+                Point::new(100.0, 0.0, 4.0, 1.0),
+            ],
+            &model,
+            1.0,
+        );
+
+        assert!(light.len() < heavy.len());
+        assert!(heavy
+            .iter()
+            .all(|(x, y)| (0.0..=100.0).contains(x) && y.abs() <= 2.0));
+    }
+
+    #[test]
+    fn test_grain_is_stable() {
+        let model = PenType::SharpPencil.model();
+        let points = [
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+            Point::new(20.0, 0.0, 4.0, 1.0),
+        ];
+
+        let whole = grain(&points, &model, 1.0);
+        let start = grain(&points[..2], &model, 1.0);
+
+        assert!(!start.is_empty());
+        assert_eq!(whole[..start.len()], start[..]);
+
+        // the same segment gets other grains on another line
+        let moved = [
+            Point::new(5.0, 0.0, 4.0, 1.0),
+            Point::new(10.0, 0.0, 4.0, 1.0),
+            Point::new(20.0, 0.0, 4.0, 1.0),
+        ];
+        let other = grain(&moved, &model, 1.0);
+        let count = whole.len() - start.len();
+        assert_ne!(whole[start.len()..], other[other.len() - count..]);
+    }
+
+    #[test]
+    fn test_bristles_spread_across_width() {
+        let model = PenType::Brush.model();
+        let points = [
+            Point::new(0.0, 0.0, 4.0, 1.0),
+            Point::new(50.0, 0.0, 4.0, 1.0),
+            Point::new(100.0, 0.0, 4.0, 1.0),
+        ];
+
+        let strokes = bristles(&points, &model, 4);
+
+        assert_eq!(strokes.len(), 4);
+        let offsets: Vec<f64> = strokes.iter().map(|s| s.points[0].1).collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_light_brush_leaves_gaps() {
+        let model = PenType::Brush.model();
+        let points = [
+            Point::new(0.0, 0.0, 4.0, 0.0),
+            Point::new(50.0, 0.0, 4.0, 0.0),
+            Point::new(100.0, 0.0, 4.0, 0.0),
+        ];
+
+        assert!(bristles(&points, &model, 16).len() < 16);
+    }
+}
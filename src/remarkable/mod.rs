@@ -12,7 +12,7 @@ pub struct BaseDomains {
 
 impl Default for BaseDomains {
     fn default() -> Self {
-        let session_base = PROTOCOL.to_string() + &REMARKABLE_SESSION_BASE_API;
+        let session_base = PROTOCOL.to_string() + REMARKABLE_SESSION_BASE_API;
         BaseDomains {
             notifications: "https://notifications.remarkable.com".to_string(),
             storage: "https://storage.remarkable.com".to_string(),
@@ -24,13 +24,17 @@ impl Default for BaseDomains {
 
 mod constants;
 
+pub mod documents;
+pub mod files;
 pub mod format;
+pub mod recording;
+pub mod remarkable_tree;
 
 pub mod tokens;
 pub mod web_socket;
 
 #[async_trait]
-pub trait RMTokenInterface {
+pub trait RMTokenInterface: Send + Sync {
     /// Queries a new session token from the remarkable API.
     /// This token will be refreshed using the current user token
     async fn create_session_token(&self, user_token: &str) -> Result<String, String>;
@@ -0,0 +1,509 @@
+//! Recordings of live sessions. Everything received on the live view socket
+//! is stored with the time it arrived, so sessions can be archived, replayed
+//! and used as test fixtures.
+//!
+//! A recording is a header followed by records. All numbers are little
+//! endian.
+//!
+//! ```text
+//! header:  magic      8 bytes, "RVSESSN\0"
+//!          version    u16, currently 1
+//!          started    u64, milliseconds since the Unix epoch
+//! record:  kind       u8, see RecordKind
+//!          timestamp  u64, microseconds since the recording started
+//!          length     u32
+//!          payload    `length` bytes
+//! ```
+//!
+//! Timestamps are taken from a monotonic clock and never decrease. Readers
+//! skip records of unknown kinds, so adding a kind keeps the version. Any
+//! other change of the layout increments it.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+
+/// Identifies a file as a recording.
+pub const MAGIC: &[u8; 8] = b"RVSESSN\0";
+
+/// The version of the layout written.
+pub const VERSION: u16 = 1;
+
+/// The extension of recording files.
+pub const EXTENSION: &str = "rvsession";
+
+/// What a record contains.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RecordKind {
+    /// A binary frame as sent by the tablet
+    Frame,
+    /// A text message received on the socket, as UTF-8
+    Text,
+    /// The socket connected, without payload
+    Opened,
+    /// The socket was closed, with the reason as UTF-8 if one was given
+    Closed,
+    /// The socket failed, with a description as UTF-8
+    Error,
+}
+
+/// A single event of a recorded session.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Record {
+    pub kind: RecordKind,
+    /// The time since the recording started
+    pub timestamp: Duration,
+    pub payload: Vec<u8>,
+}
+
+/// Writes records into a recording.
+#[derive(Debug)]
+pub struct SessionWriter<W: Write> {
+    writer: W,
+    /// The timestamp of the last record
+    last: Duration,
+}
+
+/// Reads the records of a recording in order.
+#[derive(Debug)]
+pub struct SessionReader<R: Read> {
+    reader: R,
+    version: u16,
+    started: SystemTime,
+}
+
+/// Records the events of one live session while it is switched on. Clones
+/// share the recording, so the socket task and the window can both use it.
+#[derive(Debug, Clone, Default)]
+pub struct SessionRecorder {
+    recording: Arc<Mutex<Option<Recording>>>,
+}
+
+#[derive(Debug)]
+struct Recording {
+    writer: SessionWriter<BufWriter<File>>,
+    path: PathBuf,
+    /// The monotonic time the recording started at
+    start: Instant,
+}
+
+impl RecordKind {
+    pub fn code(&self) -> u8 {
+        match self {
+            RecordKind::Frame => 1,
+            RecordKind::Text => 2,
+            RecordKind::Opened => 3,
+            RecordKind::Closed => 4,
+            RecordKind::Error => 5,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(RecordKind::Frame),
+            2 => Some(RecordKind::Text),
+            3 => Some(RecordKind::Opened),
+            4 => Some(RecordKind::Closed),
+            5 => Some(RecordKind::Error),
+            _ => None,
+        }
+    }
+}
+
+impl<W: Write> SessionWriter<W> {
+    /// Writes the header of a recording started at the given time.
+    pub fn new(mut writer: W, started: SystemTime) -> io::Result<Self> {
+        let started = started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&started.to_le_bytes())?;
+
+        Ok(SessionWriter {
+            writer,
+            last: Duration::ZERO,
+        })
+    }
+
+    /// Appends a record. Timestamps earlier than the last one are raised to
+    /// it, so readers can rely on the order.
+    pub fn write(
+        &mut self,
+        kind: RecordKind,
+        timestamp: Duration,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let length = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Record is too large"))?;
+
+        self.last = self.last.max(timestamp);
+
+        self.writer.write_all(&[kind.code()])?;
+        self.writer
+            .write_all(&(self.last.as_micros() as u64).to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(payload)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R: Read> SessionReader<R> {
+    /// Reads the header of a recording.
+    pub fn new(mut reader: R) -> Result<Self, String> {
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| "Not a recording: file is too short".to_string())?;
+
+        if &magic != MAGIC {
+            return Err("Not a recording: unknown file type".into());
+        }
+
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+
+        if version > VERSION {
+            return Err(format!(
+                "Recording version {} is newer than the supported version {}",
+                version, VERSION
+            ));
+        }
+
+        let started = u64::from_le_bytes(read_array(&mut reader)?);
+
+        Ok(SessionReader {
+            reader,
+            version,
+            started: UNIX_EPOCH + Duration::from_millis(started),
+        })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// The wall clock time the recording started at.
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    /// Reads the next record of a known kind, or `None` at the end of the
+    /// recording.
+    fn read_record(&mut self) -> Result<Option<Record>, String> {
+        loop {
+            let mut code = [0; 1];
+            match self.reader.read_exact(&mut code) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.to_string()),
+            }
+
+            let timestamp = u64::from_le_bytes(read_array(&mut self.reader)?);
+            let length = u32::from_le_bytes(read_array(&mut self.reader)?);
+
+            // the length is not trusted to allocate the payload up front, as a
+            // broken file could claim gigabytes
+            let mut payload = Vec::new();
+            (&mut self.reader)
+                .take(length as u64)
+                .read_to_end(&mut payload)
+                .map_err(|e| e.to_string())?;
+
+            if payload.len() < length as usize {
+                return Err("Recording ends within a record".to_string());
+            }
+
+            match RecordKind::from_code(code[0]) {
+                Some(kind) => {
+                    return Ok(Some(Record {
+                        kind,
+                        timestamp: Duration::from_micros(timestamp),
+                        payload,
+                    }))
+                }
+                None => debug!("Skipping record of unknown kind {}", code[0]),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for SessionReader<R> {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], String> {
+    let mut bytes = [0; N];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|_| "Recording ends within a record".to_string())?;
+
+    Ok(bytes)
+}
+
+impl SessionRecorder {
+    pub fn new() -> Self {
+        SessionRecorder::default()
+    }
+
+    /// Starts recording into a new file in the given directory and returns
+    /// its path. A running recording is finished first.
+    pub fn start(&self, directory: &Path) -> Result<PathBuf, String> {
+        self.stop();
+
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+
+        let started = SystemTime::now();
+        let millis = started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = directory.join(format!("session-{}.{}", millis, EXTENSION));
+
+        let file = File::create(&path).map_err(|e| e.to_string())?;
+        let writer =
+            SessionWriter::new(BufWriter::new(file), started).map_err(|e| e.to_string())?;
+
+        debug!("Recording session to {:?}", path);
+
+        *self.recording.lock().unwrap() = Some(Recording {
+            writer,
+            path: path.clone(),
+            start: Instant::now(),
+        });
+
+        Ok(path)
+    }
+
+    /// Finishes the recording and returns the path it was written to.
+    pub fn stop(&self) -> Option<PathBuf> {
+        let mut recording = self.recording.lock().unwrap().take()?;
+
+        if let Err(e) = recording.writer.flush() {
+            warn!("Failed to finish recording {:?}: {}", recording.path, e);
+        }
+
+        debug!("Stopped recording to {:?}", recording.path);
+        Some(recording.path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.lock().unwrap().is_some()
+    }
+
+    /// The file currently recorded into.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.recording
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|recording| recording.path.clone())
+    }
+
+    pub fn frame(&self, data: &[u8]) {
+        self.record(RecordKind::Frame, data);
+    }
+
+    pub fn text(&self, text: &str) {
+        self.record(RecordKind::Text, text.as_bytes());
+    }
+
+    pub fn opened(&self) {
+        self.record(RecordKind::Opened, &[]);
+    }
+
+    pub fn closed(&self, reason: &str) {
+        self.record(RecordKind::Closed, reason.as_bytes());
+    }
+
+    pub fn error(&self, description: &str) {
+        self.record(RecordKind::Error, description.as_bytes());
+    }
+
+    /// Writes a record if recording, stopping the recording if the file
+    /// cannot be written.
+    fn record(&self, kind: RecordKind, payload: &[u8]) {
+        let mut guard = self.recording.lock().unwrap();
+
+        let recording = match guard.as_mut() {
+            Some(recording) => recording,
+            None => return,
+        };
+
+        let timestamp = recording.start.elapsed();
+        let result = recording
+            .writer
+            .write(kind, timestamp, payload)
+            // flushed right away, so a crash does not lose the session
+            .and_then(|_| recording.writer.flush());
+
+        if let Err(e) = result {
+            warn!("Stopped recording to {:?}: {}", recording.path, e);
+            *guard = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn recording(records: &[(RecordKind, u64, &[u8])]) -> Vec<u8> {
+        let started = UNIX_EPOCH + Duration::from_millis(1_600_000_000_000);
+        let mut writer = SessionWriter::new(vec![], started).unwrap();
+
+        for (kind, timestamp, payload) in records {
+            writer
+                .write(*kind, Duration::from_millis(*timestamp), payload)
+                .unwrap();
+        }
+
+        writer.writer
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = recording(&[
+            (RecordKind::Opened, 0, b""),
+            (RecordKind::Frame, 20, &[1, 2, 3]),
+            (RecordKind::Closed, 45, b"bye"),
+        ]);
+
+        let reader = SessionReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.version(), VERSION);
+        assert_eq!(
+            reader.started(),
+            UNIX_EPOCH + Duration::from_millis(1_600_000_000_000)
+        );
+
+        let records: Vec<Record> = reader.map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    kind: RecordKind::Opened,
+                    timestamp: Duration::ZERO,
+                    payload: vec![],
+                },
+                Record {
+                    kind: RecordKind::Frame,
+                    timestamp: Duration::from_millis(20),
+                    payload: vec![1, 2, 3],
+                },
+                Record {
+                    kind: RecordKind::Closed,
+                    timestamp: Duration::from_millis(45),
+                    payload: b"bye".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamps_never_decrease() {
+        let data = recording(&[(RecordKind::Frame, 30, b"a"), (RecordKind::Frame, 10, b"b")]);
+
+        let timestamps: Vec<Duration> = SessionReader::new(Cursor::new(data))
+            .unwrap()
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+
+        assert_eq!(
+            timestamps,
+            vec![Duration::from_millis(30), Duration::from_millis(30)]
+        );
+    }
+
+    #[test]
+    fn test_unknown_records_are_skipped() {
+        let mut data = recording(&[]);
+        data.extend_from_slice(&[99]);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[7, 7]);
+        // without the header of 18 bytes
+        data.extend_from_slice(&recording(&[(RecordKind::Text, 5, b"hi")])[18..]);
+
+        let records: Vec<Record> = SessionReader::new(Cursor::new(data))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kind, RecordKind::Text);
+    }
+
+    #[test]
+    fn test_rejects_broken_files() {
+        assert!(SessionReader::new(Cursor::new(b"%PDF-1.4 something".to_vec())).is_err());
+
+        let mut newer = recording(&[]);
+        newer[8..10].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(SessionReader::new(Cursor::new(newer)).is_err());
+
+        let mut truncated = recording(&[(RecordKind::Frame, 0, &[1, 2, 3, 4])]);
+        truncated.truncate(truncated.len() - 2);
+        let mut reader = SessionReader::new(Cursor::new(truncated)).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated_record_with_huge_length() {
+        let mut data = recording(&[]);
+        data.push(RecordKind::Frame.code());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+
+        let mut reader = SessionReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(
+            reader.next().unwrap(),
+            Err("Recording ends within a record".to_string())
+        );
+    }
+
+    #[test]
+    fn test_recorder_writes_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let recorder = SessionRecorder::new();
+
+        recorder.frame(b"ignored");
+        let path = recorder.start(&directory).unwrap();
+        assert!(recorder.is_recording());
+
+        recorder.opened();
+        recorder.clone().frame(&[4, 2]);
+        assert_eq!(recorder.stop(), Some(path.clone()));
+        recorder.frame(b"ignored");
+
+        let records: Vec<Record> = SessionReader::new(File::open(&path).unwrap())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].kind, RecordKind::Frame);
+        assert_eq!(records[1].payload, vec![4, 2]);
+        assert!(records[0].timestamp <= records[1].timestamp);
+    }
+}
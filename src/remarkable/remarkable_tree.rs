@@ -0,0 +1,20 @@
+/// An entry of the document tree in the cloud, either a document or a
+/// folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    /// The name shown to the user
+    pub name: String,
+    pub id: String,
+    /// The id of the folder containing the item
+    pub parent: Option<String>,
+}
+
+/// A document in the cloud.
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub item: Item,
+    /// The page last opened on the tablet
+    pub current_page: u32,
+    /// Increases with every change synced to the cloud
+    pub version: u32,
+}
@@ -155,7 +155,7 @@ mod test {
         let token = RMTokens::new(domains);
 
         let res = token.login("1234").await;
-        assert_eq!(res.is_ok(), true);
+        assert!(res.is_ok());
     }
 
     #[tokio::test]
@@ -180,7 +180,7 @@ mod test {
         let token = RMTokens::new(domains);
 
         let res = token.create_session_token("1234").await;
-        assert_eq!(res.is_ok(), true);
+        assert!(res.is_ok());
     }
 
     #[tokio::test]
@@ -205,6 +205,6 @@ mod test {
         let token = RMTokens::new(domains);
 
         let res = token.session_okay("1234").await;
-        assert_eq!(res, true);
+        assert!(res);
     }
 }
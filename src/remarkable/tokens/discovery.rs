@@ -101,7 +101,7 @@ mod test {
 
     #[tokio::test]
     async fn test_discover() {
-        env_logger::try_init();
+        let _ = env_logger::try_init();
 
         let mut host = HashMap::new();
         host.insert("Host".to_string(), "test".to_string());
@@ -3,11 +3,19 @@ use log::{debug, error, trace, warn};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{client::IntoClientRequest, http, Error, Message::Text},
+    tungstenite::{
+        client::IntoClientRequest,
+        http, Error,
+        Message::{Binary, Close, Text},
+    },
     MaybeTlsStream, WebSocketStream,
 };
 
-use crate::remarkable::{constants::REMARKABLE_NOTIFICATION_SOCKET_PATH, BaseDomains};
+use crate::remarkable::{
+    constants::{REMARKABLE_LIVEVIEW_SUBSCRIBER_PATH, REMARKABLE_NOTIFICATION_SOCKET_PATH},
+    recording::SessionRecorder,
+    BaseDomains,
+};
 
 const PROTOCOL: &str = "wss://";
 
@@ -18,7 +26,7 @@ const PROTOCOL: &str = "wss://";
 pub enum SocketEvent {
     DocAdded(String),
     /**
-     * Containing (session_token, auth0 uid)
+     * Containing (session_token, raw message)
      */
     LiveSyncStarted(String, String),
 }
@@ -51,38 +59,100 @@ pub async fn create_socket(
 pub async fn await_message(
     socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
 ) -> Result<SocketEvent, Error> {
-    let msg = socket.next().await;
-
-    match msg {
-        Some(Ok(msg)) => {
-            trace!("Received message: {:?}", msg);
-            match msg {
-                Text(text) => {
-                    let text = text.as_str();
-                    if text.starts_with("{\"type\":\"doc_added\"") {
-                        let doc_added_msg = text.to_string();
-                        return Ok(SocketEvent::DocAdded(doc_added_msg));
-                    } else if text.starts_with("{\"type\":\"live_sync_started\"") {
-                        let live_sync_started_msg = text.to_string();
-                        return Ok(SocketEvent::LiveSyncStarted(
-                            live_sync_started_msg,
-                            token_from_msg(text),
-                        ));
+    loop {
+        let msg = socket.next().await;
+
+        match msg {
+            Some(Ok(msg)) => {
+                trace!("Received message: {:?}", msg);
+                match msg {
+                    Text(text) => {
+                        let text = text.as_str();
+                        if text.starts_with("{\"type\":\"doc_added\"") {
+                            let doc_added_msg = text.to_string();
+                            return Ok(SocketEvent::DocAdded(doc_added_msg));
+                        } else if text.starts_with("{\"type\":\"live_sync_started\"") {
+                            let live_sync_started_msg = text.to_string();
+                            return Ok(SocketEvent::LiveSyncStarted(
+                                token_from_msg(text),
+                                live_sync_started_msg,
+                            ));
+                        }
+                        debug!("Ignoring message: {}", text);
+                    }
+                    _ => {
+                        warn!("Received unexpected message: {:?}", msg);
                     }
-                }
-                _ => {
-                    warn!("Received unexpected message: {:?}", msg);
                 }
             }
+            Some(Err(e)) => {
+                error!("Error receiving message: {:?}", e);
+                return Err(e);
+            }
+            None => {
+                error!("Socket closed");
+                return Err(Error::AlreadyClosed);
+            }
         }
-        Some(Err(e)) => {
-            error!("Error receiving message: {:?}", e);
-        }
-        None => {
-            error!("Socket closed");
+    }
+}
+
+/// Connects to the livesync subscriber socket and forwards every binary frame
+/// to the given sender until the socket closes or the receiver is dropped.
+/// Everything received is handed to the recorder as it arrives.
+pub async fn stream_liveview(
+    url: &str,
+    session_token: &str,
+    sender: glib::Sender<Vec<u8>>,
+    recorder: SessionRecorder,
+) -> Result<(), Error> {
+    let result = forward_liveview(url, session_token, sender, &recorder).await;
+
+    if let Err(e) = &result {
+        recorder.error(&e.to_string());
+    }
+
+    result
+}
+
+async fn forward_liveview(
+    url: &str,
+    session_token: &str,
+    sender: glib::Sender<Vec<u8>>,
+    recorder: &SessionRecorder,
+) -> Result<(), Error> {
+    let mut socket = create_socket(url, session_token).await?;
+    recorder.opened();
+
+    debug!("Streaming live view frames");
+
+    while let Some(msg) = socket.next().await {
+        match msg? {
+            Binary(data) => {
+                trace!("Received frame of {} bytes", data.len());
+                recorder.frame(&data);
+                if sender.send(data).is_err() {
+                    debug!("Live view receiver closed");
+                    break;
+                }
+            }
+            Text(text) => {
+                trace!("Received live view message: {}", text);
+                recorder.text(&text);
+            }
+            Close(frame) => {
+                debug!("Live view socket closed: {:?}", frame);
+                let reason = frame.map(|frame| frame.reason.to_string());
+                recorder.closed(reason.as_deref().unwrap_or_default());
+                break;
+            }
+            msg => {
+                trace!("Ignoring live view message: {:?}", msg);
+            }
         }
     }
-    Err(Error::AlreadyClosed)
+
+    Ok(())
 }
 
 fn token_from_msg(msg: &str) -> String {
@@ -97,3 +167,7 @@ pub fn get_livesync_url(base: &BaseDomains) -> String {
         base.notifications, REMARKABLE_NOTIFICATION_SOCKET_PATH
     )
 }
+
+pub fn get_liveview_url(base: &BaseDomains) -> String {
+    format!("{}{}", base.livesync, REMARKABLE_LIVEVIEW_SUBSCRIBER_PATH)
+}
@@ -0,0 +1,93 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    path::Path,
+};
+
+use cairo::{Context, Error};
+use log::{debug, trace};
+use poppler::Document;
+
+use crate::{
+    remarkable::format::{content::DocumentContent, device::Transform, scheme::ColorScheme},
+    render::{draw_image, matrix},
+};
+
+/// The PDF a document on the tablet was created from, drawn below the lines
+/// of its pages.
+#[derive(Clone)]
+pub struct SourceDocument {
+    document: Document,
+    content: DocumentContent,
+}
+
+impl Debug for SourceDocument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SourceDocument")
+            .field("pages", &self.document.n_pages())
+            .field("content", &self.content)
+            .finish()
+    }
+}
+
+impl SourceDocument {
+    /// Opens the PDF at the given path, placing its pages as the content of
+    /// the document describes.
+    pub fn open(path: &Path, content: DocumentContent) -> Result<Self, String> {
+        debug!("Opening source {:?}", path);
+
+        // poppler only opens documents by their URI
+        let uri = glib::filename_to_uri(path, None).map_err(|e| e.to_string())?;
+        let document = Document::from_file(&uri, None).map_err(|e| e.to_string())?;
+
+        Ok(SourceDocument { document, content })
+    }
+
+    /// Draws the source page shown on the given page of the document in the
+    /// colours of the scheme. The page has the given size in tablet units
+    /// and is placed on the context by the given transform.
+    pub fn draw_page(
+        &self,
+        context: &Context,
+        page: u32,
+        page_size: (f64, f64),
+        transform: &Transform,
+        scheme: ColorScheme,
+    ) -> Result<(), Error> {
+        let source_page = match self
+            .content
+            .source_page(page)
+            .and_then(|index| self.document.page(index as i32))
+        {
+            Some(source_page) => source_page,
+            None => {
+                trace!("Page {} has no source page", page);
+                return Ok(());
+            }
+        };
+
+        let placement = self
+            .content
+            .placement(source_page.size(), page_size)
+            .then(transform);
+
+        context.save()?;
+
+        // zoomed in sources must not cover the area around the page
+        let (width, height) = page_size;
+        context.new_path();
+        for (x, y) in &[(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)] {
+            let (x, y) = transform.apply(*x, *y);
+            context.line_to(x, y);
+        }
+        context.close_path();
+        context.clip();
+
+        draw_image(context, scheme, || {
+            context.transform(matrix(&placement));
+            source_page.render(context);
+            Ok(())
+        })?;
+
+        context.restore()
+    }
+}
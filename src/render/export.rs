@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::{
+    remarkable::format::{
+        device::{DeviceProfile, Orientation},
+        rmlines::{parse_rm_file, RmPage},
+        smoothing::StrokePipeline,
+        template::PageTemplates,
+    },
+    render::{image::export_png, pdf::export_pdf, svg::export_svg, OutlineRenderer},
+};
+
+/// The number of PDF and SVG units per inch.
+const POINTS_PER_INCH: f64 = 72.0;
+
+/// The file formats pages can be exported to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExportFormat {
+    Pdf,
+    Svg,
+    Png,
+}
+
+impl ExportFormat {
+    /// Picks the format by the extension of the output file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "pdf" => Some(ExportFormat::Pdf),
+            "svg" => Some(ExportFormat::Svg),
+            "png" => Some(ExportFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// The number of units of the format per tablet unit. Vector formats
+    /// keep the size of the tablet's screen, images its pixels.
+    fn scale(&self, profile: &DeviceProfile) -> f64 {
+        match self {
+            ExportFormat::Pdf | ExportFormat::Svg => POINTS_PER_INCH / profile.dpi,
+            ExportFormat::Png => 1.0,
+        }
+    }
+
+    /// Vector formats get the curves lines follow, images their samples.
+    fn pipeline(&self) -> StrokePipeline {
+        match self {
+            ExportFormat::Pdf | ExportFormat::Svg => StrokePipeline::vector(),
+            ExportFormat::Png => StrokePipeline::default(),
+        }
+    }
+}
+
+/// Renders the .rm files of pages written on the given tablet into a PDF,
+/// or a single page into an SVG or PNG, depending on the output's extension.
+/// The templates are drawn below the pages in the order they are given.
+pub fn export_pages(
+    pages: &[PathBuf],
+    output: &Path,
+    profile: DeviceProfile,
+    templates: &PageTemplates,
+) -> Result<(), String> {
+    let format = ExportFormat::from_path(output).ok_or_else(|| {
+        format!(
+            "Cannot export to {:?}, expected a .pdf, .svg or .png file",
+            output
+        )
+    })?;
+
+    let pages = pages
+        .iter()
+        .map(|path| read_page(path))
+        .collect::<Result<Vec<RmPage>, String>>()?;
+
+    debug!("Exporting {} pages to {:?}", pages.len(), output);
+
+    let renderer = OutlineRenderer::new(profile, Orientation::Portrait, format.scale(&profile))
+        .with_pipeline(format.pipeline());
+
+    let page = match (format, pages.as_slice()) {
+        (_, []) => return Err("No pages to export".to_string()),
+        (ExportFormat::Pdf, pages) => {
+            return export_pdf(&renderer, pages, templates, output).map_err(|e| e.to_string())
+        }
+        (_, [page]) => page,
+        (_, _) => return Err("Only a PDF can hold more than one page".to_string()),
+    };
+
+    match format {
+        ExportFormat::Svg => {
+            export_svg(&renderer, page, templates.name(0), output).map_err(|e| e.to_string())
+        }
+        _ => export_png(&renderer, page, templates.name(0), output).map_err(|e| e.to_string()),
+    }
+}
+
+fn read_page(path: &Path) -> Result<RmPage, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    parse_rm_file(&data).map_err(|e| format!("Failed to parse {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    const PAGE: &str = "src/remarkable/format/example_v6.rm";
+
+    fn output(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}.{}", Uuid::new_v4(), extension))
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a/notes.PDF")),
+            Some(ExportFormat::Pdf)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("page.svg")),
+            Some(ExportFormat::Svg)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("page.jpg")), None);
+        assert_eq!(ExportFormat::from_path(Path::new("page")), None);
+    }
+
+    #[test]
+    fn test_export_pages() {
+        let pages = vec![PathBuf::from(PAGE), PathBuf::from(PAGE)];
+        let profile = DeviceProfile::default();
+        let templates = PageTemplates::new(vec!["P Grid medium".into(), "P Lines small".into()]);
+
+        let pdf = output("pdf");
+        export_pages(&pages, &pdf, profile, &templates).unwrap();
+        assert!(fs::read(&pdf).unwrap().starts_with(b"%PDF"));
+
+        let png = output("png");
+        export_pages(&pages[..1], &png, profile, &templates).unwrap();
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+        let templates = PageTemplates::default();
+        assert!(export_pages(&pages, &output("svg"), profile, &templates).is_err());
+        assert!(export_pages(&[], &output("pdf"), profile, &templates).is_err());
+        assert!(export_pages(&pages, &output("txt"), profile, &templates).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{fs::File, path::Path};
+
+use cairo::{Context, Format, ImageSurface, IoError};
+
+use crate::{remarkable::format::rmlines::RmPage, render::StrokeRenderer};
+
+/// Renders a page on top of the named template into a new image surface of
+/// the renderer's page size.
+pub fn render_image<R: StrokeRenderer>(
+    renderer: &R,
+    page: &RmPage,
+    template: &str,
+) -> Result<ImageSurface, cairo::Error> {
+    let (width, height) = renderer.page_size();
+    let surface = ImageSurface::create(Format::ARgb32, width.ceil() as i32, height.ceil() as i32)?;
+
+    let context = Context::new(&surface)?;
+    renderer.draw_page(&context, page, template)?;
+    surface.flush();
+
+    Ok(surface)
+}
+
+/// Renders a page and writes it as PNG.
+pub fn export_png<R: StrokeRenderer, P: AsRef<Path>>(
+    renderer: &R,
+    page: &RmPage,
+    template: &str,
+    path: P,
+) -> Result<(), IoError> {
+    let surface = render_image(renderer, page, template)?;
+    let mut file = File::create(path)?;
+
+    surface.write_to_png(&mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        remarkable::format::{
+            data::{Line, PenColor, PenType, Point},
+            device::{DeviceProfile, Orientation},
+            rmlines::RmLayer,
+            scheme::ColorScheme,
+            smoothing::StrokePipeline,
+            template::BLANK,
+        },
+        render::{InkStyle, OutlineRenderer},
+    };
+
+    /// Reads the (red, green, blue) value of a pixel.
+    fn pixel(surface: &mut ImageSurface, x: i32, y: i32) -> (u8, u8, u8) {
+        let stride = surface.stride();
+        let data = surface.data().unwrap();
+        let offset = (y * stride + x * 4) as usize;
+        let argb = u32::from_ne_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]);
+
+        ((argb >> 16) as u8, (argb >> 8) as u8, argb as u8)
+    }
+
+    #[test]
+    fn test_render_line() {
+        let page = RmPage {
+            version: 6,
+            layers: vec![RmLayer {
+                name: None,
+                lines: vec![Line {
+                    points: vec![
+                        Point::new(100.0, 100.0, 20.0, 1.0),
+                        Point::new(300.0, 100.0, 20.0, 1.0),
+                    ],
+                    brush: PenType::Fineliner,
+                    color: PenColor::BLACK,
+                }],
+            }],
+        };
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 0.5)
+            .with_pipeline(StrokePipeline::raw());
+
+        let mut surface = render_image(&renderer, &page, BLANK).unwrap();
+
+        assert_eq!(surface.width(), 702);
+        assert_eq!(surface.height(), 936);
+        assert_eq!(pixel(&mut surface, 100, 50), (0, 0, 0));
+        assert_eq!(pixel(&mut surface, 100, 200), (255, 255, 255));
+    }
+
+    #[test]
+    fn test_highlighter_keeps_lines_readable() {
+        let line = |brush, y| Line {
+            points: vec![
+                Point::new(100.0, y, 20.0, 1.0),
+                Point::new(300.0, y, 20.0, 1.0),
+            ],
+            brush,
+            color: PenColor::BLACK,
+        };
+        let page = RmPage {
+            version: 6,
+            layers: vec![RmLayer {
+                name: None,
+                lines: vec![
+                    line(PenType::Fineliner, 100.0),
+                    line(PenType::Highlighter, 100.0),
+                    line(PenType::Highlighter, 300.0),
+                ],
+            }],
+        };
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 0.5)
+            .with_pipeline(StrokePipeline::raw());
+
+        let mut surface = render_image(&renderer, &page, BLANK).unwrap();
+
+        assert_eq!(pixel(&mut surface, 100, 50), (0, 0, 0));
+
+        let (r, _, b) = pixel(&mut surface, 100, 150);
+        assert_eq!(r, 255);
+        assert!(b > 128 && b < 255);
+    }
+
+    #[test]
+    fn test_textured_pencil_is_grainy() {
+        let page = RmPage {
+            version: 6,
+            layers: vec![RmLayer {
+                name: None,
+                lines: vec![Line {
+                    points: vec![
+                        Point::new(100.0, 100.0, 20.0, 1.0),
+                        Point::new(300.0, 100.0, 20.0, 1.0),
+                    ],
+                    brush: PenType::SharpPencil,
+                    color: PenColor::BLACK,
+                }],
+            }],
+        };
+        let clean = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 1.0)
+            .with_pipeline(StrokePipeline::raw());
+        let textured = clean.clone().with_ink(InkStyle::Textured);
+
+        let mut clean = render_image(&clean, &page, BLANK).unwrap();
+        let mut textured = render_image(&textured, &page, BLANK).unwrap();
+
+        let row = |surface: &mut ImageSurface| -> Vec<(u8, u8, u8)> {
+            (120..280).map(|x| pixel(surface, x, 100)).collect()
+        };
+        let clean = row(&mut clean);
+        let textured = row(&mut textured);
+
+        assert!(clean.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(textured.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn test_translucent_ink_does_not_add_up() {
+        let pencil = |points| RmPage {
+            version: 6,
+            layers: vec![RmLayer {
+                name: None,
+                lines: vec![Line {
+                    points,
+                    brush: PenType::SharpPencil,
+                    color: PenColor::BLACK,
+                }],
+            }],
+        };
+        let mut light = Point::new(150.0, 100.0, 20.0, 1.0);
+        light.pressure = 0.2;
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 1.0)
+            .with_pipeline(StrokePipeline::raw());
+
+        let mut single = render_image(
+            &renderer,
+            &pencil(vec![
+                Point::new(100.0, 100.0, 20.0, 1.0),
+                Point::new(300.0, 100.0, 20.0, 1.0),
+            ]),
+            BLANK,
+        )
+        .unwrap();
+        let mut back = render_image(
+            &renderer,
+            &pencil(vec![
+                Point::new(100.0, 100.0, 20.0, 1.0),
+                Point::new(300.0, 100.0, 20.0, 1.0),
+                light,
+            ]),
+            BLANK,
+        )
+        .unwrap();
+
+        // the way back replaces the ink below it with its lighter one
+        let (single, _, _) = pixel(&mut single, 200, 100);
+        let (back, _, _) = pixel(&mut back, 200, 100);
+        assert!(single < 255);
+        assert!(back >= single);
+    }
+
+    #[test]
+    fn test_render_dark_scheme() {
+        let page = RmPage {
+            version: 6,
+            layers: vec![RmLayer {
+                name: None,
+                lines: vec![Line {
+                    points: vec![
+                        Point::new(100.0, 100.0, 20.0, 1.0),
+                        Point::new(300.0, 100.0, 20.0, 1.0),
+                    ],
+                    brush: PenType::Fineliner,
+                    color: PenColor::BLACK,
+                }],
+            }],
+        };
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 0.5)
+            .with_pipeline(StrokePipeline::raw())
+            .with_scheme(ColorScheme::Dark);
+
+        let mut surface = render_image(&renderer, &page, BLANK).unwrap();
+
+        let (r, g, b) = pixel(&mut surface, 100, 50);
+        assert!(r > 200 && g > 200 && b > 200);
+        let (r, g, b) = pixel(&mut surface, 100, 200);
+        assert!(r < 50 && g < 50 && b < 50);
+    }
+
+    #[test]
+    fn test_line_bounds() {
+        let line = Line {
+            points: vec![
+                Point::new(100.0, 100.0, 20.0, 1.0),
+                Point::new(300.0, 100.0, 20.0, 1.0),
+            ],
+            brush: PenType::Fineliner,
+            color: PenColor::BLACK,
+        };
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 0.5)
+            .with_pipeline(StrokePipeline::raw());
+        let surface = ImageSurface::create(Format::ARgb32, 702, 936).unwrap();
+
+        let (x, y, width, height) = renderer
+            .draw_line_bounds(&Context::new(&surface).unwrap(), &line, 0)
+            .unwrap()
+            .unwrap();
+
+        assert!(x <= 50.0 && x + width >= 150.0);
+        assert!(y < 50.0 && y + height > 50.0);
+        assert!(height < 20.0);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let page = RmPage {
+            version: 6,
+            layers: vec![],
+        };
+        let renderer = OutlineRenderer::new(DeviceProfile::default(), Orientation::Portrait, 1.0);
+
+        let mut blank = render_image(&renderer, &page, BLANK).unwrap();
+        let mut grid = render_image(&renderer, &page, "P Grid large").unwrap();
+
+        assert_eq!(pixel(&mut blank, 88, 300), (255, 255, 255));
+        assert_ne!(pixel(&mut grid, 88, 300), (255, 255, 255));
+        assert_eq!(pixel(&mut grid, 120, 300), (255, 255, 255));
+    }
+}
@@ -0,0 +1,559 @@
+//! Draws lines onto any cairo context independent of the GTK window, so the
+//! live view, exporters and tests share the same code.
+
+use std::f64::consts::PI;
+
+use cairo::{Context, Error, LineCap, LineJoin, Matrix, Operator};
+
+use crate::{
+    remarkable::format::{
+        brush::{BlendMode, Texture},
+        data::{Line, Point},
+        device::{DeviceProfile, Orientation, Transform},
+        outline::{curved_outline, stroke_outline, PathPiece, StrokeOutline},
+        rmlines::RmPage,
+        scheme::ColorScheme,
+        smoothing::{CurveFitting, StrokePipeline},
+        template::{Template, TemplateShape},
+        texture::{bristles, grain, GRAIN_RADIUS},
+    },
+    render::{document::SourceDocument, template::TemplateLibrary},
+};
+
+pub mod document;
+pub mod export;
+pub mod image;
+pub mod pdf;
+pub mod svg;
+pub mod template;
+
+/// Renders lines in tablet coordinates onto a cairo context.
+pub trait StrokeRenderer {
+    /// The size of a rendered page in the units of the context.
+    fn page_size(&self) -> (f64, f64);
+
+    /// Fills the whole page with the paper colour.
+    fn clear(&self, context: &Context) -> Result<(), Error>;
+
+    /// Clears the page and draws the template of the given name onto it.
+    fn draw_background(&self, context: &Context, _template: &str) -> Result<(), Error> {
+        self.clear(context)
+    }
+
+    /// Draws the points of the line starting at the given index on top of
+    /// what has been drawn before.
+    fn draw_line(&self, context: &Context, line: &Line, from: usize) -> Result<(), Error>;
+
+    /// Draws complete lines in the given order.
+    fn draw_lines<'a, I>(&self, context: &Context, lines: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a Line>,
+        Self: Sized,
+    {
+        for line in lines {
+            self.draw_line(context, line, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the template and all layers of a page.
+    fn draw_page(&self, context: &Context, page: &RmPage, template: &str) -> Result<(), Error> {
+        self.draw_background(context, template)?;
+
+        for layer in &page.layers {
+            for line in &layer.lines {
+                self.draw_line(context, line, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A colour with alpha as (red, green, blue, alpha).
+type Rgba = (f64, f64, f64, f64);
+
+/// The colour of template lines.
+const TEMPLATE_COLOR: (f64, f64, f64) = (0.75, 0.75, 0.75);
+
+/// The distance antialiasing and texture may reach beyond the outline of a
+/// line in the units of the context.
+const BOUNDS_MARGIN: f64 = 2.0;
+
+/// The opacity of the solid ink below a texture relative to the ink.
+const TEXTURE_BASE_OPACITY: f64 = 0.3;
+
+/// How the ink of textured tools is rendered.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum InkStyle {
+    /// Solid ink for every tool, which is the fastest
+    #[default]
+    Clean,
+    /// Pencils leave grain and brushes leave bristle streaks
+    Textured,
+}
+
+/// Fills the variable width outline of every line.
+#[derive(Debug, Clone)]
+pub struct OutlineRenderer {
+    profile: DeviceProfile,
+    orientation: Orientation,
+    scale: f64,
+    /// Places the scaled page
+    view: Transform,
+    transform: Transform,
+    pipeline: StrokePipeline,
+    ink: InkStyle,
+    scheme: ColorScheme,
+    templates: TemplateLibrary,
+    /// The PDF drawn below the lines
+    source: Option<SourceDocument>,
+}
+
+impl OutlineRenderer {
+    /// Creates a renderer for pages of the given tablet, scaled by the given
+    /// factor.
+    pub fn new(profile: DeviceProfile, orientation: Orientation, scale: f64) -> Self {
+        OutlineRenderer {
+            profile,
+            orientation,
+            scale,
+            view: Transform::identity(),
+            transform: profile.transform(orientation, scale),
+            pipeline: StrokePipeline::default(),
+            ink: InkStyle::default(),
+            scheme: ColorScheme::default(),
+            templates: TemplateLibrary::bundled(),
+            source: None,
+        }
+    }
+
+    pub fn with_pipeline(mut self, pipeline: StrokePipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Places the scaled page in a larger view, e.g. to rotate or pan it.
+    /// The page size stays the size of the unplaced page.
+    pub fn with_view(mut self, view: Transform) -> Self {
+        self.view = view;
+        self.transform = self
+            .profile
+            .transform(self.orientation, self.scale)
+            .then(&view);
+        self
+    }
+
+    pub fn with_ink(mut self, ink: InkStyle) -> Self {
+        self.ink = ink;
+        self
+    }
+
+    pub fn with_scheme(mut self, scheme: ColorScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    pub fn with_templates(mut self, templates: TemplateLibrary) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    pub fn with_source(mut self, source: Option<SourceDocument>) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    pub fn ink(&self) -> InkStyle {
+        self.ink
+    }
+
+    pub fn scheme(&self) -> ColorScheme {
+        self.scheme
+    }
+
+    /// Maps page coordinates, which unlike tablet coordinates follow the
+    /// orientation of the page, onto the context.
+    fn page_transform(&self) -> Transform {
+        Transform::scale(self.scale).then(&self.view)
+    }
+
+    /// Draws the user's image of the template, or else its vector version.
+    fn draw_template(&self, context: &Context, name: &str) -> Result<(), Error> {
+        let (width, height) = self.profile.page_size(self.orientation);
+        let transform = self.page_transform();
+
+        context.save()?;
+
+        if let Some(image) = self.templates.image(name) {
+            draw_image(context, self.scheme, || {
+                context.transform(matrix(&transform));
+                context.scale(width / image.width() as f64, height / image.height() as f64);
+                context.set_source_surface(&image, 0.0, 0.0)?;
+                context.paint()
+            })?;
+            return context.restore();
+        }
+
+        let (r, g, b) = self.scheme.color(TEMPLATE_COLOR);
+        context.set_source_rgb(r, g, b);
+        let length_scale = transform.length_scale();
+
+        for shape in Template::from_name(name).shapes(width, height) {
+            match shape {
+                TemplateShape::Line { from, to, width } => {
+                    let (x, y) = transform.apply(from.0, from.1);
+                    context.move_to(x, y);
+                    let (x, y) = transform.apply(to.0, to.1);
+                    context.line_to(x, y);
+                    context.set_line_width(width * length_scale);
+                    context.stroke()?;
+                }
+                TemplateShape::Dot { center, radius } => {
+                    let (x, y) = transform.apply(center.0, center.1);
+                    context.new_sub_path();
+                    context.arc(x, y, radius * length_scale, 0.0, 2.0 * PI);
+                    context.fill()?;
+                }
+                TemplateShape::Rectangle {
+                    origin,
+                    size,
+                    width,
+                } => {
+                    let corners = [
+                        origin,
+                        (origin.0 + size.0, origin.1),
+                        (origin.0 + size.0, origin.1 + size.1),
+                        (origin.0, origin.1 + size.1),
+                    ];
+                    for (x, y) in &corners {
+                        let (x, y) = transform.apply(*x, *y);
+                        context.line_to(x, y);
+                    }
+                    context.close_path();
+                    context.set_line_width(width * length_scale);
+                    context.stroke()?;
+                }
+            }
+        }
+
+        context.restore()
+    }
+
+    /// Draws the source page shown on the given page, if the document has
+    /// one.
+    pub fn draw_source(&self, context: &Context, page: u32) -> Result<(), Error> {
+        match &self.source {
+            Some(source) => {
+                let size = self.profile.page_size(self.orientation);
+                source.draw_page(context, page, size, &self.page_transform(), self.scheme)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn fill_polygon(&self, context: &Context, polygon: &[(f64, f64)]) -> Result<(), Error> {
+        for (x, y) in polygon {
+            let (x, y) = self.transform.apply(*x, *y);
+            context.line_to(x, y);
+        }
+
+        context.close_path();
+        context.fill()
+    }
+
+    /// Fills a path with curved pieces, e.g. an outline from
+    /// [curved_outline].
+    fn fill_path(&self, context: &Context, path: &[PathPiece]) -> Result<(), Error> {
+        for piece in path {
+            match piece {
+                PathPiece::Line((x, y)) => {
+                    let (x, y) = self.transform.apply(*x, *y);
+                    context.line_to(x, y);
+                }
+                PathPiece::Curve(control1, control2, (x, y)) => {
+                    let (x1, y1) = self.transform.apply(control1.0, control1.1);
+                    let (x2, y2) = self.transform.apply(control2.0, control2.1);
+                    let (x, y) = self.transform.apply(*x, *y);
+                    context.curve_to(x1, y1, x2, y2, x, y);
+                }
+            }
+        }
+
+        context.close_path();
+        context.fill()
+    }
+
+    /// Fills an outline with ink of the given colour. Ink of varying opacity
+    /// is composed in a group first, in which every segment replaces what is
+    /// below it, so overlapping segments don't add up.
+    fn fill_outline(
+        &self,
+        context: &Context,
+        outline: &StrokeOutline,
+        (r, g, b, a): Rgba,
+    ) -> Result<(), Error> {
+        if outline.is_uniform() {
+            context.set_source_rgba(r, g, b, a * outline.opacity);
+            return self.fill_polygon(context, &outline.polygon);
+        }
+
+        // the group keeps the operator the ink is blended with
+        context.push_group();
+        context.set_operator(Operator::Source);
+
+        for segment in &outline.segments {
+            context.set_source_rgba(r, g, b, a * segment.opacity);
+            self.fill_polygon(context, &segment.polygon)?;
+        }
+
+        context.pop_group_to_source()?;
+        context.paint()
+    }
+
+    /// Draws a light base of the given ink and the texture on top of it.
+    /// Grains are scattered along the raw points of the line, so they stay
+    /// in place when the line is drawn again as it grows.
+    fn draw_texture(
+        &self,
+        context: &Context,
+        points: &[Point],
+        outline: &StrokeOutline,
+        line: &Line,
+        (r, g, b, a): Rgba,
+        texture: Texture,
+    ) -> Result<(), Error> {
+        let model = line.brush.model();
+
+        self.fill_outline(context, outline, (r, g, b, a * TEXTURE_BASE_OPACITY))?;
+
+        context.set_source_rgba(r, g, b, a * outline.opacity);
+        let length_scale = self.transform.length_scale();
+
+        match texture {
+            Texture::Grain(density) => {
+                let size = 2.0 * GRAIN_RADIUS * length_scale;
+
+                for (x, y) in grain(&line.points, &model, density) {
+                    let (x, y) = self.transform.apply(x, y);
+                    context.rectangle(x - size / 2.0, y - size / 2.0, size, size);
+                }
+
+                context.fill()?;
+            }
+            Texture::Bristles(count) => {
+                context.set_line_cap(LineCap::Round);
+                context.set_line_join(LineJoin::Round);
+
+                for streak in bristles(points, &model, count) {
+                    for (x, y) in &streak.points {
+                        let (x, y) = self.transform.apply(*x, *y);
+                        context.line_to(x, y);
+                    }
+
+                    context.set_line_width(streak.width * length_scale);
+                    context.stroke()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws like [StrokeRenderer::draw_line] and returns the area covered
+    /// by the drawn points in the units of the context as (x, y, width,
+    /// height), if any.
+    pub fn draw_line_bounds(
+        &self,
+        context: &Context,
+        line: &Line,
+        from: usize,
+    ) -> Result<Option<(f64, f64, f64, f64)>, Error> {
+        let points = if from == 0 {
+            self.pipeline.process(line).points
+        } else {
+            self.pipeline.process_tail(line, from)
+        };
+
+        let model = line.brush.model();
+        let outline = match stroke_outline(&points, &model) {
+            Some(outline) => outline,
+            None => return Ok(None),
+        };
+
+        let (color, blend) = self.scheme.ink(line.brush.ink(line.color), model.blend);
+
+        context.save()?;
+        context.new_path();
+
+        match blend {
+            BlendMode::Normal => {}
+            BlendMode::Multiply => context.set_operator(Operator::Multiply),
+            BlendMode::Screen => context.set_operator(Operator::Screen),
+        }
+
+        match (self.ink, model.texture) {
+            (InkStyle::Textured, Some(texture)) => {
+                self.draw_texture(context, &points, &outline, line, color, texture)?
+            }
+            // vector backends draw the curves themselves, which keeps
+            // exports small
+            _ if self.pipeline.curve == CurveFitting::Bezier && outline.is_uniform() => {
+                if let Some(path) = curved_outline(&points, &model) {
+                    let (r, g, b, a) = color;
+                    context.set_source_rgba(r, g, b, a * outline.opacity);
+                    self.fill_path(context, &path)?;
+                }
+            }
+            _ => self.fill_outline(context, &outline, color)?,
+        }
+
+        context.restore()?;
+
+        Ok(Some(self.bounds(&outline)))
+    }
+
+    /// The area the points of the line starting at the given index cover,
+    /// like [OutlineRenderer::draw_line_bounds] but without drawing.
+    pub fn line_bounds(&self, line: &Line, from: usize) -> Option<(f64, f64, f64, f64)> {
+        let points = self.pipeline.process_tail(line, from);
+        stroke_outline(&points, &line.brush.model()).map(|outline| self.bounds(&outline))
+    }
+
+    /// Whether new points of the line can be drawn on top of the ones drawn
+    /// before. Translucent or blended ink would add up where they meet and
+    /// textures are laid out along the whole line, so such lines have to be
+    /// drawn again as a whole.
+    pub fn draws_incrementally(&self, line: &Line) -> bool {
+        let model = line.brush.model();
+        let textured = self.ink == InkStyle::Textured && model.texture.is_some();
+
+        model.is_opaque() && !textured
+    }
+
+    /// Whether the line might reach into the given area, judged from its
+    /// points before smoothing, to skip lines when drawing only part of a
+    /// page.
+    pub fn reaches(&self, line: &Line, (x, y, width, height): (f64, f64, f64, f64)) -> bool {
+        let model = line.brush.model();
+        let mut reach = 0.0f64;
+        let (mut left, mut top) = (f64::INFINITY, f64::INFINITY);
+        let (mut right, mut bottom) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+
+        for point in &line.points {
+            let (px, py) = self.transform.apply(point.x, point.y);
+            left = left.min(px);
+            top = top.min(py);
+            right = right.max(px);
+            bottom = bottom.max(py);
+            reach = reach.max(model.style(point).width);
+        }
+
+        // smoothing may overshoot the points by a fraction of the width
+        let margin = reach * self.transform.length_scale() + 2.0 * BOUNDS_MARGIN;
+
+        left - margin < x + width &&
+            right + margin > x &&
+            top - margin < y + height &&
+            bottom + margin > y
+    }
+
+    /// The area covered by an outline, with room for antialiasing.
+    fn bounds(&self, outline: &StrokeOutline) -> (f64, f64, f64, f64) {
+        let (mut left, mut top) = (f64::INFINITY, f64::INFINITY);
+        let (mut right, mut bottom) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+
+        for (x, y) in &outline.polygon {
+            let (x, y) = self.transform.apply(*x, *y);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+
+        (
+            left - BOUNDS_MARGIN,
+            top - BOUNDS_MARGIN,
+            right - left + 2.0 * BOUNDS_MARGIN,
+            bottom - top + 2.0 * BOUNDS_MARGIN,
+        )
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+/// Draws an image like a PDF page or a template the way the scheme shows
+/// it. On dark paper the image is inverted and may only lighten the page,
+/// so its white paper takes the colour of the scheme's paper.
+fn draw_image<F: FnOnce() -> Result<(), Error>>(
+    context: &Context,
+    scheme: ColorScheme,
+    draw: F,
+) -> Result<(), Error> {
+    if !scheme.is_dark() {
+        return draw();
+    }
+
+    context.push_group();
+    context.set_source_rgb(1.0, 1.0, 1.0);
+    context.paint()?;
+
+    context.save()?;
+    draw()?;
+    context.restore()?;
+
+    context.set_operator(Operator::Difference);
+    context.set_source_rgb(1.0, 1.0, 1.0);
+    context.paint()?;
+
+    context.pop_group_to_source()?;
+    context.set_operator(Operator::Lighten);
+    context.paint()?;
+    context.set_operator(Operator::Over);
+
+    Ok(())
+}
+
+/// Converts a transform into the matrix cairo uses.
+fn matrix(transform: &Transform) -> Matrix {
+    Matrix::new(
+        transform.xx,
+        transform.yx,
+        transform.xy,
+        transform.yy,
+        transform.x0,
+        transform.y0,
+    )
+}
+
+impl StrokeRenderer for OutlineRenderer {
+    fn page_size(&self) -> (f64, f64) {
+        let (width, height) = self.profile.page_size(self.orientation);
+        (width * self.scale, height * self.scale)
+    }
+
+    fn clear(&self, context: &Context) -> Result<(), Error> {
+        let (r, g, b) = self.scheme.paper();
+
+        context.save()?;
+        context.set_source_rgb(r, g, b);
+        context.paint()?;
+        context.restore()
+    }
+
+    fn draw_background(&self, context: &Context, template: &str) -> Result<(), Error> {
+        self.clear(context)?;
+        self.draw_template(context, template)
+    }
+
+    fn draw_line(&self, context: &Context, line: &Line, from: usize) -> Result<(), Error> {
+        self.draw_line_bounds(context, line, from).map(|_| ())
+    }
+}
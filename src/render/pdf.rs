@@ -0,0 +1,29 @@
+use std::path::Path;
+
+use cairo::{Context, PdfSurface};
+
+use crate::{
+    remarkable::format::{rmlines::RmPage, template::PageTemplates},
+    render::StrokeRenderer,
+};
+
+/// Writes all pages on top of their templates into a single PDF document.
+pub fn export_pdf<R: StrokeRenderer, P: AsRef<Path>>(
+    renderer: &R,
+    pages: &[RmPage],
+    templates: &PageTemplates,
+    path: P,
+) -> Result<(), cairo::Error> {
+    let (width, height) = renderer.page_size();
+    let surface = PdfSurface::new(width, height, path)?;
+    let context = Context::new(&surface)?;
+
+    for (index, page) in pages.iter().enumerate() {
+        renderer.draw_page(&context, page, templates.name(index as u32))?;
+        context.show_page()?;
+    }
+
+    surface.finish();
+
+    Ok(())
+}
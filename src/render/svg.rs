@@ -0,0 +1,22 @@
+use std::path::Path;
+
+use cairo::{Context, SvgSurface};
+
+use crate::{remarkable::format::rmlines::RmPage, render::StrokeRenderer};
+
+/// Writes a page on top of the named template as SVG.
+pub fn export_svg<R: StrokeRenderer, P: AsRef<Path>>(
+    renderer: &R,
+    page: &RmPage,
+    template: &str,
+    path: P,
+) -> Result<(), cairo::Error> {
+    let (width, height) = renderer.page_size();
+    let surface = SvgSurface::new(width, height, Some(path))?;
+    let context = Context::new(&surface)?;
+
+    renderer.draw_page(&context, page, template)?;
+    surface.finish();
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    path::PathBuf,
+    rc::Rc,
+};
+
+use cairo::ImageSurface;
+use log::{debug, warn};
+
+/// Page templates supplied by the user as PNG images named like the
+/// templates on the tablet, e.g. `P Grid medium.png`. They take precedence
+/// over the bundled vector versions.
+#[derive(Clone, Default)]
+pub struct TemplateLibrary {
+    directory: Option<PathBuf>,
+    /// Images already looked up, including the ones that don't exist
+    images: Rc<RefCell<HashMap<String, Option<ImageSurface>>>>,
+}
+
+impl Debug for TemplateLibrary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemplateLibrary")
+            .field("directory", &self.directory)
+            .finish()
+    }
+}
+
+impl TemplateLibrary {
+    /// Looks for images in the given directory.
+    pub fn new(directory: PathBuf) -> Self {
+        TemplateLibrary {
+            directory: Some(directory),
+            images: Rc::default(),
+        }
+    }
+
+    /// Only uses the bundled templates.
+    pub fn bundled() -> Self {
+        TemplateLibrary::default()
+    }
+
+    /// Returns the user's image for the template of the given name.
+    pub fn image(&self, name: &str) -> Option<ImageSurface> {
+        let directory = self.directory.as_ref()?;
+
+        self.images
+            .borrow_mut()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let path = directory.join(format!("{}.png", name));
+
+                if !path.is_file() {
+                    return None;
+                }
+
+                debug!("Loading template {:?}", path);
+
+                match File::open(&path)
+                    .map_err(cairo::IoError::Io)
+                    .and_then(|mut file| ImageSurface::create_from_png(&mut file))
+                {
+                    Ok(image) => Some(image),
+                    Err(e) => {
+                        warn!("Failed to load template {:?}: {}", path, e);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+}